@@ -1,8 +1,10 @@
-use actix_web::{get, post, web, HttpResponse, Result as WebResult};
+use actix_web::{delete, get, post, put, web, HttpResponse, Result as WebResult};
 use std::sync::Arc;
 
-use crate::llm::{LlmProvider, ProviderManager};
-use crate::api::models::{ActiveProviderRequest, ProviderInfo};
+use crate::config::ProviderSpec;
+use crate::db::{credentials::{self, CredentialCipher, CredentialError}, service::DbService, DbPool};
+use crate::llm::{registry::ModelRegistry, LlmProvider, ProviderFactory, ProviderManager};
+use crate::api::models::{ActiveProviderRequest, CreateProviderRequest, ProviderInfo, StoredProviderInfo};
 
 #[get("/providers")]
 pub async fn list_providers(
@@ -23,10 +25,12 @@ pub async fn list_providers(
                     vec![]
                 };
 
+                let infos = models.iter().map(|model| m.model_registry().get(&id, model)).collect();
                 providers.push(ProviderInfo {
                     id: id.clone(),
                     active: id == active_id,
                     supported_models: models,
+                    models: infos,
                     status: "unverified".to_string(),
                 });
             }
@@ -35,10 +39,13 @@ pub async fn list_providers(
         None => {
             // Fallback for single provider configuration
             let models = llm.discover_models().await.unwrap_or_else(|_| llm.supported_models());
+            let registry = ModelRegistry::new();
+            let infos = models.iter().map(|model| registry.get(llm.name(), model)).collect();
             Ok(HttpResponse::Ok().json(vec![ProviderInfo {
                 id: llm.name().to_string(),
                 active: true,
                 supported_models: models,
+                models: infos,
                 status: "online".to_string(),
             }]))
         }
@@ -86,10 +93,19 @@ pub async fn get_active_provider_info(
         Err(_) => "offline",
     };
 
+    let infos = match manager {
+        Some(m) => m.describe_models(&id, &models).await,
+        None => {
+            let registry = ModelRegistry::new();
+            models.iter().map(|model| registry.get(&id, model)).collect()
+        }
+    };
+
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "id": id,
         "status": status,
         "supported_models": models,
+        "models": infos,
         "active_model": effective_model,
     })))
 }
@@ -138,10 +154,152 @@ pub async fn verify_active_provider(
     }
 }
 
+/// Builds provider `id`, stores it with its credentials encrypted and registers it with the
+/// manager, replacing the provider registered under that id if any.
+fn save_provider(
+    manager: &ProviderManager,
+    pool: &DbPool,
+    cipher: Option<&CredentialCipher>,
+    id: &str,
+    spec: ProviderSpec,
+) -> Result<StoredProviderInfo, HttpResponse> {
+    let provider = ProviderFactory::create(id, &spec).map_err(|e| HttpResponse::BadRequest().body(e))?;
+    let (settings, sealed) = credentials::seal_provider(id, &spec, cipher).map_err(|e| match e {
+        CredentialError::MissingMasterKey => HttpResponse::BadRequest().body(e.to_string()),
+        _ => HttpResponse::InternalServerError().body(e.to_string()),
+    })?;
+
+    let conn = pool.lock().unwrap();
+    DbService::upsert_provider(&conn, id, &settings, sealed.as_deref())
+        .map_err(|e| HttpResponse::InternalServerError().body(e.to_string()))?;
+    manager.register_provider(id, provider);
+
+    Ok(StoredProviderInfo {
+        id: id.to_string(),
+        spec,
+        has_credentials: sealed.is_some(),
+    })
+}
+
+#[post("/providers")]
+pub async fn create_provider(
+    llm: web::Data<Arc<dyn LlmProvider>>,
+    pool: web::Data<DbPool>,
+    cipher: web::Data<Option<CredentialCipher>>,
+    req: web::Json<CreateProviderRequest>,
+) -> WebResult<HttpResponse> {
+    let Some(m) = llm.get_ref().as_any().downcast_ref::<ProviderManager>() else {
+        return Ok(HttpResponse::InternalServerError().body("LLM provider is not a ProviderManager"));
+    };
+    let CreateProviderRequest { id, spec } = req.into_inner();
+    if m.get_provider(&id).is_some() {
+        return Ok(HttpResponse::Conflict().body(format!("Provider '{}' already exists", id)));
+    }
+
+    match save_provider(m, &pool, cipher.as_ref().as_ref(), &id, spec) {
+        Ok(info) => Ok(HttpResponse::Created().json(info)),
+        Err(response) => Ok(response),
+    }
+}
+
+/// A provider stored through the API. Providers that only come from config.yaml are not found.
+#[get("/providers/{id}")]
+pub async fn get_provider(
+    pool: web::Data<DbPool>,
+    id: web::Path<String>,
+) -> WebResult<HttpResponse> {
+    let conn = pool.lock().unwrap();
+    let stored = match DbService::get_provider(&conn, &id) {
+        Ok(Some(stored)) => stored,
+        Ok(None) => return Ok(HttpResponse::NotFound().finish()),
+        Err(e) => return Ok(HttpResponse::InternalServerError().body(e.to_string())),
+    };
+
+    match serde_json::from_value::<ProviderSpec>(stored.settings) {
+        Ok(spec) => Ok(HttpResponse::Ok().json(StoredProviderInfo {
+            id: stored.id,
+            spec,
+            has_credentials: stored.credentials.is_some(),
+        })),
+        Err(e) => Ok(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+}
+
+/// Replaces provider `id`, or adds it. Credentials left out of the request are cleared, so a
+/// key is rotated by sending the whole spec again. A provider from config.yaml is
+/// overridden by the stored one from then on.
+#[put("/providers/{id}")]
+pub async fn update_provider(
+    llm: web::Data<Arc<dyn LlmProvider>>,
+    pool: web::Data<DbPool>,
+    cipher: web::Data<Option<CredentialCipher>>,
+    id: web::Path<String>,
+    req: web::Json<ProviderSpec>,
+) -> WebResult<HttpResponse> {
+    let Some(m) = llm.get_ref().as_any().downcast_ref::<ProviderManager>() else {
+        return Ok(HttpResponse::InternalServerError().body("LLM provider is not a ProviderManager"));
+    };
+
+    match save_provider(m, &pool, cipher.as_ref().as_ref(), &id, req.into_inner()) {
+        Ok(info) => Ok(HttpResponse::Ok().json(info)),
+        Err(response) => Ok(response),
+    }
+}
+
+/// Removes provider `id` and its stored credentials. A provider defined in config.yaml comes
+/// back on the next start unless it is removed from the file too.
+#[delete("/providers/{id}")]
+pub async fn delete_provider(
+    llm: web::Data<Arc<dyn LlmProvider>>,
+    pool: web::Data<DbPool>,
+    id: web::Path<String>,
+) -> WebResult<HttpResponse> {
+    let Some(m) = llm.get_ref().as_any().downcast_ref::<ProviderManager>() else {
+        return Ok(HttpResponse::InternalServerError().body("LLM provider is not a ProviderManager"));
+    };
+
+    let registered = m.get_provider(&id).is_some();
+    if registered {
+        if let Err(e) = m.remove_provider(&id) {
+            return Ok(HttpResponse::BadRequest().body(e));
+        }
+    }
+    // Stored providers that failed to load at startup are only in the database
+    let conn = pool.lock().unwrap();
+    match DbService::delete_provider(&conn, &id) {
+        Ok(stored) if registered || stored => Ok(HttpResponse::NoContent().finish()),
+        Ok(_) => Ok(HttpResponse::NotFound().finish()),
+        Err(e) => Ok(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+}
+
+#[post("/providers/{id}/test")]
+pub async fn test_provider(
+    llm: web::Data<Arc<dyn LlmProvider>>,
+    id: web::Path<String>,
+) -> WebResult<HttpResponse> {
+    let Some(m) = llm.get_ref().as_any().downcast_ref::<ProviderManager>() else {
+        return Ok(HttpResponse::InternalServerError().body("LLM provider is not a ProviderManager"));
+    };
+    let Some(provider) = m.get_provider(&id) else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    match provider.verify_connection().await {
+        Ok(_) => Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "online" }))),
+        Err(e) => Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "offline", "error": e.to_string() }))),
+    }
+}
+
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/config")
             .service(list_providers)
+            .service(create_provider)
+            .service(get_provider)
+            .service(update_provider)
+            .service(delete_provider)
+            .service(test_provider)
             .service(set_active_provider)
             .service(get_active_provider_info)
             .service(verify_active_provider)
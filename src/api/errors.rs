@@ -0,0 +1,78 @@
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde::Serialize;
+
+use crate::llm::{ErrorKind, LlmError};
+
+/// OpenAI-style error body, `{"error": {...}}`, used for every LLM failure we report.
+#[derive(Debug, Serialize)]
+pub struct OpenAIErrorResponse {
+    pub error: OpenAIError,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OpenAIError {
+    pub message: String,
+    pub r#type: String,
+    pub param: Option<String>,
+    pub code: ErrorKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    /// Status the upstream provider answered with, which may differ from ours.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_status: Option<u16>,
+}
+
+impl From<&LlmError> for OpenAIErrorResponse {
+    fn from(e: &LlmError) -> Self {
+        let r#type = match e.kind() {
+            ErrorKind::Auth => "authentication_error",
+            ErrorKind::Quota => "insufficient_quota",
+            ErrorKind::RateLimited => "rate_limit_error",
+            ErrorKind::ContextLengthExceeded
+            | ErrorKind::ContentFilter
+            | ErrorKind::InvalidRequest
+            | ErrorKind::NotFound => "invalid_request_error",
+            ErrorKind::Timeout => "timeout_error",
+            ErrorKind::Cancelled => "cancelled",
+            ErrorKind::Server | ErrorKind::Network | ErrorKind::Other => "server_error",
+        };
+
+        Self {
+            error: OpenAIError {
+                message: e.to_string(),
+                r#type: r#type.to_string(),
+                param: None,
+                code: e.kind(),
+                provider: e.provider().map(|p| p.to_string()),
+                upstream_status: e.status(),
+            },
+        }
+    }
+}
+
+impl ResponseError for LlmError {
+    fn status_code(&self) -> StatusCode {
+        match self.kind() {
+            // The provider rejected our own key; a 401 would tell clients their Stepbit key is bad
+            ErrorKind::Auth => StatusCode::BAD_GATEWAY,
+            ErrorKind::Quota | ErrorKind::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ErrorKind::ContextLengthExceeded
+            | ErrorKind::ContentFilter
+            | ErrorKind::InvalidRequest => StatusCode::BAD_REQUEST,
+            ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            ErrorKind::Server | ErrorKind::Network => StatusCode::BAD_GATEWAY,
+            ErrorKind::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            // nginx's "client closed request"
+            ErrorKind::Cancelled => StatusCode::from_u16(499).unwrap_or(StatusCode::BAD_REQUEST),
+            ErrorKind::Other => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let Some(retry_after) = self.retry_after() {
+            response.insert_header(("Retry-After", retry_after.as_secs().max(1).to_string()));
+        }
+        response.json(OpenAIErrorResponse::from(self))
+    }
+}
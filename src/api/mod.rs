@@ -1,3 +1,4 @@
+pub mod errors;
 pub mod middleware;
 pub mod models;
 pub mod models_openai;
@@ -8,3 +9,4 @@ pub mod websocket;
 pub mod config_routes;
 pub mod skills_routes;
 pub mod pipeline_routes;
+pub mod ollama_routes;
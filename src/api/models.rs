@@ -1,4 +1,7 @@
 use serde::{Deserialize, Serialize};
+use crate::config::ProviderSpec;
+use crate::db::models::SpendEntry;
+use crate::llm::models::MessageContent;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MemoryUsageEntry {
@@ -11,10 +14,25 @@ pub struct SystemStats {
     pub total_sessions: i64,
     pub total_messages: i64,
     pub total_tokens: i64,
+    /// USD spent on assistant messages with a known price.
+    pub total_cost: f64,
+    pub spend: Vec<SpendEntry>,
     pub db_size_bytes: u64,
     pub memory_usage: Vec<MemoryUsageEntry>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionStats {
+    pub session_id: uuid::Uuid,
+    pub total_messages: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub cache_read_tokens: i64,
+    pub cache_write_tokens: i64,
+    pub total_cost: f64,
+    pub spend: Vec<SpendEntry>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct CreateSessionRequest {
     pub name: String,
@@ -25,7 +43,7 @@ pub struct CreateSessionRequest {
 #[derive(Debug, Deserialize)]
 pub struct CreateMessageRequest {
     pub role: String,
-    pub content: String,
+    pub content: MessageContent,
     pub model: Option<String>,
     pub token_count: Option<i32>,
     #[serde(default)]
@@ -64,11 +82,35 @@ pub struct ActiveModelRequest {
     pub model_id: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct CreateProviderRequest {
+    pub id: String,
+    #[serde(flatten)]
+    pub spec: ProviderSpec,
+}
+
+/// A provider added through the API, as returned without its credentials.
+#[derive(Debug, Serialize)]
+pub struct StoredProviderInfo {
+    pub id: String,
+    #[serde(flatten)]
+    pub spec: ProviderSpec,
+    pub has_credentials: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PullModelRequest {
+    /// Name as in the Ollama library, e.g. `llama3.2:3b`.
+    pub model: String,
+}
+
 #[derive(Debug, Serialize)]
 pub struct ProviderInfo {
     pub id: String,
     pub active: bool,
     pub supported_models: Vec<String>,
+    /// Registry entries for `supported_models`, in the same order.
+    pub models: Vec<crate::llm::registry::ModelInfo>,
     pub status: String, // "online", "offline", "unverified"
 }
 
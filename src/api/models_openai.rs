@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use crate::llm::models::{ToolCall, ToolDefinition};
+use crate::llm::models::{MessageContent, ResponseFormat, SamplingParams, ToolCall, ToolDefinition};
 
 #[derive(Debug, Deserialize)]
 pub struct OpenAIChatRequest {
@@ -10,16 +10,22 @@ pub struct OpenAIChatRequest {
     pub stream: Option<bool>,
     pub tools: Option<Vec<ToolDefinition>>,
     pub tool_choice: Option<serde_json::Value>,
+    pub response_format: Option<ResponseFormat>,
+    #[serde(flatten)]
+    pub sampling: SamplingParams,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct OpenAIMessage {
     pub role: String,
-    pub content: String,
+    pub content: MessageContent,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_calls: Option<Vec<ToolCall>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_call_id: Option<String>,
+    /// The model's reasoning, named as in DeepSeek's OpenAI-compatible API. Ignored in requests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -55,6 +61,8 @@ pub struct OpenAIStreamChunk {
     pub created: u64,
     pub model: String,
     pub choices: Vec<OpenAIStreamChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<OpenAIUsage>,
 }
 
 #[derive(Debug, Serialize)]
@@ -69,4 +77,6 @@ pub struct OpenAIStreamChoice {
 pub struct OpenAIStreamDelta {
     pub role: Option<String>,
     pub content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<String>,
 }
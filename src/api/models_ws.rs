@@ -11,6 +11,6 @@ pub struct WsClientMessage {
 
 #[derive(Debug, Serialize)]
 pub struct WsServerMessage {
-    pub r#type: String, // Expected: "chunk", "done", "error", "status"
+    pub r#type: String, // Expected: "chunk", "thinking", "done", "error", "status"
     pub content: String,
 }
@@ -0,0 +1,128 @@
+use actix_web::{delete, get, post, web, HttpResponse, ResponseError, Result as WebResult};
+use bytes::Bytes;
+use std::sync::Arc;
+
+use crate::api::errors::OpenAIErrorResponse;
+use crate::api::models::PullModelRequest;
+use crate::llm::{ollama::OllamaProvider, LlmError, LlmProvider, ProviderManager};
+
+/// The Ollama provider registered under `id`, whether it is the only provider or one of the
+/// manager's. Other kinds of provider under that id are not found.
+fn ollama_provider(llm: &Arc<dyn LlmProvider>, id: &str) -> Option<OllamaProvider> {
+    let provider = match llm.as_any().downcast_ref::<ProviderManager>() {
+        Some(manager) => manager.get_provider(id)?,
+        None if llm.name() == id => llm.clone(),
+        None => return None,
+    };
+    provider.as_any().downcast_ref::<OllamaProvider>().cloned()
+}
+
+fn not_configured(id: &str) -> HttpResponse {
+    HttpResponse::NotFound().body(format!("No Ollama provider is configured as '{}'", id))
+}
+
+#[get("")]
+pub async fn list_models(
+    llm: web::Data<Arc<dyn LlmProvider>>,
+    id: web::Path<String>,
+) -> WebResult<HttpResponse> {
+    let Some(ollama) = ollama_provider(&llm, &id) else {
+        return Ok(not_configured(&id));
+    };
+    match ollama.list_models().await {
+        Ok(models) => Ok(HttpResponse::Ok().json(models)),
+        Err(e) => Ok(e.error_response()),
+    }
+}
+
+#[get("/running")]
+pub async fn running_models(
+    llm: web::Data<Arc<dyn LlmProvider>>,
+    id: web::Path<String>,
+) -> WebResult<HttpResponse> {
+    let Some(ollama) = ollama_provider(&llm, &id) else {
+        return Ok(not_configured(&id));
+    };
+    match ollama.running_models().await {
+        Ok(models) => Ok(HttpResponse::Ok().json(models)),
+        Err(e) => Ok(e.error_response()),
+    }
+}
+
+/// Streams the download progress as server-sent events. Closing the connection stops
+/// following the download.
+#[post("/pull")]
+pub async fn pull_model(
+    llm: web::Data<Arc<dyn LlmProvider>>,
+    id: web::Path<String>,
+    req: web::Json<PullModelRequest>,
+) -> WebResult<HttpResponse> {
+    let Some(ollama) = ollama_provider(&llm, &id) else {
+        return Ok(not_configured(&id));
+    };
+    let model = req.into_inner().model;
+
+    let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+    let pull_handle = tokio::spawn(async move { ollama.pull_model(&model, tx).await });
+
+    let stream = async_stream::stream! {
+        while let Some(progress) = rx.recv().await {
+            yield Ok::<Bytes, actix_web::Error>(Bytes::from(format!("data: {}\n\n", serde_json::to_string(&progress).unwrap())));
+        }
+
+        let result = pull_handle
+            .await
+            .unwrap_or_else(|e| Err(LlmError::Api(format!("Pull task failed: {}", e))));
+        if let Err(e) = &result {
+            let body = OpenAIErrorResponse::from(e);
+            yield Ok::<Bytes, actix_web::Error>(Bytes::from(format!("data: {}\n\n", serde_json::to_string(&body).unwrap())));
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .streaming(stream))
+}
+
+// Model names may contain slashes, e.g. `hf.co/bartowski/Llama-3.2-1B-Instruct-GGUF`
+#[get("/{name:.+}")]
+pub async fn show_model(
+    llm: web::Data<Arc<dyn LlmProvider>>,
+    path: web::Path<(String, String)>,
+) -> WebResult<HttpResponse> {
+    let (id, name) = path.into_inner();
+    let Some(ollama) = ollama_provider(&llm, &id) else {
+        return Ok(not_configured(&id));
+    };
+    match ollama.show_model(&name).await {
+        Ok(details) => Ok(HttpResponse::Ok().json(details)),
+        Err(e) => Ok(e.error_response()),
+    }
+}
+
+#[delete("/{name:.+}")]
+pub async fn delete_model(
+    llm: web::Data<Arc<dyn LlmProvider>>,
+    path: web::Path<(String, String)>,
+) -> WebResult<HttpResponse> {
+    let (id, name) = path.into_inner();
+    let Some(ollama) = ollama_provider(&llm, &id) else {
+        return Ok(not_configured(&id));
+    };
+    match ollama.delete_model(&name).await {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        Err(e) => Ok(e.error_response()),
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/providers/{id}/models")
+            .service(list_models)
+            // Registered ahead of `show_model`, whose pattern matches any name
+            .service(running_models)
+            .service(pull_model)
+            .service(show_model)
+            .service(delete_model)
+    );
+}
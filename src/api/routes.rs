@@ -1,20 +1,22 @@
-use actix_web::{delete, get, patch, post, web, HttpResponse, Result as WebResult};
+use actix_web::{delete, get, patch, post, web, HttpResponse, ResponseError, Result as WebResult};
 use uuid::Uuid;
 use std::sync::Arc;
 
 use crate::api::models::{CreateMessageRequest, CreateSessionRequest, UpdateSessionRequest, PaginationQuery};
-use crate::db::{service::DbService, DbPool};
-use crate::llm::{LlmProvider, models::{Message as LlmMessage, ChatOptions}};
+use crate::db::{models::THINKING_KEY, service::DbService, DbPool, MessageUsage};
+use crate::llm::{bind_session_defaults, context::ContextBuilder, LlmProvider, models::{Message as LlmMessage, ChatOptions}};
 
 // --- Sessions ---
 
 #[post("")]
 pub async fn create_session(
     pool: web::Data<DbPool>,
+    llm: web::Data<Arc<dyn LlmProvider>>,
     req: web::Json<CreateSessionRequest>,
 ) -> WebResult<HttpResponse> {
     let conn = pool.lock().unwrap();
-    let req = req.into_inner();
+    let mut req = req.into_inner();
+    bind_session_defaults(llm.get_ref().as_ref(), &mut req.metadata);
     
     match DbService::insert_session(&conn, &req.name, req.metadata) {
         Ok(session) => Ok(HttpResponse::Created().json(session)),
@@ -101,11 +103,12 @@ pub async fn add_message(
     let req = req.into_inner();
     
     // Check if session exists first
-    if DbService::get_session(&conn, id).unwrap_or(None).is_none() {
-        return Ok(HttpResponse::NotFound().body("Session not found"));
-    }
+    let session = match DbService::get_session(&conn, id).unwrap_or(None) {
+        Some(session) => session,
+        None => return Ok(HttpResponse::NotFound().body("Session not found")),
+    };
 
-    let user_msg = match DbService::insert_message(
+    let user_msg = match DbService::insert_message_content(
         &conn, 
         id, 
         &req.role, 
@@ -123,23 +126,6 @@ pub async fn add_message(
         return Ok(HttpResponse::Created().json(user_msg));
     }
 
-    // Fetch history for LLM Context
-    let history = match DbService::get_messages(&conn, id, 50, 0) {
-        Ok(msgs) => msgs,
-        Err(e) => return Ok(HttpResponse::InternalServerError().body(e.to_string())),
-    };
-
-    let mut llm_messages: Vec<LlmMessage> = history.into_iter().map(|m| {
-        let tool_calls = m.metadata.get("tool_calls").and_then(|tc| serde_json::from_value(tc.clone()).ok());
-        let tool_call_id = m.metadata.get("tool_call_id").and_then(|tid| tid.as_str().map(|s| s.to_string()));
-        LlmMessage {
-            role: m.role,
-            content: m.content,
-            tool_calls,
-            tool_call_id,
-        }
-    }).collect();
-
     // Drop the DuckDB connection lock
     drop(conn);
 
@@ -148,12 +134,24 @@ pub async fn add_message(
     let system_prompt = config.chat.system_prompt.replace("{current_date}", &current_date);
     let grounded_prompt = format!("Current Date: {}.\n\n{}", current_date, system_prompt);
     
-    let current_options = ChatOptions {
+    let mut current_options = ChatOptions {
         model: req.model,
         system_prompt: Some(grounded_prompt),
         tools: Some(tools.get_definitions()),
         ..Default::default()
+    }
+    .for_session(&session.metadata);
+
+    // Fetch history for LLM Context
+    let context = match ContextBuilder::new(llm.get_ref().as_ref(), &config.chat)
+        .build(pool.get_ref(), id, &current_options)
+        .await
+    {
+        Ok(context) => context,
+        Err(e) => return Ok(HttpResponse::InternalServerError().body(e.to_string())),
     };
+    current_options.system_prompt = context.system_prompt(current_options.system_prompt.take());
+    let mut llm_messages: Vec<LlmMessage> = context.messages;
 
     let mut loop_count = 0;
     let max_loops = 5;
@@ -161,23 +159,27 @@ pub async fn add_message(
     while loop_count < max_loops {
         let response = match llm.chat(&llm_messages, current_options.clone()).await {
             Ok(res) => res,
-            Err(e) => return Ok(HttpResponse::InternalServerError().body(format!("LLM Error: {}", e))),
+            Err(e) => return Ok(e.error_response()),
         };
 
         // If no tool calls, we're done
         if response.tool_calls.as_ref().map(|tc| tc.is_empty()).unwrap_or(true) {
             // Re-lock the DB pool to insert the assistant's context
             let conn = pool.lock().unwrap();
-            let token_count = response.usage.as_ref().map(|u| u.input_tokens + u.output_tokens).map(|t| t as i32);
+            let usage = MessageUsage::of_reply(
+                llm.get_ref().as_ref(),
+                response.provider.as_deref(),
+                &response.model,
+                response.usage.as_ref(),
+            );
 
-            return match DbService::insert_message(
+            return match DbService::insert_assistant_message(
                 &conn,
                 id,
-                "assistant",
                 &response.content,
-                Some(&response.model),
-                token_count,
-                serde_json::json!({}),
+                &response.model,
+                &usage,
+                serde_json::json!({ "provider": response.provider, THINKING_KEY: response.thinking }),
             ) {
                 Ok(assistant_msg) => Ok(HttpResponse::Created().json(assistant_msg)),
                 Err(e) => Ok(HttpResponse::InternalServerError().body(e.to_string())),
@@ -195,22 +197,32 @@ pub async fn add_message(
         // 1. Add assistant message with tool calls to history
         llm_messages.push(LlmMessage {
             role: "assistant".to_string(),
-            content: response.content.clone(),
+            content: response.content.clone().into(),
             tool_calls: Some(assistant_tool_calls.clone()),
             tool_call_id: None,
+            thinking: None,
         });
 
         // 2. Persist assistant message (optional but good for history)
         {
             let conn = pool.lock().unwrap();
-            let _ = DbService::insert_message(
+            let usage = MessageUsage::of_reply(
+                llm.get_ref().as_ref(),
+                response.provider.as_deref(),
+                &response.model,
+                response.usage.as_ref(),
+            );
+            let _ = DbService::insert_assistant_message(
                 &conn,
                 id,
-                "assistant",
                 &response.content,
-                Some(&response.model),
-                None, // We'll count tokens at the end or skip here
-                serde_json::json!({ "tool_calls": assistant_tool_calls }),
+                &response.model,
+                &usage,
+                serde_json::json!({
+                    "tool_calls": assistant_tool_calls,
+                    "provider": response.provider,
+                    THINKING_KEY: response.thinking,
+                }),
             );
         }
 
@@ -221,9 +233,10 @@ pub async fn add_message(
             
             llm_messages.push(LlmMessage {
                 role: "tool".to_string(),
-                content: result.clone(),
+                content: result.clone().into(),
                 tool_calls: None,
                 tool_call_id: Some(tool_id.clone()),
+                thinking: None,
             });
 
             // Re-lock the DB pool to insert the tool result
@@ -297,6 +310,7 @@ pub async fn export_session(
 #[post("/import")]
 pub async fn import_session(
     pool: web::Data<DbPool>,
+    llm: web::Data<Arc<dyn LlmProvider>>,
     body: String,
 ) -> WebResult<HttpResponse> {
     let conn = pool.lock().unwrap();
@@ -305,8 +319,10 @@ pub async fn import_session(
     let name = lines.next()
         .and_then(|l| l.strip_prefix("Session: "))
         .unwrap_or("Imported Session");
+    let mut metadata = serde_json::json!({});
+    bind_session_defaults(llm.get_ref().as_ref(), &mut metadata);
         
-    match DbService::insert_session(&conn, name, serde_json::json!({})) {
+    match DbService::insert_session(&conn, name, metadata) {
         Ok(session) => {
             let mut current_role = String::new();
             let mut current_content = String::new();
@@ -348,6 +364,25 @@ pub async fn get_stats(
     }
 }
 
+#[get("/{id}/stats")]
+pub async fn get_session_stats(
+    pool: web::Data<DbPool>,
+    id: web::Path<Uuid>,
+) -> WebResult<HttpResponse> {
+    let conn = pool.lock().unwrap();
+    let id = id.into_inner();
+
+    match DbService::get_session(&conn, id) {
+        Ok(Some(_)) => {}
+        Ok(None) => return Ok(HttpResponse::NotFound().finish()),
+        Err(e) => return Ok(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+    match DbService::get_session_stats(&conn, id) {
+        Ok(stats) => Ok(HttpResponse::Ok().json(stats)),
+        Err(e) => Ok(HttpResponse::InternalServerError().body(e.to_string())),
+    }
+}
+
 #[get("/llm/mcp/tools")]
 pub async fn list_mcp_tools(
     llm: web::Data<Arc<dyn LlmProvider>>,
@@ -416,6 +451,7 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
             .service(list_sessions)
             .service(purge_all_sessions)
             .service(get_stats)
+            .service(get_session_stats)
             .service(get_session)
             .service(update_session)
             .service(delete_session)
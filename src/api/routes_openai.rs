@@ -1,23 +1,26 @@
-use actix_web::{post, web, HttpRequest, HttpResponse, Result as WebResult};
+use actix_web::{post, web, HttpRequest, HttpResponse, ResponseError, Result as WebResult};
 use bytes::Bytes;
 use std::sync::Arc;
 use tokio::sync::mpsc;
 use uuid::Uuid;
 
+use crate::api::errors::OpenAIErrorResponse;
 use crate::api::models_openai::{
     OpenAIChatRequest, OpenAIChatResponse, OpenAIChoice, OpenAIMessage, OpenAIStreamChoice,
     OpenAIStreamChunk, OpenAIStreamDelta, OpenAIUsage,
 };
-use crate::db::{service::DbService, DbPool};
+use crate::db::{models::THINKING_KEY, service::DbService, DbPool, MessageUsage};
 use crate::llm::{
-    models::{ChatOptions, Message as LlmMessage},
-    LlmProvider,
+    models::{ChatOptions, Message as LlmMessage, MessageContent, StreamEvent},
+    context::ContextBuilder,
+    LlmError, LlmProvider,
 };
 
 #[post("/v1/chat/completions")]
 pub async fn openai_chat_completions(
     llm: web::Data<Arc<dyn LlmProvider>>,
     pool: web::Data<DbPool>,
+    config: web::Data<crate::config::AppConfig>,
     req_http: HttpRequest,
     req: web::Json<OpenAIChatRequest>,
 ) -> WebResult<HttpResponse> {
@@ -38,17 +41,21 @@ pub async fn openai_chat_completions(
         .iter()
         .map(|m| LlmMessage {
             role: m.role.clone(),
-            content: if m.role == "system" {
-                format!(
-                    "{}{}",
-                    grounding_str,
-                    m.content.replace("{current_date}", &current_date)
-                )
-            } else {
-                m.content.replace("{current_date}", &current_date)
+            content: match &m.content {
+                MessageContent::Text(text) => {
+                    let text = text.replace("{current_date}", &current_date);
+                    if m.role == "system" {
+                        format!("{}{}", grounding_str, text).into()
+                    } else {
+                        text.into()
+                    }
+                }
+                // Content parts (e.g. images) are passed through as sent
+                parts => parts.clone(),
             },
             tool_calls: m.tool_calls.clone(),
             tool_call_id: m.tool_call_id.clone(),
+            thinking: None,
         })
         .collect();
 
@@ -58,21 +65,24 @@ pub async fn openai_chat_completions(
             0,
             LlmMessage {
                 role: "system".to_string(),
-                content: grounding_str,
+                content: grounding_str.into(),
                 tool_calls: None,
                 tool_call_id: None,
+                thinking: None,
             },
         );
     }
 
-    // If session_id is provided, persist the last user message
+    // If session_id is provided, persist the last user message. The session's history then
+    // stands in for the conversation sent by the client.
+    let mut session_history = false;
     if let Some(sid) = session_id {
         let conn = pool.lock().unwrap();
         // Just a safety check if session exists
         if DbService::get_session(&conn, sid).unwrap_or(None).is_some() {
             if let Some(last_msg) = req.messages.last() {
                 if last_msg.role == "user" {
-                    let _ = DbService::insert_message(
+                    session_history = DbService::insert_message_content(
                         &conn,
                         sid,
                         "user",
@@ -80,7 +90,8 @@ pub async fn openai_chat_completions(
                         Some(&req.model),
                         None,
                         serde_json::json!({ "source": "openai_adapter" }),
-                    );
+                    )
+                    .is_ok();
                 }
             }
         }
@@ -94,6 +105,10 @@ pub async fn openai_chat_completions(
         tools: req.tools,
         tool_choice: req.tool_choice,
         user: None,
+        provider: None,
+        response_format: req.response_format,
+        thinking_budget: None,
+        sampling: req.sampling,
     };
 
     // If no tools provided in request, offer the default ones from the registry
@@ -102,22 +117,37 @@ pub async fn openai_chat_completions(
         chat_options.tools = Some(registry.get_definitions());
     }
 
+    if let (true, Some(sid)) = (session_history, session_id) {
+        let context = match ContextBuilder::new(llm.get_ref().as_ref(), &config.chat)
+            .build(pool.get_ref(), sid, &chat_options)
+            .await
+        {
+            Ok(context) => context,
+            Err(e) => return Ok(HttpResponse::InternalServerError().body(e.to_string())),
+        };
+        chat_options.system_prompt = context.system_prompt(chat_options.system_prompt.take());
+        current_llm_messages.retain(|m| m.role == "system");
+        current_llm_messages.extend(context.messages);
+    }
+
     let is_streaming = req.stream.unwrap_or(false);
 
     if is_streaming {
         // For streaming, we'll keep the existing logic but pass the options
         let (tx, mut rx) = mpsc::channel(100);
+        let llm_pricing = llm.get_ref().clone();
         let llm_clone = llm.into_inner();
         let model_name = req.model.clone();
         let pool_clone = pool.as_ref().clone();
 
-        tokio::spawn(async move {
-            if let Err(e) = llm_clone
+        let stream_handle = tokio::spawn(async move {
+            let res = llm_clone
                 .chat_streaming(&current_llm_messages, chat_options, tx)
-                .await
-            {
+                .await;
+            if let Err(ref e) = res {
                 tracing::error!("OpenAI Adapter Streaming Error: {:?}", e);
             }
+            res
         });
 
         // ... (rest of streaming logic remains largely same, just uses current_llm_messages)
@@ -125,6 +155,7 @@ pub async fn openai_chat_completions(
         let stream = async_stream::stream! {
             let id = format!("chatcmpl-{}", Uuid::new_v4());
             let mut full_content = String::new();
+            let mut full_thinking = String::new();
 
             // OpenAI requires an empty role delta to start
             let initial_chunk = OpenAIStreamChunk {
@@ -134,15 +165,25 @@ pub async fn openai_chat_completions(
                 model: model_name.clone(),
                 choices: vec![OpenAIStreamChoice {
                     index: 0,
-                    delta: OpenAIStreamDelta { role: Some("assistant".to_string()), content: None },
+                    delta: OpenAIStreamDelta { role: Some("assistant".to_string()), content: None, reasoning_content: None },
                     finish_reason: None,
                 }],
+                usage: None,
             };
 
             yield Ok::<Bytes, actix_web::Error>(Bytes::from(format!("data: {}\n\n", serde_json::to_string(&initial_chunk).unwrap())));
 
-            while let Some(chunk_text) = rx.recv().await {
-                full_content.push_str(&chunk_text);
+            while let Some(event) = rx.recv().await {
+                let delta = match event {
+                    StreamEvent::Text(chunk_text) => {
+                        full_content.push_str(&chunk_text);
+                        OpenAIStreamDelta { role: None, content: Some(chunk_text), reasoning_content: None }
+                    }
+                    StreamEvent::Thinking(chunk_text) => {
+                        full_thinking.push_str(&chunk_text);
+                        OpenAIStreamDelta { role: None, content: None, reasoning_content: Some(chunk_text) }
+                    }
+                };
                 let chunk = OpenAIStreamChunk {
                     id: id.clone(),
                     object: "chat.completion.chunk".to_string(),
@@ -150,27 +191,53 @@ pub async fn openai_chat_completions(
                     model: model_name.clone(),
                     choices: vec![OpenAIStreamChoice {
                         index: 0,
-                        delta: OpenAIStreamDelta { role: None, content: Some(chunk_text) },
+                        delta,
                         finish_reason: None,
                     }],
+                    usage: None,
                 };
                 yield Ok::<Bytes, actix_web::Error>(Bytes::from(format!("data: {}\n\n", serde_json::to_string(&chunk).unwrap())));
             }
 
+            let result = stream_handle
+                .await
+                .unwrap_or_else(|e| Err(LlmError::Api(format!("Streaming task failed: {}", e))));
+            let answered = result.as_ref().ok();
+            let usage = answered.and_then(|r| r.usage.clone());
+            let answered_model = answered.and_then(|r| r.model.clone()).unwrap_or_else(|| model_name.clone());
+            let answered_provider = answered.and_then(|r| r.provider.clone());
+
             // Persist the assistant message if session_id was present
             if let Some(sid) = session_id {
                 let conn = pool_clone.lock().unwrap();
-                let _ = DbService::insert_message(
+                let message_usage = MessageUsage::of_reply(
+                    llm_pricing.as_ref(),
+                    answered_provider.as_deref(),
+                    &answered_model,
+                    usage.as_ref(),
+                );
+                let _ = DbService::insert_assistant_message(
                     &conn,
                     sid,
-                    "assistant",
                     &full_content,
-                    Some(&model_name),
-                    None,
-                    serde_json::json!({ "source": "openai_adapter" }),
+                    &answered_model,
+                    &message_usage,
+                    serde_json::json!({
+                        "source": "openai_adapter",
+                        "provider": answered_provider,
+                        THINKING_KEY: Some(full_thinking).filter(|t| !t.is_empty()),
+                    }),
                 );
             }
 
+            // Headers are already sent, so the failure travels as an error event like OpenAI's
+            if let Err(e) = &result {
+                let body = OpenAIErrorResponse::from(e);
+                yield Ok::<Bytes, actix_web::Error>(Bytes::from(format!("data: {}\n\n", serde_json::to_string(&body).unwrap())));
+                yield Ok::<Bytes, actix_web::Error>(Bytes::from("data: [DONE]\n\n"));
+                return;
+            }
+
             // Final chunk
             let final_chunk = OpenAIStreamChunk {
                 id: id.clone(),
@@ -179,9 +246,14 @@ pub async fn openai_chat_completions(
                 model: model_name.clone(),
                 choices: vec![OpenAIStreamChoice {
                     index: 0,
-                    delta: OpenAIStreamDelta { role: None, content: None },
+                    delta: OpenAIStreamDelta { role: None, content: None, reasoning_content: None },
                     finish_reason: Some("stop".to_string()),
                 }],
+                usage: usage.as_ref().map(|u| OpenAIUsage {
+                    prompt_tokens: u.input_tokens,
+                    completion_tokens: u.output_tokens,
+                    total_tokens: u.input_tokens + u.output_tokens,
+                }),
             };
             yield Ok::<Bytes, actix_web::Error>(Bytes::from(format!("data: {}\n\n", serde_json::to_string(&final_chunk).unwrap())));
             yield Ok::<Bytes, actix_web::Error>(Bytes::from("data: [DONE]\n\n"));
@@ -199,10 +271,7 @@ pub async fn openai_chat_completions(
         while loop_count < max_loops {
             let response = match llm.chat(&current_llm_messages, chat_options.clone()).await {
                 Ok(res) => res,
-                Err(e) => {
-                    return Ok(HttpResponse::InternalServerError()
-                        .json(serde_json::json!({ "error": e.to_string() })))
-                }
+                Err(e) => return Ok(e.error_response()),
             };
 
             if response
@@ -224,9 +293,10 @@ pub async fn openai_chat_completions(
                         index: 0,
                         message: OpenAIMessage {
                             role: "assistant".to_string(),
-                            content: response.content.clone(),
+                            content: response.content.clone().into(),
                             tool_calls: None,
                             tool_call_id: None,
+                            reasoning_content: response.thinking.clone(),
                         },
                         finish_reason: Some("stop".to_string()),
                     }],
@@ -240,17 +310,23 @@ pub async fn openai_chat_completions(
                 // Persist the assistant message if session_id was present
                 if let Some(sid) = session_id {
                     let conn = pool.lock().unwrap();
-                    let _ = DbService::insert_message(
+                    let usage = MessageUsage::of_reply(
+                        llm.get_ref().as_ref(),
+                        response.provider.as_deref(),
+                        &response.model,
+                        response.usage.as_ref(),
+                    );
+                    let _ = DbService::insert_assistant_message(
                         &conn,
                         sid,
-                        "assistant",
                         &response.content,
-                        Some(&response.model),
-                        response
-                            .usage
-                            .as_ref()
-                            .map(|u| (u.input_tokens + u.output_tokens) as i32),
-                        serde_json::json!({ "source": "openai_adapter" }),
+                        &response.model,
+                        &usage,
+                        serde_json::json!({
+                            "source": "openai_adapter",
+                            "provider": response.provider,
+                            THINKING_KEY: response.thinking,
+                        }),
                     );
                 }
 
@@ -268,22 +344,32 @@ pub async fn openai_chat_completions(
             // Add assistant message with tool calls to history
             current_llm_messages.push(LlmMessage {
                 role: "assistant".to_string(),
-                content: response.content.clone(),
+                content: response.content.clone().into(),
                 tool_calls: Some(assistant_tool_calls.clone()),
                 tool_call_id: None,
+                thinking: None,
             });
 
             // Persist assistant message
             if let Some(sid) = session_id {
                 let conn = pool.lock().unwrap();
-                let _ = DbService::insert_message(
+                let usage = MessageUsage::of_reply(
+                    llm.get_ref().as_ref(),
+                    response.provider.as_deref(),
+                    &response.model,
+                    response.usage.as_ref(),
+                );
+                let _ = DbService::insert_assistant_message(
                     &conn,
                     sid,
-                    "assistant",
                     &response.content,
-                    Some(&response.model),
-                    None,
-                    serde_json::json!({ "source": "openai_adapter", "tool_calls": assistant_tool_calls }),
+                    &response.model,
+                    &usage,
+                    serde_json::json!({
+                        "source": "openai_adapter",
+                        "tool_calls": assistant_tool_calls,
+                        "provider": response.provider
+                    }),
                 );
             }
 
@@ -310,9 +396,10 @@ pub async fn openai_chat_completions(
 
                 current_llm_messages.push(LlmMessage {
                     role: "tool".to_string(),
-                    content: result.clone(),
+                    content: result.clone().into(),
                     tool_calls: None,
                     tool_call_id: Some(tool_id.clone()),
+                    thinking: None,
                 });
 
                 // Persist tool result
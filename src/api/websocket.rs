@@ -6,10 +6,11 @@ use tracing::{error, info};
 use uuid::Uuid;
 
 use crate::api::models_ws::{WsClientMessage, WsServerMessage};
-use crate::db::{service::DbService, DbPool};
+use crate::db::{models::THINKING_KEY, service::DbService, DbPool, MessageUsage};
 use crate::llm::{
-    models::{ChatOptions, Message as LlmMessage},
-    LlmProvider,
+    context::ContextBuilder,
+    models::{ChatOptions, Message as LlmMessage, StreamEvent, StreamResponse},
+    registry, LlmProvider,
 };
 
 #[get("/ws/chat/{session_id}")]
@@ -189,57 +190,26 @@ async fn handle_chat_message(
         }
     }
 
-    // 2. Fetch History & Session Metadata
-    let (history, session_db) = {
+    // 2. Fetch Session Metadata
+    let session_db = {
         let conn = pool.lock().unwrap();
-        let history = DbService::get_messages(&conn, session_id, 50, 0).unwrap_or_default();
-        let session_db = DbService::get_session(&conn, session_id).unwrap_or(None);
-        (history, session_db)
+        DbService::get_session(&conn, session_id).unwrap_or(None)
     };
-    info!(
-        "Fetched history and session metadata for session {:?}",
-        session_id
-    );
 
     let mut system_prompt = config.chat.system_prompt.clone();
-    if let Some(s) = session_db {
-        if let Some(prompt) = s.metadata.get("system_prompt").and_then(|v| v.as_str()) {
-            system_prompt = prompt.to_string();
-        }
+    let session_metadata = session_db.map(|s| s.metadata).unwrap_or_default();
+    if let Some(prompt) = session_metadata.get("system_prompt").and_then(|v| v.as_str()) {
+        system_prompt = prompt.to_string();
     }
 
-    let mut llm_messages: Vec<LlmMessage> = history
-        .into_iter()
-        .map(|m| {
-            let tool_calls = m
-                .metadata
-                .get("tool_calls")
-                .and_then(|tc| serde_json::from_value(tc.clone()).ok());
-            let tool_call_id = m
-                .metadata
-                .get("tool_call_id")
-                .and_then(|tid| tid.as_str().map(|s| s.to_string()));
-            LlmMessage {
-                role: m.role,
-                content: m.content,
-                tool_calls,
-                tool_call_id,
-            }
-        })
-        .collect();
-
     let tools = crate::tools::ToolRegistry::new();
     let current_date = chrono::Local::now().format("%A, %B %d, %Y").to_string();
     let grounded_system_prompt = system_prompt.replace("{current_date}", &current_date);
-    let mut final_prompt = format!(
+    let final_prompt = format!(
         "Current Date: {}.\n\n{}",
         current_date, grounded_system_prompt
     );
 
-    if reason {
-        final_prompt.push_str("\n\nIMPORTANT: Please reason step-by-step before providing your final answer. Externalize your internal monologue if possible.");
-    }
-
     let mut tool_definitions = tools.get_definitions();
     if !search {
         // Filter out search tools if search is disabled
@@ -250,13 +220,39 @@ async fn handle_chat_message(
         });
     }
 
-    let current_options = ChatOptions {
+    let mut current_options = ChatOptions {
         system_prompt: Some(final_prompt),
         tools: Some(tool_definitions),
         user: Some(session_id.to_string()),
         max_tokens: Some(4096), // Increase default to prevent cut-off
         ..Default::default()
-    };
+    }
+    .for_session(&session_metadata);
+
+    // Models that reason natively think on their own budget, the others are asked to in the prompt
+    if reason {
+        if registry::describe_target(llm.as_ref(), &current_options).await.supports_thinking == Some(true) {
+            current_options.thinking_budget = Some(config.chat.thinking_budget);
+        } else if let Some(prompt) = current_options.system_prompt.as_mut() {
+            prompt.push_str("\n\nIMPORTANT: Please reason step-by-step before providing your final answer. Externalize your internal monologue if possible.");
+        }
+    }
+
+    // 3. Fetch History that fits the model's context
+    let context = ContextBuilder::new(llm.as_ref(), &config.chat)
+        .build(&pool, session_id, &current_options)
+        .await
+        .unwrap_or_else(|e| {
+            error!("Failed to load history for session {:?}: {}", session_id, e);
+            Default::default()
+        });
+    info!(
+        "Fetched {} history messages for session {:?}",
+        context.messages.len(),
+        session_id
+    );
+    current_options.system_prompt = context.system_prompt(current_options.system_prompt.take());
+    let mut llm_messages: Vec<LlmMessage> = context.messages;
 
     info!("Starting chat loop for session {:?}", session_id);
     let mut loop_count = 0;
@@ -290,28 +286,58 @@ async fn handle_chat_message(
         });
 
         let mut turn_content = String::new();
-        while let Some(chunk) = rx_stream.recv().await {
-            turn_content.push_str(&chunk);
-            let resp = WsServerMessage {
-                r#type: "chunk".to_string(),
-                content: chunk,
+        let mut turn_thinking = String::new();
+        while let Some(event) = rx_stream.recv().await {
+            let resp = match event {
+                StreamEvent::Text(chunk) => {
+                    turn_content.push_str(&chunk);
+                    WsServerMessage {
+                        r#type: "chunk".to_string(),
+                        content: chunk,
+                    }
+                }
+                StreamEvent::Thinking(chunk) => {
+                    turn_thinking.push_str(&chunk);
+                    WsServerMessage {
+                        r#type: "thinking".to_string(),
+                        content: chunk,
+                    }
+                }
             };
             if let Ok(json) = serde_json::to_string(&resp) {
                 let _ = session.text(json).await;
             }
         }
 
+        // The channel closes when the provider returns, so joining here is immediate and gives us usage
+        let stream_result = stream_handle.await;
+        info!("stepbit-core stream worker joined for session {:?}", session_id);
+
+        // With failover the answering provider may not be the active one
+        let (answered_model, answered_provider) = match &stream_result {
+            Ok(Ok(res)) => (res.model.clone(), res.provider.clone()),
+            _ => (None, None),
+        };
+        let answered_model = answered_model.unwrap_or_else(|| llm.name().to_string());
+        let usage = match &stream_result {
+            Ok(Ok(res)) => res.usage.as_ref(),
+            _ => None,
+        };
+        let usage = MessageUsage::of_reply(llm.as_ref(), answered_provider.as_deref(), &answered_model, usage);
+
         // PERSIST FIRST
         {
             let conn = pool.lock().unwrap();
-            let _ = crate::db::service::DbService::insert_message(
+            let _ = crate::db::service::DbService::insert_assistant_message(
                 &conn,
                 session_id,
-                "assistant",
                 &turn_content,
-                Some(llm.name()),
-                None,
-                serde_json::json!({}),
+                &answered_model,
+                &usage,
+                serde_json::json!({
+                    "provider": answered_provider,
+                    THINKING_KEY: Some(turn_thinking).filter(|t| !t.is_empty()),
+                }),
             );
         }
 
@@ -328,13 +354,10 @@ async fn handle_chat_message(
             .text(serde_json::to_string(&done_msg).unwrap())
             .await;
 
-        let stream_result = stream_handle.await;
-        info!("stepbit-core stream worker joined for session {:?}", session_id);
-
         let mut next_loop = false;
 
         match stream_result {
-            Ok(Ok(Some(tool_calls))) => {
+            Ok(Ok(StreamResponse { tool_calls: Some(tool_calls), thinking, .. })) => {
                 info!("Extracted {} tool calls from stream", tool_calls.len());
                 
                 // Strip the trailing JSON array from turn_content so it doesn't pollute the context
@@ -342,12 +365,14 @@ async fn handle_chat_message(
                     turn_content = clean_text;
                 }
                 
-                // Add assistant message with tool calls to history
+                // Add assistant message with tool calls to history, with the reasoning behind
+                // them so the model keeps thinking for the rest of the loop
                 llm_messages.push(LlmMessage {
                     role: "assistant".to_string(),
-                    content: turn_content.clone(),
+                    content: turn_content.clone().into(),
                     tool_calls: Some(tool_calls.clone()),
                     tool_call_id: None,
+                    thinking,
                 });
 
                 // Update DB with the tool calls
@@ -358,9 +383,9 @@ async fn handle_chat_message(
                         session_id,
                         "assistant",
                         &turn_content,
-                        Some(llm.name()),
+                        Some(&answered_model),
                         None,
-                        serde_json::json!({ "tool_calls": tool_calls }),
+                        serde_json::json!({ "tool_calls": tool_calls, "provider": answered_provider }),
                     );
                 }
 
@@ -380,9 +405,10 @@ async fn handle_chat_message(
                     
                     llm_messages.push(LlmMessage {
                         role: "tool".to_string(),
-                        content: result.clone(),
+                        content: result.clone().into(),
                         tool_calls: None,
                         tool_call_id: Some(tool_id.clone()),
+                        thinking: None,
                     });
 
                     // Persist tool result
@@ -401,13 +427,14 @@ async fn handle_chat_message(
                 }
                 next_loop = true;
             }
-            Ok(Ok(None)) => {
+            Ok(Ok(StreamResponse { tool_calls: None, .. })) => {
                 // No tools, just a normal answer
                 llm_messages.push(LlmMessage {
                     role: "assistant".to_string(),
-                    content: turn_content.clone(),
+                    content: turn_content.clone().into(),
                     tool_calls: None,
                     tool_call_id: None,
+                    thinking: None,
                 });
             }
             Ok(Err(e)) => {
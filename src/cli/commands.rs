@@ -34,9 +34,21 @@ pub enum Commands {
     Database {
         #[command(subcommand)]
         action: DatabaseAction,
+    },
+
+    /// Sign in to a provider that needs an interactive login
+    Auth {
+        #[command(subcommand)]
+        provider: AuthProvider,
     }
 }
 
+#[derive(Subcommand)]
+pub enum AuthProvider {
+    /// Sign in to GitHub Copilot with the GitHub device flow
+    Copilot,
+}
+
 #[derive(Subcommand)]
 pub enum DatabaseAction {
     /// Clear all data from the database
@@ -3,13 +3,14 @@ pub mod commands;
 use tokio::sync::mpsc;
 use std::io::{self, Write};
 
-use crate::config::AppConfig;
-use crate::db::{service::DbService, get_connection};
+use crate::config::{AppConfig, CopilotConfig};
+use crate::db::{credentials::CredentialCipher, models::THINKING_KEY, service::DbService, get_connection, MessageUsage};
 use crate::llm::{
-    models::{ChatOptions, Message as LlmMessage},
-    ProviderFactory,
+    context::ContextBuilder,
+    models::{ChatOptions, Message as LlmMessage, StreamEvent},
+    bind_session_defaults, copilot, ProviderFactory,
 };
-use crate::cli::commands::{Commands, SessionAction, DatabaseAction};
+use crate::cli::commands::{AuthProvider, Commands, SessionAction, DatabaseAction};
 use uuid::Uuid;
 
 pub async fn run_cli(command: Commands, config_path: String) {
@@ -25,7 +26,11 @@ pub async fn run_cli(command: Commands, config_path: String) {
             
             match action {
                 SessionAction::Create { name } => {
-                    match DbService::insert_session(&conn, &name, serde_json::json!({})) {
+                    let mut metadata = serde_json::json!({});
+                    if let Some(llm) = ProviderFactory::create_default(&config) {
+                        bind_session_defaults(llm.as_ref(), &mut metadata);
+                    }
+                    match DbService::insert_session(&conn, &name, metadata) {
                         Ok(session) => println!("Created Session: {} ({})", session.name, session.id),
                         Err(e) => eprintln!("Error: {}", e),
                     }
@@ -81,8 +86,12 @@ pub async fn run_cli(command: Commands, config_path: String) {
                     let name = lines.next()
                         .and_then(|l| l.strip_prefix("Session: "))
                         .unwrap_or("Imported Session");
+                    let mut metadata = serde_json::json!({});
+                    if let Some(llm) = ProviderFactory::create_default(&config) {
+                        bind_session_defaults(llm.as_ref(), &mut metadata);
+                    }
                         
-                    match DbService::insert_session(&conn, name, serde_json::json!({})) {
+                    match DbService::insert_session(&conn, name, metadata) {
                         Ok(session) => {
                             println!("Created new session: {}", session.id);
                             
@@ -131,6 +140,31 @@ pub async fn run_cli(command: Commands, config_path: String) {
         Commands::Chat { session } => {
             run_repl(session, config).await;
         }
+        Commands::Auth { provider: AuthProvider::Copilot } => {
+            login_copilot(&config.llm.copilot.clone().unwrap_or_default()).await;
+        }
+    }
+}
+
+async fn login_copilot(cfg: &CopilotConfig) {
+    let client = reqwest::Client::new();
+    let code = match copilot::request_device_code(&client, &cfg.device_code_url, &cfg.client_id).await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Failed to start GitHub login: {}", e);
+            return;
+        }
+    };
+
+    println!("Open {} and enter the code {}", code.verification_uri, code.user_code);
+    println!("Waiting for approval...");
+
+    match copilot::poll_access_token(&client, &cfg.access_token_url, &cfg.client_id, &code).await {
+        Ok(token) => match copilot::save_oauth_token(&cfg.token_file, &token) {
+            Ok(()) => println!("Signed in to GitHub Copilot. Token saved to {}", cfg.token_file),
+            Err(e) => eprintln!("Failed to save token to {}: {}", cfg.token_file, e),
+        },
+        Err(e) => eprintln!("{}", e),
     }
 }
 
@@ -138,17 +172,31 @@ async fn run_repl(session_id: Uuid, config: AppConfig) {
     let pool = get_connection(&config.database).expect("DB Error");
     
     // Verify session
-    let session_exists = {
+    let session = {
         let conn = pool.lock().unwrap();
-        DbService::get_session(&conn, session_id).unwrap_or(None).is_some()
+        DbService::get_session(&conn, session_id).unwrap_or(None)
     };
     
-    if !session_exists {
+    let Some(session) = session else {
         eprintln!("Session {} not found.", session_id);
         return;
-    }
+    };
+    let options = ChatOptions::default().for_session(&session.metadata);
     
     let llm = ProviderFactory::create_default(&config).expect("Failed to init LLM provider");
+    let cipher = match CredentialCipher::from_env() {
+        Ok(cipher) => cipher,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    {
+        let conn = pool.lock().unwrap();
+        if let Err(e) = ProviderFactory::register_stored(llm.as_ref(), &conn, cipher.as_ref()) {
+            eprintln!("Failed to load stored providers: {}", e);
+        }
+    }
     
     println!("--- Stepbit Terminal Chat ---");
     println!("Connected to Session: {}", session_id);
@@ -176,40 +224,54 @@ async fn run_repl(session_id: Uuid, config: AppConfig) {
         }
         
         // Fetch history
-        let history = {
-            let conn = pool.lock().unwrap();
-            DbService::get_messages(&conn, session_id, 50, 0).unwrap_or_default()
+        let context = match ContextBuilder::new(llm.as_ref(), &config.chat).build(&pool, session_id, &options).await {
+            Ok(context) => context,
+            Err(e) => {
+                eprintln!("Failed to load history: {}", e);
+                continue;
+            }
         };
+        let mut options = options.clone();
+        options.system_prompt = context.system_prompt(options.system_prompt.take());
+        let llm_messages: Vec<LlmMessage> = context.messages;
         
-        let llm_messages: Vec<LlmMessage> = history.into_iter().map(|m| LlmMessage {
-            role: m.role,
-            content: m.content,
-            tool_calls: None,
-            tool_call_id: None,
-        }).collect();
-        
-        let (tx, mut rx) = mpsc::channel::<String>(100);
+        let (tx, mut rx) = mpsc::channel::<StreamEvent>(100);
         let llm_clone = llm.clone();
         
         print!("Stepbit> ");
         io::stdout().flush().unwrap();
         
-        tokio::spawn(async move {
-            let _ = llm_clone.chat_streaming(&llm_messages, ChatOptions::default(), tx).await;
+        let stream_handle = tokio::spawn(async move {
+            llm_clone.chat_streaming(&llm_messages, options, tx).await
         });
         
         let mut response_text = String::new();
-        while let Some(chunk) = rx.recv().await {
-            print!("{}", chunk);
-            io::stdout().flush().unwrap();
-            response_text.push_str(&chunk);
+        let mut thinking = String::new();
+        while let Some(event) = rx.recv().await {
+            match event {
+                StreamEvent::Text(chunk) => {
+                    print!("{}", chunk);
+                    io::stdout().flush().unwrap();
+                    response_text.push_str(&chunk);
+                }
+                // Only kept with the message, the terminal shows the answer
+                StreamEvent::Thinking(chunk) => thinking.push_str(&chunk),
+            }
         }
         println!();
+
+        let (usage, model, provider) = match stream_handle.await {
+            Ok(Ok(res)) => (res.usage, res.model, res.provider),
+            _ => (None, None, None),
+        };
+        let model = model.unwrap_or_else(|| llm.name().to_string());
+        let usage = MessageUsage::of_reply(llm.as_ref(), provider.as_deref(), &model, usage.as_ref());
         
         // Save assistant content
         {
             let conn = pool.lock().unwrap();
-            let _ = DbService::insert_message(&conn, session_id, "assistant", &response_text, Some(llm.name()), None, serde_json::json!({}));
+            let metadata = serde_json::json!({ "provider": provider, THINKING_KEY: Some(thinking).filter(|t| !t.is_empty()) });
+            let _ = DbService::insert_assistant_message(&conn, session_id, &response_text, &model, &usage, metadata);
         }
     }
 }
@@ -1,4 +1,5 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct ServerConfig {
@@ -17,11 +18,57 @@ pub struct AuthConfig {
     pub token_expiry_hours: u32,
 }
 
+/// Retry policy applied to a provider's calls before giving up or failing over.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RetryConfig {
+    /// Total attempts including the first one; `1` disables retries.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled on every further attempt.
+    #[serde(default = "default_base_delay_ms")]
+    pub base_delay_ms: u64,
+    /// Upper bound for a single wait. A server asking for longer is not retried.
+    #[serde(default = "default_max_delay_ms")]
+    pub max_delay_ms: u64,
+    /// Random spread applied to each backoff delay, as a fraction of it (0.0 - 1.0).
+    #[serde(default = "default_jitter")]
+    pub jitter: f64,
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_base_delay_ms() -> u64 {
+    500
+}
+
+fn default_max_delay_ms() -> u64 {
+    30_000
+}
+
+fn default_jitter() -> f64 {
+    0.2
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_max_attempts(),
+            base_delay_ms: default_base_delay_ms(),
+            max_delay_ms: default_max_delay_ms(),
+            jitter: default_jitter(),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct OpenAiConfig {
     pub api_base: String,
     pub api_key: String,
     pub default_model: String,
+    #[serde(default)]
+    pub retry: RetryConfig,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -29,19 +76,144 @@ pub struct AnthropicConfig {
     pub api_base: String,
     pub api_key: String,
     pub default_model: String,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    #[serde(default)]
+    pub prompt_caching: PromptCachingConfig,
+}
+
+/// Which parts of an Anthropic request are marked as cacheable. Cached input is billed at a
+/// fraction of the normal price on later turns that start with the same prefix.
+#[derive(Debug, Deserialize, Clone)]
+pub struct PromptCachingConfig {
+    #[serde(default = "default_cache_breakpoint")]
+    pub system: bool,
+    #[serde(default = "default_cache_breakpoint")]
+    pub tools: bool,
+    /// The history up to the newest user turn.
+    #[serde(default = "default_cache_breakpoint")]
+    pub history: bool,
+}
+
+fn default_cache_breakpoint() -> bool {
+    true
+}
+
+impl Default for PromptCachingConfig {
+    fn default() -> Self {
+        Self {
+            system: default_cache_breakpoint(),
+            tools: default_cache_breakpoint(),
+            history: default_cache_breakpoint(),
+        }
+    }
+}
+
+impl PromptCachingConfig {
+    /// No breakpoints at all.
+    pub fn disabled() -> Self {
+        Self {
+            system: false,
+            tools: false,
+            history: false,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct OllamaConfig {
     pub base_url: String,
     pub default_model: String,
+    #[serde(default)]
+    pub retry: RetryConfig,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct CopilotConfig {
+    #[serde(default = "default_copilot_api_base")]
     pub api_base: String,
+    /// GitHub OAuth token exchanged for Copilot session tokens. When empty, the token saved
+    /// in `token_file` by `stepbit auth copilot` is used.
+    #[serde(default)]
     pub api_key: String,
+    #[serde(default = "default_copilot_model")]
     pub default_model: String,
+    #[serde(default = "default_copilot_token_url")]
+    pub token_url: String,
+    /// GitHub OAuth app used for the device-flow login.
+    #[serde(default = "default_copilot_client_id")]
+    pub client_id: String,
+    #[serde(default = "default_github_device_code_url")]
+    pub device_code_url: String,
+    #[serde(default = "default_github_access_token_url")]
+    pub access_token_url: String,
+    #[serde(default = "default_copilot_token_file")]
+    pub token_file: String,
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+fn default_copilot_api_base() -> String {
+    "https://api.githubcopilot.com".to_string()
+}
+
+fn default_copilot_model() -> String {
+    "gpt-4o".to_string()
+}
+
+fn default_copilot_token_url() -> String {
+    crate::llm::copilot::DEFAULT_TOKEN_URL.to_string()
+}
+
+fn default_copilot_client_id() -> String {
+    // The client id of the Copilot editor integrations
+    "Iv1.b507a08c87ecfe98".to_string()
+}
+
+fn default_github_device_code_url() -> String {
+    "https://github.com/login/device/code".to_string()
+}
+
+fn default_github_access_token_url() -> String {
+    "https://github.com/login/oauth/access_token".to_string()
+}
+
+fn default_copilot_token_file() -> String {
+    "./copilot_token.json".to_string()
+}
+
+impl Default for CopilotConfig {
+    fn default() -> Self {
+        Self {
+            api_base: default_copilot_api_base(),
+            api_key: String::new(),
+            default_model: default_copilot_model(),
+            token_url: default_copilot_token_url(),
+            client_id: default_copilot_client_id(),
+            device_code_url: default_github_device_code_url(),
+            access_token_url: default_github_access_token_url(),
+            token_file: default_copilot_token_file(),
+            retry: RetryConfig::default(),
+        }
+    }
+}
+
+impl CopilotConfig {
+    /// The configured OAuth token, or the one saved by `stepbit auth copilot`.
+    pub fn oauth_token(&self) -> Option<String> {
+        Some(self.api_key.clone())
+            .filter(|key| !key.is_empty() && !key.starts_with("${"))
+            .or_else(|| crate::llm::copilot::load_oauth_token(&self.token_file))
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct GeminiConfig {
+    pub api_base: String,
+    pub api_key: String,
+    pub default_model: String,
+    #[serde(default)]
+    pub retry: RetryConfig,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -49,6 +221,135 @@ pub struct StepbitCoreConfig {
     pub base_url: String,
     pub default_model: String,
     pub api_key: Option<String>,
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+/// The scripted offline provider, see `llm::mock` for the script format.
+#[derive(Debug, Deserialize, Clone)]
+pub struct MockConfig {
+    /// Path to a YAML or JSON script.
+    pub script: String,
+    #[serde(default = "default_mock_model")]
+    pub default_model: String,
+    /// Delay before every reply.
+    #[serde(default)]
+    pub latency_ms: u64,
+    /// Delay between streamed chunks.
+    #[serde(default)]
+    pub chunk_delay_ms: u64,
+}
+
+fn default_mock_model() -> String {
+    "mock".to_string()
+}
+
+/// Where `embed` calls go, independently of the active chat provider.
+#[derive(Debug, Deserialize, Clone)]
+pub struct EmbeddingConfig {
+    pub provider: String,
+    /// Defaults to the provider's own embedding model.
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CassetteMode {
+    Record,
+    Replay,
+}
+
+/// Records all chat traffic to `path`, or replays a recording from it instead of calling providers.
+#[derive(Debug, Deserialize, Clone)]
+pub struct CassetteConfig {
+    pub path: String,
+    pub mode: CassetteMode,
+}
+
+/// An extra OpenAI-compatible server (llama.cpp, vLLM, LM Studio, ...) registered under `id`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct CustomProviderConfig {
+    pub id: String,
+    pub api_base: String,
+    #[serde(default)]
+    pub api_key: String,
+    pub default_model: String,
+    /// Extra headers sent with every request.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+/// The client built for a provider added through the API.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    /// OpenAI or any OpenAI-compatible server.
+    #[serde(rename = "openai")]
+    OpenAi,
+    Anthropic,
+    Ollama,
+    Gemini,
+    Copilot,
+    #[serde(rename = "stepbit-core")]
+    StepbitCore,
+}
+
+impl ProviderKind {
+    pub fn default_api_base(&self) -> String {
+        match self {
+            ProviderKind::OpenAi => "https://api.openai.com/v1".to_string(),
+            ProviderKind::Anthropic => "https://api.anthropic.com".to_string(),
+            ProviderKind::Ollama => "http://localhost:11434".to_string(),
+            ProviderKind::Gemini => "https://generativelanguage.googleapis.com/v1beta".to_string(),
+            ProviderKind::Copilot => default_copilot_api_base(),
+            ProviderKind::StepbitCore => "http://127.0.0.1:3000".to_string(),
+        }
+    }
+}
+
+/// A provider added or replaced at runtime through the API and kept in the database.
+/// The API key and headers are never serialized nor shown in `Debug` output, they are
+/// stored encrypted on their own.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ProviderSpec {
+    pub kind: ProviderKind,
+    /// Defaults to the vendor's public API, or to the local server for Ollama and stepbit-core.
+    #[serde(default)]
+    pub api_base: Option<String>,
+    pub default_model: String,
+    /// The API key, or the GitHub OAuth token for Copilot.
+    #[serde(default, skip_serializing)]
+    pub api_key: Option<String>,
+    /// Extra headers sent with every request, for OpenAI-compatible servers only.
+    #[serde(default, skip_serializing)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+impl ProviderSpec {
+    pub fn api_base(&self) -> String {
+        self.api_base.clone().unwrap_or_else(|| self.kind.default_api_base())
+    }
+}
+
+impl std::fmt::Debug for ProviderSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Header values may carry tokens too, so only their names are shown
+        let mut header_names: Vec<&String> = self.headers.keys().collect();
+        header_names.sort();
+        f.debug_struct("ProviderSpec")
+            .field("kind", &self.kind)
+            .field("api_base", &self.api_base)
+            .field("default_model", &self.default_model)
+            .field("api_key", &self.api_key.as_ref().map(|_| "<redacted>"))
+            .field("headers", &header_names)
+            .field("retry", &self.retry)
+            .finish()
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -59,13 +360,54 @@ pub struct LlmConfig {
     pub anthropic: Option<AnthropicConfig>,
     pub ollama: Option<OllamaConfig>,
     pub copilot: Option<CopilotConfig>,
+    pub gemini: Option<GeminiConfig>,
     pub stepbit_core: Option<StepbitCoreConfig>,
+    pub mock: Option<MockConfig>,
+    pub cassette: Option<CassetteConfig>,
+    #[serde(default)]
+    pub custom: Vec<CustomProviderConfig>,
+    /// Provider ids tried in order when the active provider is unavailable.
+    #[serde(default)]
+    pub fallback: Vec<String>,
+    pub embedding: Option<EmbeddingConfig>,
+    /// Model metadata overriding the bundled table, e.g. prices negotiated with a provider.
+    #[serde(default)]
+    pub models: Vec<crate::llm::registry::ModelInfo>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct ChatConfig {
     pub max_history_messages: u32,
     pub system_prompt: String,
+    /// Tool results longer than this are cut down to their start and end in the context.
+    #[serde(default = "default_max_tool_result_tokens")]
+    pub max_tool_result_tokens: usize,
+    /// Context window assumed for models without a known context length.
+    #[serde(default = "default_context_tokens")]
+    pub default_context_tokens: usize,
+    /// Fold history that leaves the context window into a rolling summary instead of
+    /// dropping it.
+    #[serde(default = "default_summarize_history")]
+    pub summarize_history: bool,
+    /// Tokens a model that reasons natively may think for when a message asks for reasoning.
+    #[serde(default = "default_thinking_budget")]
+    pub thinking_budget: u32,
+}
+
+fn default_max_tool_result_tokens() -> usize {
+    2000
+}
+
+fn default_context_tokens() -> usize {
+    8192
+}
+
+fn default_summarize_history() -> bool {
+    true
+}
+
+fn default_thinking_budget() -> u32 {
+    4096
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -100,6 +442,16 @@ impl AppConfig {
         }
         if let Some(ref mut copilot) = app_config.llm.copilot {
             copilot.api_key = expand_env(&copilot.api_key);
+            copilot.token_file = expand_env(&copilot.token_file);
+        }
+        if let Some(ref mut gemini) = app_config.llm.gemini {
+            gemini.api_key = expand_env(&gemini.api_key);
+        }
+        for custom in &mut app_config.llm.custom {
+            custom.api_key = expand_env(&custom.api_key);
+            for value in custom.headers.values_mut() {
+                *value = expand_env(value);
+            }
         }
         if let Some(ref mut stepbit_core) = app_config.llm.stepbit_core {
             if let Some(ref key) = stepbit_core.api_key {
@@ -28,6 +28,14 @@ CREATE TABLE IF NOT EXISTS messages (
     metadata JSON DEFAULT '{}'
 );
 
+-- Usage and cost of assistant messages, added to existing databases in place
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS provider VARCHAR;
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS input_tokens INTEGER;
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS output_tokens INTEGER;
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS cost DOUBLE;
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS cache_read_tokens INTEGER;
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS cache_write_tokens INTEGER;
+
 CREATE INDEX IF NOT EXISTS idx_messages_session ON messages(session_id, created_at);
 
 CREATE TABLE IF NOT EXISTS tool_results (
@@ -61,6 +69,15 @@ CREATE TABLE IF NOT EXISTS pipelines (
     created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
     updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
 );
+
+-- Providers added through the API, overriding config.yaml entries with the same id
+CREATE TABLE IF NOT EXISTS providers (
+    id VARCHAR PRIMARY KEY,
+    settings JSON NOT NULL,
+    credentials VARCHAR,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
 "#;
 
 pub fn get_connection(config: &DatabaseConfig) -> DbResult<DbPool> {
@@ -0,0 +1,227 @@
+//! Encryption of provider credentials at rest.
+//!
+//! Secrets are sealed with AES-256-GCM under the master key in the `STEPBIT_MASTER_KEY`
+//! environment variable, 32 random bytes in base64. Each blob is bound to the id of the
+//! provider it belongs to, so a blob copied to another row fails to decrypt.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use thiserror::Error;
+
+use crate::config::ProviderSpec;
+use crate::db::models::StoredProvider;
+
+pub const MASTER_KEY_ENV: &str = "STEPBIT_MASTER_KEY";
+
+#[derive(Debug, Error)]
+pub enum CredentialError {
+    #[error("STEPBIT_MASTER_KEY must be set to store provider credentials")]
+    MissingMasterKey,
+    /// A passphrase would be open to offline guessing, so only a full random key is taken.
+    #[error("STEPBIT_MASTER_KEY must be 32 bytes in base64, e.g. the output of `openssl rand -base64 32`")]
+    InvalidMasterKey,
+    #[error("Could not encrypt the credentials of provider '{0}'")]
+    Encrypt(String),
+    /// Wrong master key, or a tampered blob.
+    #[error("Could not decrypt the credentials of provider '{0}'")]
+    Decrypt(String),
+    #[error("Stored provider '{0}' is invalid: {1}")]
+    Invalid(String, String),
+}
+
+/// The secret part of a [`ProviderSpec`].
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Secrets {
+    #[serde(default)]
+    api_key: Option<String>,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+}
+
+#[derive(Clone)]
+pub struct CredentialCipher {
+    key: [u8; 32],
+}
+
+impl CredentialCipher {
+    /// Takes the AES key from `master_key`, which has to be 32 bytes in base64 such as the
+    /// output of `openssl rand -base64 32`.
+    pub fn new(master_key: &str) -> Result<Self, CredentialError> {
+        let key = STANDARD
+            .decode(master_key.trim())
+            .ok()
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .ok_or(CredentialError::InvalidMasterKey)?;
+        Ok(Self { key })
+    }
+
+    /// The cipher for the master key in the environment, `None` if none is set. A key that
+    /// is set but malformed is an error, so it is caught at startup.
+    pub fn from_env() -> Result<Option<Self>, CredentialError> {
+        match std::env::var(MASTER_KEY_ENV).ok().filter(|key| !key.is_empty()) {
+            Some(key) => Self::new(&key).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn aead_key(&self) -> LessSafeKey {
+        LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &self.key).expect("AES-256 keys are 32 bytes"))
+    }
+
+    /// Encrypts `plaintext` for provider `id`. The result is the base64 of a random nonce
+    /// followed by the ciphertext and its tag.
+    pub fn encrypt(&self, plaintext: &[u8], id: &str) -> Result<String, CredentialError> {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| CredentialError::Encrypt(id.to_string()))?;
+
+        let mut sealed = plaintext.to_vec();
+        self.aead_key()
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(id.as_bytes()), &mut sealed)
+            .map_err(|_| CredentialError::Encrypt(id.to_string()))?;
+
+        let mut blob = nonce.to_vec();
+        blob.extend(sealed);
+        Ok(STANDARD.encode(blob))
+    }
+
+    pub fn decrypt(&self, blob: &str, id: &str) -> Result<Vec<u8>, CredentialError> {
+        let error = || CredentialError::Decrypt(id.to_string());
+        let mut nonce = STANDARD.decode(blob).map_err(|_| error())?;
+        if nonce.len() < NONCE_LEN {
+            return Err(error());
+        }
+        let mut sealed = nonce.split_off(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&nonce).map_err(|_| error())?;
+
+        let plaintext = self
+            .aead_key()
+            .open_in_place(nonce, Aad::from(id.as_bytes()), &mut sealed)
+            .map_err(|_| error())?;
+        Ok(plaintext.to_vec())
+    }
+}
+
+/// Splits `spec` into the settings stored as they are and its encrypted credentials,
+/// `None` when it has no API key nor headers.
+pub fn seal_provider(
+    id: &str,
+    spec: &ProviderSpec,
+    cipher: Option<&CredentialCipher>,
+) -> Result<(serde_json::Value, Option<String>), CredentialError> {
+    let settings = serde_json::to_value(spec).map_err(|e| CredentialError::Invalid(id.to_string(), e.to_string()))?;
+    if spec.api_key.is_none() && spec.headers.is_empty() {
+        return Ok((settings, None));
+    }
+
+    let cipher = cipher.ok_or(CredentialError::MissingMasterKey)?;
+    let secrets = Secrets {
+        api_key: spec.api_key.clone(),
+        headers: spec.headers.clone(),
+    };
+    let plaintext = serde_json::to_vec(&secrets).map_err(|_| CredentialError::Encrypt(id.to_string()))?;
+    Ok((settings, Some(cipher.encrypt(&plaintext, id)?)))
+}
+
+/// Rebuilds the spec of a stored provider, decrypting its credentials.
+pub fn open_provider(stored: &StoredProvider, cipher: Option<&CredentialCipher>) -> Result<ProviderSpec, CredentialError> {
+    let invalid = |e: serde_json::Error| CredentialError::Invalid(stored.id.clone(), e.to_string());
+    let mut spec: ProviderSpec = serde_json::from_value(stored.settings.clone()).map_err(invalid)?;
+
+    if let Some(blob) = &stored.credentials {
+        let cipher = cipher.ok_or(CredentialError::MissingMasterKey)?;
+        let secrets: Secrets = serde_json::from_slice(&cipher.decrypt(blob, &stored.id)?).map_err(invalid)?;
+        spec.api_key = secrets.api_key;
+        spec.headers = secrets.headers;
+    }
+    Ok(spec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use serde_json::json;
+
+    fn stored(id: &str, settings: serde_json::Value, credentials: Option<String>) -> StoredProvider {
+        StoredProvider {
+            id: id.to_string(),
+            settings,
+            credentials,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_credentials_round_trip_and_stay_out_of_the_settings() {
+        let cipher = CredentialCipher::new("AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=").unwrap();
+        let spec: ProviderSpec = serde_json::from_value(json!({
+            "kind": "openai",
+            "api_base": "http://localhost:8080/v1",
+            "default_model": "qwen2.5",
+            "api_key": "sk-secret",
+            "headers": { "X-Tenant": "acme" }
+        }))
+        .unwrap();
+
+        let (settings, credentials) = seal_provider("vllm", &spec, Some(&cipher)).unwrap();
+        let credentials = credentials.unwrap();
+        assert!(!settings.to_string().contains("sk-secret"));
+        assert!(!settings.to_string().contains("acme"));
+        assert!(!credentials.contains("sk-secret"));
+
+        let opened = open_provider(&stored("vllm", settings, Some(credentials)), Some(&cipher)).unwrap();
+        assert_eq!(opened.api_key.as_deref(), Some("sk-secret"));
+        assert_eq!(opened.headers.get("X-Tenant").map(String::as_str), Some("acme"));
+        assert_eq!(opened.api_base(), "http://localhost:8080/v1");
+
+        let debug = format!("{:?}", opened);
+        assert!(!debug.contains("sk-secret"));
+        assert!(!debug.contains("acme"));
+        assert!(debug.contains("X-Tenant"));
+    }
+
+    #[test]
+    fn test_wrong_key_or_provider_does_not_decrypt() {
+        let cipher = CredentialCipher::new("AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=").unwrap();
+        let blob = cipher.encrypt(b"sk-secret", "openai").unwrap();
+
+        assert_eq!(cipher.decrypt(&blob, "openai").unwrap(), b"sk-secret");
+        assert!(matches!(cipher.decrypt(&blob, "anthropic"), Err(CredentialError::Decrypt(_))));
+        assert!(matches!(
+            CredentialCipher::new("AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=").unwrap().decrypt(&blob, "openai"),
+            Err(CredentialError::Decrypt(_))
+        ));
+        // A fresh nonce every time
+        assert_ne!(blob, cipher.encrypt(b"sk-secret", "openai").unwrap());
+    }
+
+    #[test]
+    fn test_credentials_need_a_master_key() {
+        let spec: ProviderSpec = serde_json::from_value(json!({
+            "kind": "anthropic",
+            "default_model": "claude-sonnet-4-20250514",
+            "api_key": "sk-ant"
+        }))
+        .unwrap();
+        assert!(matches!(seal_provider("anthropic", &spec, None), Err(CredentialError::MissingMasterKey)));
+
+        let local: ProviderSpec = serde_json::from_value(json!({ "kind": "ollama", "default_model": "llama3.2" })).unwrap();
+        let (settings, credentials) = seal_provider("ollama", &local, None).unwrap();
+        assert_eq!(credentials, None);
+        assert_eq!(open_provider(&stored("ollama", settings, None), None).unwrap().api_base(), "http://localhost:11434");
+    }
+
+    #[test]
+    fn test_master_key_must_be_32_random_bytes() {
+        for key in ["correct horse battery staple", "c2hvcnQ=", "not base64 at all!"] {
+            assert!(matches!(CredentialCipher::new(key), Err(CredentialError::InvalidMasterKey)));
+        }
+        assert!(CredentialCipher::new(" AwMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwM=\n").is_ok());
+    }
+}
@@ -1,4 +1,5 @@
 pub mod connection;
+pub mod credentials;
 pub mod models;
 pub mod service;
 
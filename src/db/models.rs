@@ -2,6 +2,17 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::llm::models::{Message as LlmMessage, MessageContent, Usage};
+use crate::llm::{registry, LlmProvider};
+
+/// Metadata key holding the content parts of a message that carries images. The `content`
+/// column only keeps the text, so search and exports are unaffected.
+pub const CONTENT_PARTS_KEY: &str = "content_parts";
+
+/// Metadata key holding the reasoning behind an assistant message, for models that return
+/// it apart from the answer.
+pub const THINKING_KEY: &str = "thinking";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Session {
     pub id: Uuid,
@@ -21,6 +32,95 @@ pub struct Message {
     pub token_count: Option<i32>,
     pub created_at: DateTime<Utc>,
     pub metadata: serde_json::Value,
+    #[serde(flatten)]
+    pub usage: MessageUsage,
+}
+
+/// Provider, token counts and cost of an assistant message. Unset for other messages.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MessageUsage {
+    #[serde(default)]
+    pub provider: Option<String>,
+    #[serde(default)]
+    pub input_tokens: Option<i32>,
+    #[serde(default)]
+    pub output_tokens: Option<i32>,
+    /// Input tokens served from the provider's prompt cache, on top of `input_tokens`.
+    #[serde(default)]
+    pub cache_read_tokens: Option<i32>,
+    /// Input tokens written to the provider's prompt cache, on top of `input_tokens`.
+    #[serde(default)]
+    pub cache_write_tokens: Option<i32>,
+    /// USD, unknown when the model has no price in the registry.
+    #[serde(default)]
+    pub cost: Option<f64>,
+}
+
+impl MessageUsage {
+    /// Usage of a reply `provider` gave with `model`, priced with what `llm` knows about the
+    /// model. The provider defaults to `llm` itself.
+    pub fn of_reply(llm: &dyn LlmProvider, provider: Option<&str>, model: &str, usage: Option<&Usage>) -> Self {
+        let provider = provider.unwrap_or_else(|| llm.name()).to_string();
+        let cost = usage.and_then(|u| registry::lookup(llm, &provider, model).cost(u));
+        Self {
+            provider: Some(provider),
+            input_tokens: usage.map(|u| u.input_tokens as i32),
+            output_tokens: usage.map(|u| u.output_tokens as i32),
+            cache_read_tokens: usage.and_then(|u| u.cache_read_tokens).map(|n| n as i32),
+            cache_write_tokens: usage.and_then(|u| u.cache_write_tokens).map(|n| n as i32),
+            cost,
+        }
+    }
+
+    pub fn token_count(&self) -> Option<i32> {
+        match (self.input_tokens, self.output_tokens) {
+            (None, None) => None,
+            (input, output) => Some(input.unwrap_or(0) + output.unwrap_or(0)),
+        }
+    }
+}
+
+/// Spend of the messages sharing a provider, model and day.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpendEntry {
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    /// `YYYY-MM-DD`
+    pub day: String,
+    pub messages: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub cache_read_tokens: i64,
+    pub cache_write_tokens: i64,
+    pub cost: f64,
+}
+
+impl Message {
+    /// The full content of the message, including any images stored in the metadata.
+    pub fn content_parts(&self) -> MessageContent {
+        self.metadata
+            .get(CONTENT_PARTS_KEY)
+            .and_then(|parts| serde_json::from_value(parts.clone()).ok())
+            .map(MessageContent::Parts)
+            .unwrap_or_else(|| MessageContent::Text(self.content.clone()))
+    }
+
+    /// Rebuilds the message as it was exchanged with the provider, tool calls included.
+    pub fn to_llm_message(&self) -> LlmMessage {
+        LlmMessage {
+            role: self.role.clone(),
+            content: self.content_parts(),
+            tool_calls: self
+                .metadata
+                .get("tool_calls")
+                .and_then(|tc| serde_json::from_value(tc.clone()).ok()),
+            tool_call_id: self
+                .metadata
+                .get("tool_call_id")
+                .and_then(|tid| tid.as_str().map(|s| s.to_string())),
+            thinking: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -43,6 +143,17 @@ pub struct Skill {
     pub updated_at: DateTime<Utc>,
 }
 
+/// A provider added through the API. `credentials` holds its API key and headers, encrypted
+/// as described in [`crate::db::credentials`].
+#[derive(Debug, Clone)]
+pub struct StoredProvider {
+    pub id: String,
+    pub settings: serde_json::Value,
+    pub credentials: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Pipeline {
     pub id: i64,
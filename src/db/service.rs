@@ -1,4 +1,5 @@
-use crate::db::models::{Message, Session, Skill, ToolResult, Pipeline};
+use crate::db::models::{Message, MessageUsage, Session, Skill, SpendEntry, StoredProvider, ToolResult, Pipeline, CONTENT_PARTS_KEY};
+use crate::llm::models::MessageContent;
 use chrono::{DateTime, Utc};
 use duckdb::{params, params_from_iter, Connection, Result as DbResult, Row};
 use uuid::Uuid;
@@ -58,6 +59,14 @@ impl DbService {
             token_count: row.get::<_, Option<i32>>(5)?,
             created_at,
             metadata,
+            usage: MessageUsage {
+                provider: row.get::<_, Option<String>>(8)?,
+                input_tokens: row.get::<_, Option<i32>>(9)?,
+                output_tokens: row.get::<_, Option<i32>>(10)?,
+                cost: row.get::<_, Option<f64>>(11)?,
+                cache_read_tokens: row.get::<_, Option<i32>>(12)?,
+                cache_write_tokens: row.get::<_, Option<i32>>(13)?,
+            },
         })
     }
 
@@ -167,13 +176,53 @@ impl DbService {
         model: Option<&str>,
         token_count: Option<i32>,
         metadata: serde_json::Value,
+    ) -> DbResult<Message> {
+        Self::insert_message_row(conn, session_id, role, content, model, token_count, &MessageUsage::default(), metadata)
+    }
+
+    /// Inserts an assistant reply along with its usage and cost. `token_count` is the sum of
+    /// input and output tokens.
+    pub fn insert_assistant_message(
+        conn: &Connection,
+        session_id: Uuid,
+        content: &str,
+        model: &str,
+        usage: &MessageUsage,
+        metadata: serde_json::Value,
+    ) -> DbResult<Message> {
+        Self::insert_message_row(conn, session_id, "assistant", content, Some(model), usage.token_count(), usage, metadata)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn insert_message_row(
+        conn: &Connection,
+        session_id: Uuid,
+        role: &str,
+        content: &str,
+        model: Option<&str>,
+        token_count: Option<i32>,
+        usage: &MessageUsage,
+        metadata: serde_json::Value,
     ) -> DbResult<Message> {
         let meta_str = metadata.to_string();
         
         conn.execute(
-            "INSERT INTO messages (session_id, role, content, model, token_count, metadata) 
-             VALUES (?, ?, ?, ?, ?, ?)",
-            params![session_id.to_string(), role, content, model, token_count, meta_str],
+            "INSERT INTO messages (session_id, role, content, model, token_count, metadata, provider, input_tokens, output_tokens, cost, cache_read_tokens, cache_write_tokens) 
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                session_id.to_string(),
+                role,
+                content,
+                model,
+                token_count,
+                meta_str,
+                usage.provider,
+                usage.input_tokens,
+                usage.output_tokens,
+                usage.cost,
+                usage.cache_read_tokens,
+                usage.cache_write_tokens
+            ],
         )?;
 
         // Update the session's updated_at timestamp
@@ -184,7 +233,7 @@ impl DbService {
         
         // Fetch the message we just inserted (since ID is generated by sequence)
         let mut stmt = conn.prepare(
-            "SELECT id, session_id, role, content, model, token_count, CAST(created_at AS VARCHAR), metadata 
+            "SELECT id, session_id, role, content, model, token_count, CAST(created_at AS VARCHAR), metadata, provider, input_tokens, output_tokens, cost, cache_read_tokens, cache_write_tokens 
              FROM messages 
              WHERE session_id = ? 
              ORDER BY id DESC LIMIT 1"
@@ -194,10 +243,32 @@ impl DbService {
         Ok(rows.next().unwrap()?)
     }
 
+    /// Inserts a message that may carry images. The text goes in `content` as usual and the
+    /// full parts are kept under the `content_parts` metadata key, see [`Message::content_parts`].
+    pub fn insert_message_content(
+        conn: &Connection,
+        session_id: Uuid,
+        role: &str,
+        content: &MessageContent,
+        model: Option<&str>,
+        token_count: Option<i32>,
+        mut metadata: serde_json::Value,
+    ) -> DbResult<Message> {
+        if let MessageContent::Parts(parts) = content {
+            if content.has_images() {
+                if !metadata.is_object() {
+                    metadata = serde_json::json!({});
+                }
+                metadata[CONTENT_PARTS_KEY] = serde_json::to_value(parts).unwrap_or_default();
+            }
+        }
+        Self::insert_message(conn, session_id, role, &content.text(), model, token_count, metadata)
+    }
+
     pub fn get_messages(conn: &Connection, session_id: Uuid, limit: usize, offset: usize) -> DbResult<Vec<Message>> {
         let mut stmt = conn.prepare(
             "SELECT * FROM (
-                SELECT id, session_id, role, content, model, token_count, CAST(created_at AS VARCHAR) as created_at, metadata 
+                SELECT id, session_id, role, content, model, token_count, CAST(created_at AS VARCHAR) as created_at, metadata, provider, input_tokens, output_tokens, cost, cache_read_tokens, cache_write_tokens 
                 FROM messages 
                 WHERE session_id = ? 
                 ORDER BY created_at DESC 
@@ -215,6 +286,23 @@ impl DbService {
         Ok(messages)
     }
 
+    /// The newest `limit` messages with an id above `after_id`, oldest first.
+    pub fn get_messages_after(conn: &Connection, session_id: Uuid, after_id: i64, limit: usize) -> DbResult<Vec<Message>> {
+        let mut stmt = conn.prepare(
+            "SELECT * FROM (
+                SELECT id, session_id, role, content, model, token_count, CAST(created_at AS VARCHAR) as created_at, metadata, provider, input_tokens, output_tokens, cost, cache_read_tokens, cache_write_tokens 
+                FROM messages 
+                WHERE session_id = ? AND id > ? 
+                ORDER BY id DESC 
+                LIMIT ?
+             ) sub
+             ORDER BY id ASC"
+        )?;
+
+        let rows = stmt.query_map(params![session_id.to_string(), after_id, limit as i64], Self::row_to_message)?;
+        rows.collect()
+    }
+
     // --- Tool Result Operations ---
 
     pub fn insert_tool_result(
@@ -257,6 +345,7 @@ impl DbService {
         let total_sessions: i64 = conn.query_row("SELECT count(*) FROM sessions", [], |r| r.get(0))?;
         let total_messages: i64 = conn.query_row("SELECT count(*) FROM messages", [], |r| r.get(0))?;
         let total_tokens: i64 = conn.query_row("SELECT coalesce(sum(token_count), 0) FROM messages", [], |r| r.get(0))?;
+        let spend = Self::get_spend(conn, None)?;
         
         let db_size_bytes = std::fs::metadata(db_path)
             .map(|m| m.len())
@@ -274,11 +363,64 @@ impl DbService {
             total_sessions,
             total_messages,
             total_tokens,
+            total_cost: spend.iter().map(|entry| entry.cost).sum(),
+            spend,
             db_size_bytes,
             memory_usage,
         })
     }
 
+    pub fn get_session_stats(conn: &Connection, session_id: Uuid) -> DbResult<crate::api::models::SessionStats> {
+        let total_messages: i64 = conn.query_row(
+            "SELECT count(*) FROM messages WHERE session_id = ?",
+            params![session_id.to_string()],
+            |r| r.get(0),
+        )?;
+        let spend = Self::get_spend(conn, Some(session_id))?;
+
+        Ok(crate::api::models::SessionStats {
+            session_id,
+            total_messages,
+            input_tokens: spend.iter().map(|entry| entry.input_tokens).sum(),
+            output_tokens: spend.iter().map(|entry| entry.output_tokens).sum(),
+            cache_read_tokens: spend.iter().map(|entry| entry.cache_read_tokens).sum(),
+            cache_write_tokens: spend.iter().map(|entry| entry.cache_write_tokens).sum(),
+            total_cost: spend.iter().map(|entry| entry.cost).sum(),
+            spend,
+        })
+    }
+
+    /// Assistant message usage grouped by provider, model and day, oldest day first. Limited
+    /// to one session when `session_id` is given.
+    pub fn get_spend(conn: &Connection, session_id: Option<Uuid>) -> DbResult<Vec<SpendEntry>> {
+        let filter = if session_id.is_some() { "AND session_id = ?" } else { "" };
+        let mut stmt = conn.prepare(&format!(
+            "SELECT provider, model, CAST(CAST(created_at AS DATE) AS VARCHAR) AS day, count(*),
+                    coalesce(sum(input_tokens), 0), coalesce(sum(output_tokens), 0), coalesce(sum(cost), 0),
+                    coalesce(sum(cache_read_tokens), 0), coalesce(sum(cache_write_tokens), 0)
+             FROM messages
+             WHERE role = 'assistant' {}
+             GROUP BY provider, model, day
+             ORDER BY day, provider, model",
+            filter
+        ))?;
+        let params: Vec<String> = session_id.iter().map(|id| id.to_string()).collect();
+        let rows = stmt.query_map(params_from_iter(params), |r| {
+            Ok(SpendEntry {
+                provider: r.get(0)?,
+                model: r.get(1)?,
+                day: r.get(2)?,
+                messages: r.get(3)?,
+                input_tokens: r.get(4)?,
+                output_tokens: r.get(5)?,
+                cost: r.get(6)?,
+                cache_read_tokens: r.get(7)?,
+                cache_write_tokens: r.get(8)?,
+            })
+        })?;
+        rows.collect()
+    }
+
     fn row_to_tool_result(row: &Row) -> DbResult<ToolResult> {
         let created_val: duckdb::types::Value = row.get(4)?;
         let created_str = match created_val {
@@ -668,4 +810,84 @@ impl DbService {
         conn.execute("DELETE FROM pipelines WHERE id = ?", params![id])?;
         Ok(())
     }
+
+    // --- Provider Operations ---
+
+    fn row_to_provider(row: &Row) -> DbResult<StoredProvider> {
+        let settings_str: String = row.get(1)?;
+        let settings = serde_json::from_str(&settings_str).unwrap_or(serde_json::json!({}));
+
+        let created_val: duckdb::types::Value = row.get(3)?;
+        let updated_val: duckdb::types::Value = row.get(4)?;
+
+        let created_str = match created_val {
+            duckdb::types::Value::Text(s) => s,
+            _ => String::new(),
+        };
+        let updated_str = match updated_val {
+            duckdb::types::Value::Text(s) => s,
+            _ => String::new(),
+        };
+
+        let created_at = created_str.parse::<DateTime<Utc>>().unwrap_or_else(|_| Utc::now());
+        let updated_at = updated_str.parse::<DateTime<Utc>>().unwrap_or_else(|_| Utc::now());
+
+        Ok(StoredProvider {
+            id: row.get::<_, String>(0)?,
+            settings,
+            credentials: row.get::<_, Option<String>>(2)?,
+            created_at,
+            updated_at,
+        })
+    }
+
+    /// Inserts provider `id`, or replaces its settings and credentials.
+    pub fn upsert_provider(
+        conn: &Connection,
+        id: &str,
+        settings: &serde_json::Value,
+        credentials: Option<&str>,
+    ) -> DbResult<()> {
+        conn.execute(
+            "INSERT INTO providers (id, settings, credentials) VALUES (?, ?, ?)
+             ON CONFLICT (id) DO UPDATE SET
+                settings = excluded.settings,
+                credentials = excluded.credentials,
+                updated_at = now()",
+            params![id, settings.to_string(), credentials],
+        )?;
+        Ok(())
+    }
+
+    pub fn list_providers(conn: &Connection) -> DbResult<Vec<StoredProvider>> {
+        let mut stmt = conn.prepare(
+            "SELECT id, CAST(settings AS VARCHAR), credentials, CAST(created_at AS VARCHAR), CAST(updated_at AS VARCHAR)
+             FROM providers ORDER BY id"
+        )?;
+        let rows = stmt.query_map([], Self::row_to_provider)?;
+        let mut providers = Vec::new();
+        for row in rows {
+            providers.push(row?);
+        }
+        Ok(providers)
+    }
+
+    pub fn get_provider(conn: &Connection, id: &str) -> DbResult<Option<StoredProvider>> {
+        let mut stmt = conn.prepare(
+            "SELECT id, CAST(settings AS VARCHAR), credentials, CAST(created_at AS VARCHAR), CAST(updated_at AS VARCHAR)
+             FROM providers WHERE id = ?"
+        )?;
+        let mut rows = stmt.query_map(params![id], Self::row_to_provider)?;
+        if let Some(row) = rows.next() {
+            Ok(Some(row?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Returns whether a provider was stored under `id`.
+    pub fn delete_provider(conn: &Connection, id: &str) -> DbResult<bool> {
+        let deleted = conn.execute("DELETE FROM providers WHERE id = ?", params![id])?;
+        Ok(deleted > 0)
+    }
 }
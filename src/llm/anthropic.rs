@@ -1,15 +1,39 @@
 use async_trait::async_trait;
 use reqwest::Client;
 use serde_json::json;
+use std::collections::BTreeMap;
 use tokio::sync::mpsc::Sender;
 
-use crate::llm::{models::{ChatOptions, ChatResponse, Message, Usage, ToolCall}, LlmError, LlmProvider};
+use crate::config::PromptCachingConfig;
+use crate::llm::{
+    models::{
+        ChatOptions, ChatResponse, ContentPart, FunctionCall, Message, MessageContent, StreamEvent, StreamResponse,
+        ThinkingBlock, ToolCall, ToolDefinition, Usage,
+    },
+    registry, sse, LlmError, LlmProvider,
+};
+
+/// A streamed `tool_use` block whose JSON input arrives in `input_json_delta` fragments.
+struct PendingToolUse {
+    id: String,
+    name: String,
+    input_json: String,
+}
+
+const ANTHROPIC_SAMPLING: &[&str] = &["top_p", "top_k", "stop"];
+
+/// Name of the tool that carries structured replies, see `build_body`.
+const RESPONSE_TOOL: &str = "structured_response";
+
+/// Smallest thinking budget the API accepts.
+const MIN_THINKING_BUDGET: u32 = 1024;
 
 pub struct AnthropicProvider {
     client: Client,
     api_key: String,
     base_url: String,
     default_model: String,
+    prompt_caching: PromptCachingConfig,
 }
 
 impl AnthropicProvider {
@@ -22,46 +46,320 @@ impl AnthropicProvider {
             api_key,
             base_url,
             default_model,
+            prompt_caching: PromptCachingConfig::disabled(),
         }
     }
-}
 
-#[async_trait]
-impl LlmProvider for AnthropicProvider {
-    fn name(&self) -> &str {
-        "anthropic"
+    /// Marks the parts of each request enabled in `config` as cacheable. Off by default.
+    pub fn with_prompt_caching(mut self, config: PromptCachingConfig) -> Self {
+        self.prompt_caching = config;
+        self
     }
 
-    async fn chat(&self, messages: &[Message], options: ChatOptions) -> Result<ChatResponse, LlmError> {
-        let model = options.model.as_deref().unwrap_or(&self.default_model);
+    /// Text and image blocks for a message's content, skipping blank text.
+    fn content_blocks(content: &MessageContent) -> Vec<serde_json::Value> {
+        let parts = match content {
+            MessageContent::Text(text) => vec![ContentPart::text(text.as_str())],
+            MessageContent::Parts(parts) => parts.clone(),
+        };
+        parts
+            .into_iter()
+            .filter_map(|part| match part {
+                ContentPart::Text { text } if text.trim().is_empty() => None,
+                ContentPart::Text { text } => Some(json!({ "type": "text", "text": text })),
+                ContentPart::ImageUrl { image_url } => Some(match image_url.as_base64() {
+                    Some((media_type, data)) => json!({
+                        "type": "image",
+                        "source": { "type": "base64", "media_type": media_type, "data": data },
+                    }),
+                    None => json!({
+                        "type": "image",
+                        "source": { "type": "url", "url": image_url.url },
+                    }),
+                }),
+            })
+            .collect()
+    }
 
-        // Anthropic requires the 'system' prompt as a separate field, and 'messages' handles role/content only for 'user'/'assistant'
+    /// Builds the `/v1/messages` request body. Anthropic requires the system prompt as a
+    /// separate field, strictly alternating user/assistant turns, and tool traffic expressed
+    /// as `tool_use` / `tool_result` content blocks instead of OpenAI-style fields.
+    ///
+    /// With prompt caching on, cache breakpoints go after the tools, the system prompt and
+    /// the newest turn, so the next call reads everything up to its own new messages from
+    /// the cache.
+    fn build_body(
+        &self,
+        model: &str,
+        messages: &[Message],
+        options: &ChatOptions,
+        stream: bool,
+    ) -> Result<serde_json::Value, LlmError> {
         let mut system = String::new();
-        let filtered_messages: Vec<Message> = messages
-            .iter()
-            .filter_map(|m| {
-                if m.role == "system" {
-                    system.push_str(&m.content);
+        let mut turns: Vec<(String, Vec<serde_json::Value>)> = Vec::new();
+
+        for m in messages {
+            let (role, blocks) = match m.role.as_str() {
+                "system" => {
+                    system.push_str(&m.content.text());
                     system.push('\n');
-                    None
-                } else {
-                    Some(m.clone())
+                    continue;
                 }
-            })
-            .collect();
+                "tool" => (
+                    "user",
+                    vec![json!({
+                        "type": "tool_result",
+                        "tool_use_id": m.tool_call_id.clone().unwrap_or_default(),
+                        "content": m.content.text(),
+                    })],
+                ),
+                role => {
+                    let mut blocks = Self::content_blocks(&m.content);
+                    if let Some(block) = m.thinking.as_ref().filter(|_| role == "assistant") {
+                        blocks.insert(
+                            0,
+                            json!({ "type": "thinking", "thinking": block.thinking, "signature": block.signature }),
+                        );
+                    }
+                    if role == "assistant" {
+                        for call in m.tool_calls.iter().flatten() {
+                            let input: serde_json::Value = serde_json::from_str(&call.function.arguments)
+                                .unwrap_or_else(|_| json!({}));
+                            blocks.push(json!({
+                                "type": "tool_use",
+                                "id": call.id.clone().unwrap_or_default(),
+                                "name": call.function.name,
+                                "input": input,
+                            }));
+                        }
+                    }
+                    (if role == "assistant" { "assistant" } else { "user" }, blocks)
+                }
+            };
+
+            if blocks.is_empty() {
+                continue;
+            }
+
+            // Consecutive messages with the same role (e.g. several tool results) must be merged
+            match turns.last_mut() {
+                Some((last_role, last_blocks)) if last_role == role => last_blocks.extend(blocks),
+                _ => turns.push((role.to_string(), blocks)),
+            }
+        }
 
         if let Some(opts_system) = &options.system_prompt {
             system.push_str(opts_system);
         }
 
-        let body = json!({
+        if self.prompt_caching.history {
+            if let Some(block) = turns.last_mut().and_then(|(_, blocks)| blocks.last_mut()) {
+                block["cache_control"] = Self::cache_control();
+            }
+        }
+
+        let anthropic_messages: Vec<serde_json::Value> = turns
+            .into_iter()
+            .map(|(role, content)| json!({ "role": role, "content": content }))
+            .collect();
+
+        let mut body = json!({
             "model": model,
-            "messages": filtered_messages,
+            "messages": anthropic_messages,
             "system": system.trim(),
             "temperature": options.temperature.unwrap_or(0.7),
             "max_tokens": options.max_tokens.unwrap_or(4096),
         });
 
+        // Breakpoints can only be set on blocks, not on the plain string form
+        if self.prompt_caching.system && !system.trim().is_empty() {
+            body["system"] = json!([{
+                "type": "text",
+                "text": system.trim(),
+                "cache_control": Self::cache_control(),
+            }]);
+        }
+
+        if stream {
+            body["stream"] = json!(true);
+        }
+        if let Some(top_p) = options.sampling.top_p {
+            body["top_p"] = json!(top_p);
+        }
+        if let Some(top_k) = options.sampling.top_k {
+            body["top_k"] = json!(top_k);
+        }
+        if let Some(stop) = &options.sampling.stop {
+            body["stop_sequences"] = json!(stop);
+        }
+
+        let mut tools: Vec<serde_json::Value> = options.tools.iter().flatten().map(Self::map_tool).collect();
+        let mut tool_choice = options.tool_choice.as_ref().and_then(Self::map_tool_choice);
+
+        // There is no JSON mode, so structured replies are given as the input of a tool the
+        // model has to call. Other tools stay callable until it is ready to answer.
+        if let Some(schema) = options.response_format.as_ref().and_then(|f| f.schema()) {
+            tool_choice = Some(if tools.is_empty() {
+                json!({ "type": "tool", "name": RESPONSE_TOOL })
+            } else {
+                json!({ "type": "any" })
+            });
+            tools.push(json!({
+                "name": RESPONSE_TOOL,
+                "description": "Give your final answer as the input of this tool.",
+                "input_schema": schema,
+            }));
+        }
+
+        if self.prompt_caching.tools {
+            if let Some(tool) = tools.last_mut() {
+                tool["cache_control"] = Self::cache_control();
+            }
+        }
+
+        // A turn that called tools has to start with its signed thinking block when thinking
+        // is on. Tool loops carry it, history read back from the database does not, and
+        // thinking has to be left off then. Thinking also rules out forcing a tool call.
+        let calling_tools = body["messages"]
+            .as_array()
+            .and_then(|turns| turns.iter().rev().find(|turn| turn["role"] == "assistant"))
+            .and_then(|turn| turn["content"].as_array())
+            .is_some_and(|blocks| {
+                blocks.iter().any(|block| block["type"] == "tool_use")
+                    && !blocks.iter().any(|block| block["type"] == "thinking")
+            });
+        let forced_tool = tool_choice
+            .as_ref()
+            .is_some_and(|choice| choice["type"] == "any" || choice["type"] == "tool");
+        if let Some(budget) = options.thinking_budget.filter(|_| !calling_tools && !forced_tool) {
+            let budget = budget.max(MIN_THINKING_BUDGET);
+            body["thinking"] = json!({ "type": "enabled", "budget_tokens": budget });
+            // The budget counts towards max_tokens, and thinking only runs at the default
+            // temperature, without top_k and with a top_p of at least 0.95
+            if let Some(top_p) = options.sampling.top_p.filter(|p| !(0.95..=1.0).contains(p)) {
+                return Err(LlmError::Unsupported(format!(
+                    "{} only accepts a top_p between 0.95 and 1 with thinking on, got {}",
+                    self.name(),
+                    top_p
+                )));
+            }
+            body["max_tokens"] = json!(options.max_tokens.unwrap_or(4096) + budget);
+            if let Some(body) = body.as_object_mut() {
+                body.remove("temperature");
+                body.remove("top_k");
+            }
+        } else {
+            // Reasoning carried through a tool loop only goes back with thinking on
+            for turn in body["messages"].as_array_mut().into_iter().flatten() {
+                if let Some(blocks) = turn["content"].as_array_mut() {
+                    blocks.retain(|block| block["type"] != "thinking");
+                }
+            }
+        }
+
+        if !tools.is_empty() {
+            body["tools"] = json!(tools);
+            if let Some(choice) = tool_choice {
+                body["tool_choice"] = choice;
+            }
+        }
+
+        Ok(body)
+    }
+
+    fn cache_control() -> serde_json::Value {
+        json!({ "type": "ephemeral" })
+    }
+
+    /// Copies the token counts present in an Anthropic `usage` object into `usage`.
+    fn merge_usage(usage: &mut Option<Usage>, u: &serde_json::Value) {
+        if !u.is_object() {
+            return;
+        }
+        let usage = usage.get_or_insert_with(Usage::default);
+        if let Some(tokens) = u["input_tokens"].as_u64() {
+            usage.input_tokens = tokens as u32;
+        }
+        if let Some(tokens) = u["output_tokens"].as_u64() {
+            usage.output_tokens = tokens as u32;
+        }
+        if let Some(tokens) = u["cache_read_input_tokens"].as_u64() {
+            usage.cache_read_tokens = Some(tokens as u32);
+        }
+        if let Some(tokens) = u["cache_creation_input_tokens"].as_u64() {
+            usage.cache_write_tokens = Some(tokens as u32);
+        }
+    }
+
+    fn map_tool(tool: &ToolDefinition) -> serde_json::Value {
+        json!({
+            "name": tool.function.name,
+            "description": tool.function.description,
+            "input_schema": tool.function.parameters,
+        })
+    }
+
+    /// Translates an OpenAI `tool_choice` value into Anthropic's object form.
+    fn map_tool_choice(choice: &serde_json::Value) -> Option<serde_json::Value> {
+        match choice {
+            serde_json::Value::String(s) => match s.as_str() {
+                "auto" => Some(json!({ "type": "auto" })),
+                "required" => Some(json!({ "type": "any" })),
+                "none" => Some(json!({ "type": "none" })),
+                _ => None,
+            },
+            serde_json::Value::Object(_) => choice["function"]["name"]
+                .as_str()
+                .map(|name| json!({ "type": "tool", "name": name })),
+            _ => None,
+        }
+    }
+
+    /// Collects text blocks into the reply content, `thinking` blocks into the reasoning and
+    /// `tool_use` blocks into tool calls.
+    fn parse_content(blocks: &serde_json::Value) -> (String, Option<String>, Option<Vec<ToolCall>>) {
+        let mut content = String::new();
+        let mut thinking = String::new();
+        let mut tool_calls = Vec::new();
+
+        for block in blocks.as_array().into_iter().flatten() {
+            match block["type"].as_str() {
+                Some("text") => content.push_str(block["text"].as_str().unwrap_or_default()),
+                Some("thinking") => thinking.push_str(block["thinking"].as_str().unwrap_or_default()),
+                Some("tool_use") => tool_calls.push(ToolCall {
+                    id: block["id"].as_str().map(|s| s.to_string()),
+                    r#type: Some("function".to_string()),
+                    function: FunctionCall {
+                        name: block["name"].as_str().unwrap_or_default().to_string(),
+                        arguments: block["input"].to_string(),
+                    },
+                }),
+                _ => {}
+            }
+        }
+
+        // A structured reply replaces the text content
+        if let Some(index) = tool_calls.iter().position(|c| c.function.name == RESPONSE_TOOL) {
+            content = tool_calls.remove(index).function.arguments;
+        }
+
+        let thinking = if thinking.is_empty() { None } else { Some(thinking) };
+        let tool_calls = if tool_calls.is_empty() { None } else { Some(tool_calls) };
+        (content, thinking, tool_calls)
+    }
+}
+
+#[async_trait]
+impl LlmProvider for AnthropicProvider {
+    fn name(&self) -> &str {
+        "anthropic"
+    }
+
+    async fn chat(&self, messages: &[Message], options: ChatOptions) -> Result<ChatResponse, LlmError> {
+        let model = options.model.as_deref().unwrap_or(&self.default_model);
+        options.sampling.check_supported(self.name(), ANTHROPIC_SAMPLING)?;
+        let body = self.build_body(model, messages, &options, false)?;
+
         let response = self
             .client
             .post(format!("{}/v1/messages", self.base_url))
@@ -71,41 +369,32 @@ impl LlmProvider for AnthropicProvider {
             .json(&body)
             .send()
             .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+            .map_err(LlmError::from)?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let text = response.text().await.unwrap_or_default();
-            if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
-                return Err(LlmError::RateLimited);
-            }
-            return Err(LlmError::Api(format!("Anthropic Error {}: {}", status, text)));
+            return Err(LlmError::from_response(response, self.name(), "Anthropic Error").await);
         }
 
         let json: serde_json::Value = response
             .json()
             .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+            .map_err(LlmError::from)?;
 
-        let content = json["content"][0]["text"]
-            .as_str()
-            .ok_or(LlmError::InvalidRequest)?
-            .to_string();
+        if !json["content"].is_array() {
+            return Err(LlmError::InvalidRequest);
+        }
+        let (content, thinking, tool_calls) = Self::parse_content(&json["content"]);
 
-        let usage = if let Some(u) = json.get("usage") {
-            Some(Usage {
-                input_tokens: u["input_tokens"].as_u64().unwrap_or(0) as u32,
-                output_tokens: u["output_tokens"].as_u64().unwrap_or(0) as u32,
-            })
-        } else {
-            None
-        };
+        let mut usage = None;
+        Self::merge_usage(&mut usage, &json["usage"]);
 
         Ok(ChatResponse {
             content,
             model: model.to_string(),
             usage,
-            tool_calls: None,
+            tool_calls,
+            provider: None,
+            thinking,
         })
     }
 
@@ -113,37 +402,11 @@ impl LlmProvider for AnthropicProvider {
         &self,
         messages: &[Message],
         options: ChatOptions,
-        tx: Sender<String>,
-    ) -> Result<Option<Vec<ToolCall>>, LlmError> {
+        tx: Sender<StreamEvent>,
+    ) -> Result<StreamResponse, LlmError> {
         let model = options.model.as_deref().unwrap_or(&self.default_model);
-
-        // Anthropic requires the 'system' prompt as a separate field
-        let mut system = String::new();
-        let filtered_messages: Vec<Message> = messages
-            .iter()
-            .filter_map(|m| {
-                if m.role == "system" {
-                    system.push_str(&m.content);
-                    system.push('\n');
-                    None
-                } else {
-                    Some(m.clone())
-                }
-            })
-            .collect();
-
-        if let Some(opts_system) = &options.system_prompt {
-            system.push_str(opts_system);
-        }
-
-        let body = json!({
-            "model": model,
-            "messages": filtered_messages,
-            "system": system.trim(),
-            "stream": true,
-            "temperature": options.temperature.unwrap_or(0.7),
-            "max_tokens": options.max_tokens.unwrap_or(4096),
-        });
+        options.sampling.check_supported(self.name(), ANTHROPIC_SAMPLING)?;
+        let body = self.build_body(model, messages, &options, true)?;
 
         let response = self
             .client
@@ -154,56 +417,172 @@ impl LlmProvider for AnthropicProvider {
             .json(&body)
             .send()
             .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+            .map_err(LlmError::from)?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let text = response.text().await.unwrap_or_default();
-            if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
-                return Err(LlmError::RateLimited);
-            }
-            return Err(LlmError::Api(format!("Anthropic Stream Error {}: {}", status, text)));
+            return Err(LlmError::from_response(response, self.name(), "Anthropic Stream Error").await);
         }
 
-        let mut stream = response.bytes_stream();
         use futures_util::StreamExt;
+        let events = sse::events(response.bytes_stream());
+        futures_util::pin_mut!(events);
+
+        // tool_use blocks being assembled, keyed by their content block index
+        let mut pending_tools: BTreeMap<u64, PendingToolUse> = BTreeMap::new();
+        let mut usage = None;
+        // Kept with its signature, a tool call has to be sent back after it
+        let mut thinking: Option<ThinkingBlock> = None;
 
-        while let Some(chunk) = stream.next().await {
-            let bytes = chunk.map_err(|e| LlmError::Network(e.to_string()))?;
-            if let Ok(text) = String::from_utf8(bytes.to_vec()) {
-                for line in text.lines() {
-                    let line = line.trim();
-                    if line.is_empty() {
-                        continue;
+        while let Some(event) = events.next().await {
+            let event = event.map_err(LlmError::from)?;
+            let Ok(json) = serde_json::from_str::<serde_json::Value>(&event.data) else {
+                continue;
+            };
+
+            // Anthropic streams delta objects differently than OpenAI
+            match json["type"].as_str() {
+                Some("message_start") => Self::merge_usage(&mut usage, &json["message"]["usage"]),
+                // Usage on message_delta is cumulative for the whole message
+                Some("message_delta") => Self::merge_usage(&mut usage, &json["usage"]),
+                Some("content_block_start") => {
+                    let block = &json["content_block"];
+                    if block["type"].as_str() == Some("thinking") && thinking.is_none() {
+                        thinking = Some(ThinkingBlock {
+                            thinking: String::new(),
+                            signature: String::new(),
+                        });
+                    }
+                    if block["type"].as_str() == Some("tool_use") {
+                        pending_tools.insert(
+                            json["index"].as_u64().unwrap_or(0),
+                            PendingToolUse {
+                                id: block["id"].as_str().unwrap_or_default().to_string(),
+                                name: block["name"].as_str().unwrap_or_default().to_string(),
+                                input_json: String::new(),
+                            },
+                        );
                     }
-                    if let Some(data) = line.strip_prefix("data: ") {
-                        if let Ok(json) = serde_json::from_str::<serde_json::Value>(data) {
-                            // Anthropic streams delta objects differently than OpenAI
-                            if json["type"].as_str() == Some("content_block_delta") {
-                                if let Some(content) = json["delta"]["text"].as_str() {
-                                    let _ = tx.send(content.to_string()).await;
+                }
+                Some("content_block_delta") => {
+                    let delta = &json["delta"];
+                    match delta["type"].as_str() {
+                        Some("input_json_delta") => {
+                            let index = json["index"].as_u64().unwrap_or(0);
+                            if let (Some(tool), Some(partial)) =
+                                (pending_tools.get_mut(&index), delta["partial_json"].as_str())
+                            {
+                                tool.input_json.push_str(partial);
+                            }
+                        }
+                        Some("thinking_delta") => {
+                            if let Some(text) = delta["thinking"].as_str() {
+                                if let Some(block) = thinking.as_mut() {
+                                    block.thinking.push_str(text);
+                                }
+                                if tx.send(StreamEvent::Thinking(text.to_string())).await.is_err() {
+                                    return Err(LlmError::Cancelled);
+                                }
+                            }
+                        }
+                        Some("signature_delta") => {
+                            if let (Some(block), Some(signature)) = (thinking.as_mut(), delta["signature"].as_str()) {
+                                block.signature.push_str(signature);
+                            }
+                        }
+                        _ => {
+                            if let Some(content) = delta["text"].as_str() {
+                                if tx.send(StreamEvent::Text(content.to_string())).await.is_err() {
+                                    return Err(LlmError::Cancelled);
                                 }
                             }
                         }
                     }
                 }
+                Some("error") => {
+                    return Err(LlmError::from_stream_event(&json, self.name(), "Anthropic Stream Error"));
+                }
+                _ => {}
             }
         }
 
-        Ok(None)
+        let response_index = pending_tools
+            .iter()
+            .find(|(_, tool)| tool.name == RESPONSE_TOOL)
+            .map(|(index, _)| *index);
+        if let Some(tool) = response_index.and_then(|index| pending_tools.remove(&index)) {
+            if tx.send(StreamEvent::Text(tool.input_json)).await.is_err() {
+                return Err(LlmError::Cancelled);
+            }
+        }
+
+        if pending_tools.is_empty() {
+            return Ok(StreamResponse { tool_calls: None, usage, ..Default::default() });
+        }
+
+        let tool_calls = pending_tools
+            .into_values()
+            .map(|tool| ToolCall {
+                id: Some(tool.id),
+                r#type: Some("function".to_string()),
+                function: FunctionCall {
+                    name: tool.name,
+                    arguments: if tool.input_json.trim().is_empty() {
+                        "{}".to_string()
+                    } else {
+                        tool.input_json
+                    },
+                },
+            })
+            .collect();
+
+        Ok(StreamResponse {
+            tool_calls: Some(tool_calls),
+            usage,
+            thinking: thinking.filter(|block| !block.signature.is_empty()),
+            ..Default::default()
+        })
     }
 
     fn supported_models(&self) -> Vec<String> {
-        vec!["claude-3-5-sonnet-20241022", "claude-3-opus-20240229"].into_iter().map(|s| s.to_string()).collect()
+        registry::bundled_models("anthropic")
     }
 
     async fn discover_models(&self) -> Result<Vec<String>, LlmError> {
-        Ok(self.supported_models())
+        let response = self
+            .client
+            .get(format!("{}/v1/models", self.base_url))
+            .query(&[("limit", "1000")])
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", "2023-06-01")
+            .send()
+            .await
+            .map_err(LlmError::from)?;
+
+        if !response.status().is_success() {
+            return Ok(self.supported_models());
+        }
+
+        let json: serde_json::Value = response
+            .json()
+            .await
+            .map_err(LlmError::from)?;
+
+        let model_names: Vec<String> = json["data"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|m| m["id"].as_str().map(|s| s.to_string()))
+            .collect();
+
+        if model_names.is_empty() {
+            Ok(self.supported_models())
+        } else {
+            Ok(model_names)
+        }
     }
 
     async fn verify_connection(&self) -> Result<(), LlmError> {
-        // Anthropic doesn't have a simple GET /models endpoint. 
-        // We'll just check if the key is provided for now.
+        // Checking the key would cost a request, so only its presence is verified
         if self.api_key.is_empty() {
             return Err(LlmError::Api("Anthropic API key is missing".to_string()));
         }
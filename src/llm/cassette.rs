@@ -0,0 +1,369 @@
+//! Records provider interactions to a cassette file and replays them without network access.
+//!
+//! A cassette is a JSON file holding every `chat` and `chat_streaming` call made through a
+//! [`RecordingProvider`] in record mode: the request messages and options, and the reply
+//! including the exact sequence of streamed text chunks. In replay mode the same provider serves
+//! those replies back. Requests are matched on their messages only, since options carry
+//! volatile values such as the current date in the system prompt; each recorded interaction
+//! is served once, in recording order.
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::mpsc::Sender;
+
+use crate::llm::{
+    models::{
+        ChatOptions, ChatResponse, McpToolDefinition, Message, PipelineExecuteResult,
+        ReasoningGraph, StreamEvent, StreamResponse, ToolCall, ToolDefinition, Usage,
+    },
+    registry::ModelInfo,
+    LlmError, LlmProvider,
+};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Cassette {
+    /// Name of the provider that was recorded.
+    pub provider: String,
+    pub interactions: Vec<Interaction>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub messages: Vec<Message>,
+    pub options: ChatOptions,
+    pub response: RecordedResponse,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub content: String,
+    /// The chunks in the order they were streamed, `None` for a non-streaming call.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunks: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// The model's reasoning, replayed as a single event ahead of the chunks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<String>,
+}
+
+enum Mode {
+    Record(Arc<dyn LlmProvider>),
+    /// Which recorded interactions have already been served.
+    Replay(Mutex<Vec<bool>>),
+}
+
+/// Wraps a provider to record its interactions, or stands in for it to replay them.
+/// Failed calls are passed through and never recorded.
+pub struct RecordingProvider {
+    mode: Mode,
+    path: PathBuf,
+    cassette: Mutex<Cassette>,
+}
+
+impl RecordingProvider {
+    /// Records every successful call to `inner` into a new cassette at `path`, rewriting the
+    /// file after each call so that an interrupted session keeps what it captured.
+    pub fn record(inner: Arc<dyn LlmProvider>, path: impl AsRef<Path>) -> Self {
+        let cassette = Cassette {
+            provider: inner.name().to_string(),
+            interactions: Vec::new(),
+        };
+        Self {
+            mode: Mode::Record(inner),
+            path: path.as_ref().to_path_buf(),
+            cassette: Mutex::new(cassette),
+        }
+    }
+
+    /// Serves the interactions recorded in the cassette at `path`.
+    pub fn replay(path: impl AsRef<Path>) -> Result<Self, LlmError> {
+        let path = path.as_ref().to_path_buf();
+        let cassette: Cassette = std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string()))
+            .map_err(|e| LlmError::Api(format!("Failed to load cassette {}: {}", path.display(), e)))?;
+
+        Ok(Self {
+            mode: Mode::Replay(Mutex::new(vec![false; cassette.interactions.len()])),
+            path,
+            cassette: Mutex::new(cassette),
+        })
+    }
+
+    /// A copy of everything recorded or loaded so far.
+    pub fn cassette(&self) -> Cassette {
+        self.cassette.lock().clone()
+    }
+
+    fn save(&self, messages: &[Message], options: ChatOptions, response: RecordedResponse) -> Result<(), LlmError> {
+        let mut cassette = self.cassette.lock();
+        cassette.interactions.push(Interaction {
+            messages: messages.to_vec(),
+            options,
+            response,
+        });
+        let json = serde_json::to_string_pretty(&*cassette)
+            .map_err(|e| LlmError::Api(format!("Failed to serialize cassette: {}", e)))?;
+        std::fs::write(&self.path, json)
+            .map_err(|e| LlmError::Api(format!("Failed to write cassette {}: {}", self.path.display(), e)))
+    }
+
+    /// Takes the first unserved interaction whose messages equal `messages`.
+    fn next_match(&self, used: &Mutex<Vec<bool>>, messages: &[Message]) -> Result<RecordedResponse, LlmError> {
+        let wanted = serde_json::to_value(messages).unwrap_or_default();
+        let cassette = self.cassette.lock();
+        let mut used = used.lock();
+
+        let index = cassette
+            .interactions
+            .iter()
+            .enumerate()
+            .position(|(i, interaction)| {
+                !used[i] && serde_json::to_value(&interaction.messages).unwrap_or_default() == wanted
+            })
+            .ok_or_else(|| {
+                let last = messages.last().map(|m| m.content.text()).unwrap_or_default();
+                LlmError::Api(format!(
+                    "No unused interaction in cassette {} matches the conversation ending with {:?}",
+                    self.path.display(),
+                    last
+                ))
+            })?;
+        used[index] = true;
+        Ok(cassette.interactions[index].response.clone())
+    }
+}
+
+#[async_trait]
+impl LlmProvider for RecordingProvider {
+    fn name(&self) -> &str {
+        match &self.mode {
+            Mode::Record(inner) => inner.name(),
+            Mode::Replay(_) => "cassette",
+        }
+    }
+
+    async fn chat(&self, messages: &[Message], options: ChatOptions) -> Result<ChatResponse, LlmError> {
+        match &self.mode {
+            Mode::Record(inner) => {
+                let response = inner.chat(messages, options.clone()).await?;
+                self.save(
+                    messages,
+                    options,
+                    RecordedResponse {
+                        content: response.content.clone(),
+                        chunks: None,
+                        model: Some(response.model.clone()),
+                        provider: response.provider.clone(),
+                        usage: response.usage.clone(),
+                        tool_calls: response.tool_calls.clone(),
+                        thinking: response.thinking.clone(),
+                    },
+                )?;
+                Ok(response)
+            }
+            Mode::Replay(used) => {
+                let recorded = self.next_match(used, messages)?;
+                Ok(ChatResponse {
+                    content: recorded.content,
+                    model: recorded.model.unwrap_or_default(),
+                    usage: recorded.usage,
+                    tool_calls: recorded.tool_calls,
+                    provider: recorded.provider,
+                    thinking: recorded.thinking,
+                })
+            }
+        }
+    }
+
+    async fn chat_streaming(
+        &self,
+        messages: &[Message],
+        options: ChatOptions,
+        tx: Sender<StreamEvent>,
+    ) -> Result<StreamResponse, LlmError> {
+        match &self.mode {
+            Mode::Record(inner) => {
+                let (inner_tx, mut inner_rx) = tokio::sync::mpsc::channel::<StreamEvent>(100);
+                // Owning inner_rx drops it once the client leaves, which cancels the inner stream
+                let relay = async move {
+                    let mut chunks = Vec::new();
+                    let mut thinking = String::new();
+                    while let Some(event) = inner_rx.recv().await {
+                        match &event {
+                            StreamEvent::Text(chunk) => chunks.push(chunk.clone()),
+                            StreamEvent::Thinking(chunk) => thinking.push_str(chunk),
+                        }
+                        if tx.send(event).await.is_err() {
+                            break;
+                        }
+                    }
+                    (chunks, thinking)
+                };
+                let (result, (chunks, thinking)) =
+                    tokio::join!(inner.chat_streaming(messages, options.clone(), inner_tx), relay);
+                let response = result?;
+
+                self.save(
+                    messages,
+                    options,
+                    RecordedResponse {
+                        content: chunks.concat(),
+                        chunks: Some(chunks),
+                        model: response.model.clone(),
+                        provider: response.provider.clone(),
+                        usage: response.usage.clone(),
+                        tool_calls: response.tool_calls.clone(),
+                        thinking: Some(thinking).filter(|t| !t.is_empty()),
+                    },
+                )?;
+                Ok(response)
+            }
+            Mode::Replay(used) => {
+                let recorded = self.next_match(used, messages)?;
+                // A non-streaming recording is replayed as a single chunk
+                let chunks = recorded.chunks.unwrap_or_else(|| vec![recorded.content]);
+                let events = recorded
+                    .thinking
+                    .into_iter()
+                    .map(StreamEvent::Thinking)
+                    .chain(chunks.into_iter().filter(|c| !c.is_empty()).map(StreamEvent::Text));
+                for event in events {
+                    if tx.send(event).await.is_err() {
+                        return Err(LlmError::Cancelled);
+                    }
+                }
+                Ok(StreamResponse {
+                    tool_calls: recorded.tool_calls,
+                    usage: recorded.usage,
+                    provider: recorded.provider,
+                    model: recorded.model,
+                    ..Default::default()
+                })
+            }
+        }
+    }
+
+    fn supported_models(&self) -> Vec<String> {
+        match &self.mode {
+            Mode::Record(inner) => inner.supported_models(),
+            Mode::Replay(_) => {
+                let cassette = self.cassette.lock();
+                let mut models: Vec<String> = cassette
+                    .interactions
+                    .iter()
+                    .filter_map(|i| i.response.model.clone())
+                    .collect();
+                models.dedup();
+                models
+            }
+        }
+    }
+
+    async fn embed(&self, inputs: &[String], model: Option<String>) -> Result<Vec<Vec<f32>>, LlmError> {
+        match &self.mode {
+            Mode::Record(inner) => inner.embed(inputs, model).await,
+            Mode::Replay(_) => Err(LlmError::Unsupported("Embeddings are not recorded in cassettes".to_string())),
+        }
+    }
+
+    async fn discover_models(&self) -> Result<Vec<String>, LlmError> {
+        match &self.mode {
+            Mode::Record(inner) => inner.discover_models().await,
+            Mode::Replay(_) => Ok(self.supported_models()),
+        }
+    }
+
+    async fn describe_model(&self, model: &str) -> Result<Option<ModelInfo>, LlmError> {
+        match &self.mode {
+            Mode::Record(inner) => inner.describe_model(model).await,
+            Mode::Replay(_) => Ok(None),
+        }
+    }
+
+    async fn verify_connection(&self) -> Result<(), LlmError> {
+        match &self.mode {
+            Mode::Record(inner) => inner.verify_connection().await,
+            Mode::Replay(_) => Ok(()),
+        }
+    }
+
+    fn default_model(&self) -> String {
+        match &self.mode {
+            Mode::Record(inner) => inner.default_model(),
+            Mode::Replay(_) => self.supported_models().into_iter().next().unwrap_or_default(),
+        }
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        match &self.mode {
+            // Recording stays transparent to callers that look for the ProviderManager
+            Mode::Record(inner) => inner.as_any(),
+            Mode::Replay(_) => self,
+        }
+    }
+
+    async fn cancel(&self, session_id: &str) -> Result<(), LlmError> {
+        match &self.mode {
+            Mode::Record(inner) => inner.cancel(session_id).await,
+            Mode::Replay(_) => Ok(()),
+        }
+    }
+
+    fn tools(&self) -> Vec<ToolDefinition> {
+        match &self.mode {
+            Mode::Record(inner) => inner.tools(),
+            Mode::Replay(_) => vec![],
+        }
+    }
+
+    async fn get_mcp_tools(&self) -> Result<Vec<McpToolDefinition>, LlmError> {
+        match &self.mode {
+            Mode::Record(inner) => inner.get_mcp_tools().await,
+            Mode::Replay(_) => Ok(vec![]),
+        }
+    }
+
+    async fn execute_reasoning(
+        &self,
+        graph: ReasoningGraph,
+    ) -> Result<HashMap<String, serde_json::Value>, LlmError> {
+        match &self.mode {
+            Mode::Record(inner) => inner.execute_reasoning(graph).await,
+            Mode::Replay(_) => Err(LlmError::Api("Reasoning is not recorded in cassettes".to_string())),
+        }
+    }
+
+    async fn execute_reasoning_streaming(
+        &self,
+        graph: ReasoningGraph,
+        tx: Sender<serde_json::Value>,
+    ) -> Result<(), LlmError> {
+        match &self.mode {
+            Mode::Record(inner) => inner.execute_reasoning_streaming(graph, tx).await,
+            Mode::Replay(_) => Err(LlmError::Api("Reasoning is not recorded in cassettes".to_string())),
+        }
+    }
+
+    async fn execute_pipeline(
+        &self,
+        pipeline: serde_json::Value,
+        question: String,
+    ) -> Result<PipelineExecuteResult, LlmError> {
+        match &self.mode {
+            Mode::Record(inner) => inner.execute_pipeline(pipeline, question).await,
+            Mode::Replay(_) => Err(LlmError::Api("Pipelines are not recorded in cassettes".to_string())),
+        }
+    }
+}
@@ -0,0 +1,307 @@
+//! Builds the history sent with each chat call of a session.
+//!
+//! The newest messages are kept as long as they fit the model's context window (and
+//! `chat.max_history_messages`), long tool results are cut down to their start and end, and
+//! the messages that fall out of the window are folded by the LLM into a rolling summary
+//! that is stored in the session metadata and sent along with the system prompt.
+
+use duckdb::Result as DbResult;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::config::ChatConfig;
+use crate::db::{models::Message as DbMessage, service::DbService, DbPool};
+use crate::llm::{
+    models::{ChatOptions, Message, MessageContent},
+    registry, LlmError, LlmProvider,
+};
+
+/// Session metadata key holding the rolling [`Summary`].
+pub const SUMMARY_KEY: &str = "context_summary";
+
+/// How many messages after the summary are loaded at most. Older ones are neither sent nor
+/// summarized.
+const LOAD_LIMIT: usize = 1000;
+/// Output tokens kept free when the call does not set `max_tokens`.
+const DEFAULT_OUTPUT_RESERVE: usize = 1024;
+/// Rough per-message overhead of roles and separators.
+const MESSAGE_OVERHEAD: usize = 4;
+/// What an image is counted as, close to OpenAI's cost of a high detail tile set.
+const IMAGE_TOKENS: usize = 765;
+const SUMMARY_MAX_TOKENS: u32 = 512;
+/// How much of each message goes into the summarization prompt.
+const SUMMARY_MESSAGE_TOKENS: usize = 500;
+
+const SUMMARY_PROMPT: &str = "You maintain a running summary of a conversation between a user and an assistant. \
+Merge the new messages into the summary so far. Keep facts, decisions, names, numbers and open questions; \
+drop small talk. Reply with the updated summary only, in at most a few short paragraphs.";
+
+/// Estimated token count of `text`, about four characters per token. Providers count
+/// differently, so budgets leave some slack.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Estimated tokens `message` takes up in a request.
+pub fn estimate_message_tokens(message: &Message) -> usize {
+    let calls = message
+        .tool_calls
+        .as_ref()
+        .map(|calls| estimate_tokens(&serde_json::to_string(calls).unwrap_or_default()))
+        .unwrap_or(0);
+    MESSAGE_OVERHEAD
+        + estimate_tokens(&message.content.text())
+        + message.content.images().len() * IMAGE_TOKENS
+        + calls
+}
+
+/// Cuts `text` down to about `max_tokens` by keeping its start and end.
+pub fn elide(text: &str, max_tokens: usize) -> String {
+    let len = text.chars().count();
+    let max_chars = max_tokens * 4;
+    if len <= max_chars {
+        return text.to_string();
+    }
+    let head = max_chars * 2 / 3;
+    let tail = max_chars - head;
+    let start: String = text.chars().take(head).collect();
+    let end: String = text.chars().skip(len - tail).collect();
+    format!("{}\n[... {} characters elided ...]\n{}", start, len - head - tail, end)
+}
+
+/// The conversation so far, condensed by the LLM.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Summary {
+    pub content: String,
+    /// Id of the last message folded into the summary.
+    pub through_id: i64,
+}
+
+/// History ready to be sent, and the summary of what came before it.
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    pub messages: Vec<Message>,
+    pub summary: Option<Summary>,
+}
+
+impl Context {
+    /// `system_prompt` followed by the summary of the earlier conversation, if any.
+    pub fn system_prompt(&self, system_prompt: Option<String>) -> Option<String> {
+        let Some(summary) = &self.summary else {
+            return system_prompt;
+        };
+        let summary = format!("Summary of the earlier conversation:\n{}", summary.content);
+        match system_prompt {
+            Some(prompt) => Some(format!("{}\n\n{}", prompt, summary)),
+            None => Some(summary),
+        }
+    }
+}
+
+pub struct ContextBuilder<'a> {
+    llm: &'a dyn LlmProvider,
+    max_messages: usize,
+    max_tool_result_tokens: usize,
+    default_context_tokens: usize,
+    summarize: bool,
+}
+
+impl<'a> ContextBuilder<'a> {
+    pub fn new(llm: &'a dyn LlmProvider, config: &ChatConfig) -> Self {
+        Self {
+            llm,
+            max_messages: config.max_history_messages as usize,
+            max_tool_result_tokens: config.max_tool_result_tokens,
+            default_context_tokens: config.default_context_tokens,
+            summarize: config.summarize_history,
+        }
+    }
+
+    /// Context for the next call in session `session_id`. Messages leaving the window are
+    /// folded into the session's summary, which is saved before returning.
+    pub async fn build(&self, pool: &DbPool, session_id: Uuid, options: &ChatOptions) -> DbResult<Context> {
+        let (history, summary) = {
+            let conn = pool.lock().unwrap();
+            let summary = DbService::get_session(&conn, session_id)?
+                .and_then(|session| session.metadata.get(SUMMARY_KEY).cloned())
+                .and_then(|summary| serde_json::from_value::<Summary>(summary).ok());
+            let after = summary.as_ref().map_or(0, |summary| summary.through_id);
+            (DbService::get_messages_after(&conn, session_id, after, LOAD_LIMIT)?, summary)
+        };
+
+        let previous = summary.clone();
+        let context = self.build_from(&history, summary, options).await;
+
+        if context.summary != previous {
+            if let Some(summary) = &context.summary {
+                let conn = pool.lock().unwrap();
+                if let Some(session) = DbService::get_session(&conn, session_id)? {
+                    let mut metadata = session.metadata;
+                    if !metadata.is_object() {
+                        metadata = serde_json::json!({});
+                    }
+                    metadata[SUMMARY_KEY] = serde_json::to_value(summary).unwrap_or_default();
+                    DbService::update_session(&conn, session_id, None, Some(metadata))?;
+                }
+            }
+        }
+        Ok(context)
+    }
+
+    /// Same as [`build`](Self::build) for `history` already loaded: the messages after
+    /// `summary`, oldest first. Nothing is saved.
+    pub async fn build_from(&self, history: &[DbMessage], summary: Option<Summary>, options: &ChatOptions) -> Context {
+        let budget = self.budget(options);
+        let (mut start, mut messages) = self.window(history, budget, self.max_messages);
+        if start > 0 && self.summarize {
+            // Fold a batch at once, so the next few turns fit without another summary call
+            (start, messages) = self.window(history, budget * 3 / 4, (self.max_messages * 3 / 4).max(1));
+        }
+        let dropped = &history[..start];
+
+        let summary = if dropped.is_empty() || !self.summarize {
+            summary
+        } else {
+            match self.fold(summary.as_ref(), dropped, options).await {
+                Ok(folded) => Some(folded),
+                Err(e) => {
+                    warn!("Could not summarize {} earlier messages, leaving them out: {}", dropped.len(), e);
+                    summary
+                }
+            }
+        };
+        Context { messages, summary }
+    }
+
+    /// Tokens left for history once the model's output and thinking, the system prompt and
+    /// the tool definitions are accounted for.
+    pub fn budget(&self, options: &ChatOptions) -> usize {
+        let (provider, model) = registry::target(self.llm, options);
+        let window = registry::lookup(self.llm, &provider, &model)
+            .context_length
+            .map_or(self.default_context_tokens, |length| length as usize);
+        let reserved = options.max_tokens.map_or(DEFAULT_OUTPUT_RESERVE, |tokens| tokens as usize)
+            + options.thinking_budget.unwrap_or(0) as usize;
+        let system = options.system_prompt.as_deref().map_or(0, estimate_tokens);
+        let tools = options
+            .tools
+            .as_ref()
+            .map_or(0, |tools| estimate_tokens(&serde_json::to_string(tools).unwrap_or_default()));
+        window.saturating_sub(reserved + system + tools)
+    }
+
+    /// Picks the newest messages of `history` that fit `budget` and `max_messages`.
+    /// Returns where the kept messages start and the messages ready to send. The newest
+    /// message is always kept, and the window never opens on a tool result whose call
+    /// was left out.
+    pub fn window(&self, history: &[DbMessage], budget: usize, max_messages: usize) -> (usize, Vec<Message>) {
+        let prepared: Vec<Message> = history.iter().map(|m| self.prepare(m)).collect();
+
+        let mut start = history.len();
+        let mut used = 0;
+        for (index, message) in prepared.iter().enumerate().rev() {
+            let tokens = estimate_message_tokens(message);
+            let full = history.len() - index > max_messages || used + tokens > budget;
+            if full && start < history.len() {
+                break;
+            }
+            used += tokens;
+            start = index;
+        }
+        while start + 1 < history.len() && history[start].role == "tool" {
+            start += 1;
+        }
+
+        (start, prepared.into_iter().skip(start).collect())
+    }
+
+    fn prepare(&self, message: &DbMessage) -> Message {
+        let mut message = message.to_llm_message();
+        if message.role == "tool" {
+            if let MessageContent::Text(text) = &message.content {
+                message.content = elide(text, self.max_tool_result_tokens).into();
+            }
+        }
+        message
+    }
+
+    /// Asks the LLM to merge `dropped` into `previous`.
+    async fn fold(&self, previous: Option<&Summary>, dropped: &[DbMessage], options: &ChatOptions) -> Result<Summary, LlmError> {
+        let summary_options = ChatOptions {
+            provider: options.provider.clone(),
+            model: options.model.clone(),
+            system_prompt: Some(SUMMARY_PROMPT.to_string()),
+            max_tokens: Some(SUMMARY_MAX_TOKENS),
+            user: options.user.clone(),
+            ..Default::default()
+        };
+
+        // A very long backlog is cut down to its most recent messages
+        let mut budget = self.budget(&summary_options).saturating_sub(previous.map_or(0, |p| estimate_tokens(&p.content)));
+        let mut transcript = Vec::new();
+        for message in dropped.iter().rev().filter(|m| !m.content.trim().is_empty()) {
+            let line = format!("{}: {}", message.role, elide(&message.content, SUMMARY_MESSAGE_TOKENS));
+            let tokens = estimate_tokens(&line);
+            if tokens > budget {
+                break;
+            }
+            budget -= tokens;
+            transcript.push(line);
+        }
+        transcript.reverse();
+
+        let mut prompt = String::new();
+        if let Some(previous) = previous {
+            prompt.push_str(&format!("Summary so far:\n{}\n\n", previous.content));
+        }
+        prompt.push_str(&format!("New messages:\n{}", transcript.join("\n\n")));
+
+        let messages = vec![Message {
+            role: "user".to_string(),
+            content: prompt.into(),
+            tool_calls: None,
+            tool_call_id: None,
+            thinking: None,
+        }];
+        let response = self.llm.chat(&messages, summary_options).await?;
+
+        Ok(Summary {
+            content: response.content.trim().to_string(),
+            through_id: dropped.last().map_or(0, |m| m.id),
+        })
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_elide_keeps_start_and_end() {
+        assert_eq!(elide("short", 10), "short");
+
+        let text = format!("{}{}", "a".repeat(300), "z".repeat(100));
+        let elided = elide(&text, 25);
+        assert!(elided.starts_with(&"a".repeat(66)));
+        assert!(elided.ends_with(&"z".repeat(34)));
+        assert!(elided.contains("[... 300 characters elided ...]"));
+    }
+
+    #[test]
+    fn test_summary_follows_the_system_prompt() {
+        let context = Context {
+            messages: vec![],
+            summary: Some(Summary {
+                content: "The user is planning a trip to Lyon.".to_string(),
+                through_id: 4,
+            }),
+        };
+        assert_eq!(
+            context.system_prompt(Some("Be brief.".to_string())).unwrap(),
+            "Be brief.\n\nSummary of the earlier conversation:\nThe user is planning a trip to Lyon."
+        );
+        assert_eq!(Context::default().system_prompt(None), None);
+    }
+}
@@ -1,29 +1,251 @@
 use async_trait::async_trait;
-use reqwest::Client;
+use reqwest::{Client, Method};
+use serde::Deserialize;
 use serde_json::json;
+use std::path::Path;
 use tokio::sync::mpsc::Sender;
+use tracing::debug;
 
-use crate::llm::{models::{ChatOptions, ChatResponse, Message, Usage, ToolCall}, LlmError, LlmProvider};
+use crate::llm::{
+    models::{ChatOptions, ChatResponse, Message, StreamEvent, StreamResponse, ToolCall},
+    openai::{apply_sampling, parse_usage, reasoning_text, ToolCallDeltas, OPENAI_SAMPLING},
+    registry, sse, ErrorKind, LlmError, LlmProvider,
+};
 
+/// GitHub's endpoint exchanging an OAuth token for a Copilot session token.
+pub const DEFAULT_TOKEN_URL: &str = "https://api.github.com/copilot_internal/v2/token";
+
+/// Session tokens are refreshed this many seconds before they expire.
+const REFRESH_MARGIN_SECS: i64 = 60;
+
+/// A short-lived Copilot session token.
+#[derive(Debug, Clone, Deserialize)]
+struct SessionToken {
+    token: String,
+    /// Unix time in seconds.
+    expires_at: i64,
+}
+
+impl SessionToken {
+    fn is_fresh(&self) -> bool {
+        chrono::Utc::now().timestamp() < self.expires_at - REFRESH_MARGIN_SECS
+    }
+}
+
+/// Talks to the Copilot chat API with session tokens obtained from a GitHub OAuth token.
+/// Session tokens last about 30 minutes; one is fetched on first use, reused while it is
+/// fresh, and fetched again when it is about to expire or the API rejects it.
 pub struct CopilotProvider {
     client: Client,
-    api_key: String,
+    oauth_token: String,
     base_url: String,
     default_model: String,
+    token_url: String,
+    session: tokio::sync::Mutex<Option<SessionToken>>,
 }
 
 impl CopilotProvider {
-    pub fn new(api_key: String, base_url: String, default_model: String) -> Self {
+    pub fn new(oauth_token: String, base_url: String, default_model: String) -> Self {
         Self {
             client: Client::builder()
                 .timeout(std::time::Duration::from_secs(60))
                 .build()
                 .unwrap_or_else(|_| Client::new()),
-            api_key,
+            oauth_token,
             base_url,
             default_model,
+            token_url: DEFAULT_TOKEN_URL.to_string(),
+            session: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    pub fn with_token_url(mut self, token_url: &str) -> Self {
+        self.token_url = token_url.to_string();
+        self
+    }
+
+    /// A session token that is valid for at least another minute.
+    async fn session_token(&self) -> Result<String, LlmError> {
+        let mut session = self.session.lock().await;
+        if let Some(current) = session.as_ref().filter(|s| s.is_fresh()) {
+            return Ok(current.token.clone());
+        }
+
+        if self.oauth_token.is_empty() {
+            return Err(LlmError::Provider {
+                provider: self.name().to_string(),
+                status: 401,
+                kind: ErrorKind::Auth,
+                message: "Copilot is not signed in, run `stepbit auth copilot`".to_string(),
+                retry_after: None,
+            });
+        }
+
+        debug!("Exchanging the GitHub OAuth token for a Copilot session token");
+        let response = self
+            .client
+            .get(&self.token_url)
+            .header("Authorization", format!("token {}", self.oauth_token))
+            .header("Editor-Version", "vscode/1.93.0")
+            .header("User-Agent", "stepbit")
+            .header("Accept", "application/json")
+            .send()
+            .await
+            .map_err(LlmError::from)?;
+
+        if !response.status().is_success() {
+            return Err(LlmError::from_response(response, self.name(), "Copilot Token Exchange Error").await);
+        }
+
+        let fresh: SessionToken = response
+            .json()
+            .await
+            .map_err(|e| LlmError::Api(format!("Invalid Copilot token response: {}", e)))?;
+        let token = fresh.token.clone();
+        *session = Some(fresh);
+        Ok(token)
+    }
+
+    /// Sends a request to the Copilot API. A 401 means the session token was revoked early,
+    /// so it is dropped and the request is sent once more with a new one.
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&serde_json::Value>,
+        vision: bool,
+    ) -> Result<reqwest::Response, LlmError> {
+        for attempt in 0..2 {
+            let token = self.session_token().await?;
+            let mut request = self
+                .client
+                .request(method.clone(), format!("{}{}", self.base_url, path))
+                .header("Authorization", format!("Bearer {}", token))
+                .header("Editor-Version", "vscode/1.93.0")
+                .header("Source", "vscode-chat")
+                .header("Openai-Organization", "github-copilot");
+            // Copilot rejects image parts unless the request is flagged as a vision request
+            if vision {
+                request = request.header("Copilot-Vision-Request", "true");
+            }
+            if let Some(body) = body {
+                request = request.json(body);
+            }
+
+            let response = request.send().await.map_err(LlmError::from)?;
+            if response.status() != reqwest::StatusCode::UNAUTHORIZED || attempt == 1 {
+                return Ok(response);
+            }
+            debug!("Copilot session token was rejected, fetching a new one");
+            *self.session.lock().await = None;
+        }
+        unreachable!("the second attempt always returns")
+    }
+}
+
+/// A pending GitHub device-flow login, see [`request_device_code`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceCode {
+    pub device_code: String,
+    /// The code the user enters at `verification_uri`.
+    pub user_code: String,
+    pub verification_uri: String,
+    /// Seconds to wait between polls.
+    #[serde(default = "default_poll_interval")]
+    pub interval: u64,
+    pub expires_in: u64,
+}
+
+fn default_poll_interval() -> u64 {
+    5
+}
+
+/// Starts a GitHub device-flow login for the OAuth app `client_id`.
+pub async fn request_device_code(client: &Client, url: &str, client_id: &str) -> Result<DeviceCode, LlmError> {
+    let response = client
+        .post(url)
+        .header("Accept", "application/json")
+        .json(&json!({ "client_id": client_id, "scope": "read:user" }))
+        .send()
+        .await
+        .map_err(LlmError::from)?;
+
+    if !response.status().is_success() {
+        return Err(LlmError::from_response(response, "copilot", "GitHub Device Code Error").await);
+    }
+    response
+        .json()
+        .await
+        .map_err(|e| LlmError::Api(format!("Invalid device code response: {}", e)))
+}
+
+/// Polls until the user has approved the device-flow login and returns the OAuth token.
+pub async fn poll_access_token(
+    client: &Client,
+    url: &str,
+    client_id: &str,
+    code: &DeviceCode,
+) -> Result<String, LlmError> {
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(code.expires_in);
+    let mut interval = code.interval;
+
+    while std::time::Instant::now() < deadline {
+        tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+
+        let response = client
+            .post(url)
+            .header("Accept", "application/json")
+            .json(&json!({
+                "client_id": client_id,
+                "device_code": code.device_code,
+                "grant_type": "urn:ietf:params:oauth:grant-type:device_code",
+            }))
+            .send()
+            .await
+            .map_err(LlmError::from)?;
+
+        if !response.status().is_success() {
+            return Err(LlmError::from_response(response, "copilot", "GitHub Access Token Error").await);
+        }
+
+        // GitHub answers 200 with an `error` field until the login is complete
+        let json: serde_json::Value = response.json().await.map_err(LlmError::from)?;
+        if let Some(token) = json["access_token"].as_str() {
+            return Ok(token.to_string());
+        }
+        match json["error"].as_str() {
+            Some("authorization_pending") => {}
+            Some("slow_down") => interval += 5,
+            Some(error) => {
+                let description = json["error_description"].as_str().unwrap_or(error);
+                return Err(LlmError::Api(format!("GitHub login failed: {}", description)));
+            }
+            None => return Err(LlmError::Api("GitHub login failed: unexpected response".to_string())),
         }
     }
+
+    Err(LlmError::Api("GitHub login expired before it was approved".to_string()))
+}
+
+/// Reads the OAuth token saved by [`save_oauth_token`], if any.
+pub fn load_oauth_token(path: impl AsRef<Path>) -> Option<String> {
+    let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()?;
+    json["oauth_token"].as_str().map(str::to_string)
+}
+
+/// Saves the OAuth token, readable by the current user only.
+pub fn save_oauth_token(path: impl AsRef<Path>, token: &str) -> std::io::Result<()> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, json!({ "oauth_token": token }).to_string())?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
 }
 
 #[async_trait]
@@ -34,6 +256,7 @@ impl LlmProvider for CopilotProvider {
 
     async fn chat(&self, messages: &[Message], options: ChatOptions) -> Result<ChatResponse, LlmError> {
         let model = options.model.as_deref().unwrap_or(&self.default_model);
+        options.sampling.check_supported(self.name(), OPENAI_SAMPLING)?;
 
         let mut body = json!({
             "model": model,
@@ -45,57 +268,56 @@ impl LlmProvider for CopilotProvider {
         if let Some(system) = &options.system_prompt {
             let mut final_messages = vec![Message {
                 role: "system".to_string(),
-                content: system.clone(),
+                content: system.clone().into(),
                 tool_calls: None,
                 tool_call_id: None,
+                thinking: None,
             }];
             final_messages.extend_from_slice(messages);
             body["messages"] = json!(final_messages);
         }
 
-        let response = self
-            .client
-            .post(format!("{}/chat/completions", self.base_url))
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .header("Editor-Version", "vscode/1.93.0")
-            .header("Source", "vscode-chat")
-            .header("Openai-Organization", "github-copilot")
-            .header("Content-Type", "application/json")
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+        if let Some(tools) = &options.tools {
+            body["tools"] = json!(tools);
+        }
+        if let Some(choice) = &options.tool_choice {
+            body["tool_choice"] = json!(choice);
+        }
+        if let Some(format) = &options.response_format {
+            body["response_format"] = json!(format);
+        }
+        apply_sampling(&mut body, &options.sampling);
+
+        let vision = messages.iter().any(|m| m.content.has_images());
+        let response = self.send(Method::POST, "/chat/completions", Some(&body), vision).await?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let text = response.text().await.unwrap_or_default();
-            return Err(LlmError::Api(format!("Copilot Error {}: {}", status, text)));
+            return Err(LlmError::from_response(response, self.name(), "Copilot Error").await);
         }
 
         let json: serde_json::Value = response
             .json()
             .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+            .map_err(LlmError::from)?;
 
-        let content = json["choices"][0]["message"]["content"]
+        let message = &json["choices"][0]["message"];
+        let content = message["content"]
             .as_str()
             .unwrap_or_default()
             .to_string();
 
-        let usage = if let Some(u) = json.get("usage") {
-            Some(Usage {
-                input_tokens: u["prompt_tokens"].as_u64().unwrap_or(0) as u32,
-                output_tokens: u["completion_tokens"].as_u64().unwrap_or(0) as u32,
-            })
-        } else {
-            None
-        };
+        let tool_calls: Option<Vec<ToolCall>> = message.get("tool_calls")
+            .and_then(|tc| serde_json::from_value(tc.clone()).ok());
+
+        let usage = parse_usage(&json);
 
         Ok(ChatResponse {
             content,
             model: model.to_string(),
             usage,
-            tool_calls: None,
+            tool_calls,
+            provider: None,
+            thinking: reasoning_text(message).map(str::to_string),
         })
     }
 
@@ -103,14 +325,16 @@ impl LlmProvider for CopilotProvider {
         &self,
         messages: &[Message],
         options: ChatOptions,
-        tx: Sender<String>,
-    ) -> Result<Option<Vec<ToolCall>>, LlmError> {
+        tx: Sender<StreamEvent>,
+    ) -> Result<StreamResponse, LlmError> {
         let model = options.model.as_deref().unwrap_or(&self.default_model);
+        options.sampling.check_supported(self.name(), OPENAI_SAMPLING)?;
 
         let mut body = json!({
             "model": model,
             "messages": messages,
             "stream": true,
+            "stream_options": { "include_usage": true },
             "temperature": options.temperature.unwrap_or(0.7),
             "max_tokens": options.max_tokens.unwrap_or(4096),
         });
@@ -118,72 +342,80 @@ impl LlmProvider for CopilotProvider {
         if let Some(system) = &options.system_prompt {
             let mut final_messages = vec![Message {
                 role: "system".to_string(),
-                content: system.clone(),
+                content: system.clone().into(),
                 tool_calls: None,
                 tool_call_id: None,
+                thinking: None,
             }];
             final_messages.extend_from_slice(messages);
             body["messages"] = json!(final_messages);
         }
 
-        let response = self
-            .client
-            .post(format!("{}/chat/completions", self.base_url))
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .header("Editor-Version", "vscode/1.93.0")
-            .header("Source", "vscode-chat")
-            .header("Openai-Organization", "github-copilot")
-            .header("Content-Type", "application/json")
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+        if let Some(tools) = &options.tools {
+            body["tools"] = json!(tools);
+        }
+        if let Some(choice) = &options.tool_choice {
+            body["tool_choice"] = json!(choice);
+        }
+        if let Some(format) = &options.response_format {
+            body["response_format"] = json!(format);
+        }
+        apply_sampling(&mut body, &options.sampling);
+
+        let vision = messages.iter().any(|m| m.content.has_images());
+        let response = self.send(Method::POST, "/chat/completions", Some(&body), vision).await?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let text = response.text().await.unwrap_or_default();
-            return Err(LlmError::Api(format!("Copilot Stream Error {}: {}", status, text)));
+            return Err(LlmError::from_response(response, self.name(), "Copilot Stream Error").await);
         }
 
-        let mut stream = response.bytes_stream();
         use futures_util::StreamExt;
+        let events = sse::events(response.bytes_stream());
+        futures_util::pin_mut!(events);
+
+        let mut tool_deltas = ToolCallDeltas::default();
+        let mut usage = None;
+
+        while let Some(event) = events.next().await {
+            let event = event.map_err(LlmError::from)?;
+            if event.data == "[DONE]" {
+                break;
+            }
+            let Ok(json) = serde_json::from_str::<serde_json::Value>(&event.data) else {
+                continue;
+            };
 
-        while let Some(chunk) = stream.next().await {
-            let bytes = chunk.map_err(|e| LlmError::Network(e.to_string()))?;
-            if let Ok(text) = String::from_utf8(bytes.to_vec()) {
-                for line in text.lines() {
-                    let line = line.trim();
-                    if line.is_empty() || line == "data: [DONE]" {
-                        continue;
-                    }
-                    if let Some(data) = line.strip_prefix("data: ") {
-                        if let Ok(json) = serde_json::from_str::<serde_json::Value>(data) {
-                            if let Some(content) = json["choices"][0]["delta"]["content"].as_str() {
-                                let _ = tx.send(content.to_string()).await;
-                            }
-                        }
-                    }
+            let delta = &json["choices"][0]["delta"];
+            if let Some(thinking) = reasoning_text(delta) {
+                if tx.send(StreamEvent::Thinking(thinking.to_string())).await.is_err() {
+                    return Err(LlmError::Cancelled);
+                }
+            }
+            if let Some(content) = delta["content"].as_str() {
+                if tx.send(StreamEvent::Text(content.to_string())).await.is_err() {
+                    return Err(LlmError::Cancelled);
                 }
             }
+            tool_deltas.push(&delta["tool_calls"]);
+            // With include_usage the last chunk carries usage and no choices
+            if let Some(u) = parse_usage(&json) {
+                usage = Some(u);
+            }
         }
 
-        Ok(None)
+        Ok(StreamResponse {
+            tool_calls: tool_deltas.finish(),
+            usage,
+            ..Default::default()
+        })
     }
 
     fn supported_models(&self) -> Vec<String> {
-        vec!["gpt-4o", "gpt-4", "gpt-3.5-turbo"].into_iter().map(|s| s.to_string()).collect()
+        registry::bundled_models("copilot")
     }
 
     async fn discover_models(&self) -> Result<Vec<String>, LlmError> {
-        let response = self
-            .client
-            .get(format!("{}/models", self.base_url))
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .header("Editor-Version", "vscode/1.93.0")
-            .header("Source", "vscode-chat")
-            .send()
-            .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+        let response = self.send(Method::GET, "/models", None, false).await?;
 
         if !response.status().is_success() {
             return Ok(self.supported_models());
@@ -192,7 +424,7 @@ impl LlmProvider for CopilotProvider {
         let json: serde_json::Value = response
             .json()
             .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+            .map_err(LlmError::from)?;
 
         if let Some(data) = json["data"].as_array() {
             let model_names: Vec<String> = data
@@ -209,15 +441,7 @@ impl LlmProvider for CopilotProvider {
     }
 
     async fn verify_connection(&self) -> Result<(), LlmError> {
-        let response = self
-            .client
-            .get(format!("{}/models", self.base_url))
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .header("Editor-Version", "vscode/1.93.0")
-            .header("Source", "vscode-chat")
-            .send()
-            .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+        let response = self.send(Method::GET, "/models", None, false).await?;
 
         if response.status().is_success() {
             Ok(())
@@ -0,0 +1,211 @@
+use serde::Serialize;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::llm::retry;
+
+/// What went wrong, independently of the provider that reported it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Auth,
+    Quota,
+    RateLimited,
+    ContextLengthExceeded,
+    ContentFilter,
+    InvalidRequest,
+    NotFound,
+    Server,
+    Timeout,
+    Cancelled,
+    Network,
+    Other,
+}
+
+#[derive(Debug, Error)]
+pub enum LlmError {
+    #[error("Network Error: {0}")]
+    Network(String),
+    #[error("API Error: {0}")]
+    Api(String),
+    #[error("Invalid Request")]
+    InvalidRequest,
+    /// The request uses an option the provider cannot honour.
+    #[error("Unsupported: {0}")]
+    Unsupported(String),
+    #[error("Timeout: {0}")]
+    Timeout(String),
+    /// The caller stopped listening before the provider finished.
+    #[error("Cancelled")]
+    Cancelled,
+    /// The provider answered with a non-success HTTP status.
+    #[error("{message}")]
+    Provider {
+        provider: String,
+        status: u16,
+        kind: ErrorKind,
+        message: String,
+        retry_after: Option<Duration>,
+    },
+}
+
+impl LlmError {
+    /// Builds the error for a non-success response from `provider`. The message reads
+    /// `"{context} {status}: {detail}"`, where the detail is the vendor's own error message
+    /// when the body could be parsed, and the raw body otherwise.
+    pub async fn from_response(response: reqwest::Response, provider: &str, context: &str) -> Self {
+        let status = response.status();
+        let retry_after = retry::retry_after(response.headers());
+        let body = response.text().await.unwrap_or_default();
+
+        let vendor = VendorError::parse(&body);
+        let kind = vendor.classify(status.as_u16());
+        let detail = vendor.message.unwrap_or(body);
+
+        LlmError::Provider {
+            provider: provider.to_string(),
+            status: status.as_u16(),
+            kind,
+            message: format!("{} {}: {}", context, status, detail),
+            retry_after,
+        }
+    }
+
+    /// Builds the error for an `error` event sent by `provider` inside a stream that opened
+    /// with 200, classified like the same vendor error returned as an HTTP status.
+    pub fn from_stream_event(event: &serde_json::Value, provider: &str, context: &str) -> Self {
+        let vendor = VendorError::parse(&event.to_string());
+        // No HTTP status to go by, the vendor's error type decides alone
+        let kind = vendor.classify(0);
+        let detail = vendor.message.unwrap_or_else(|| "unknown error".to_string());
+
+        LlmError::Provider {
+            provider: provider.to_string(),
+            status: 200,
+            kind,
+            message: format!("{}: {}", context, detail),
+            retry_after: None,
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            LlmError::Network(_) => ErrorKind::Network,
+            LlmError::Api(_) => ErrorKind::Other,
+            LlmError::InvalidRequest | LlmError::Unsupported(_) => ErrorKind::InvalidRequest,
+            LlmError::Timeout(_) => ErrorKind::Timeout,
+            LlmError::Cancelled => ErrorKind::Cancelled,
+            LlmError::Provider { kind, .. } => *kind,
+        }
+    }
+
+    /// The HTTP status the provider answered with, if it answered at all.
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            LlmError::Provider { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// Id of the provider that reported the error, if it came from a provider response.
+    pub fn provider(&self) -> Option<&str> {
+        match self {
+            LlmError::Provider { provider, .. } => Some(provider),
+            _ => None,
+        }
+    }
+
+    /// Whether the same request may succeed if sent again later, or to another provider.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self.kind(),
+            ErrorKind::Network | ErrorKind::Timeout | ErrorKind::RateLimited | ErrorKind::Server
+        )
+    }
+
+    /// How long the server asked us to wait before trying again, if it said so.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            LlmError::Provider { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for LlmError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            LlmError::Timeout(e.to_string())
+        } else {
+            LlmError::Network(e.to_string())
+        }
+    }
+}
+
+/// The fields we care about from a vendor error body. Covers the OpenAI-compatible
+/// `{"error": {"message", "type", "code"}}` shape (OpenAI, Copilot, stepbit-core),
+/// Anthropic's `{"type": "error", "error": {"type", "message"}}`, Gemini's
+/// `{"error": {"code", "status", "message"}}` and Ollama's `{"error": "..."}`.
+#[derive(Debug, Default)]
+struct VendorError {
+    code: Option<String>,
+    r#type: Option<String>,
+    message: Option<String>,
+}
+
+impl VendorError {
+    fn parse(body: &str) -> Self {
+        let Ok(json) = serde_json::from_str::<serde_json::Value>(body) else {
+            return Self::default();
+        };
+        let text = |v: &serde_json::Value| v.as_str().map(|s| s.to_string());
+
+        match &json["error"] {
+            serde_json::Value::String(message) => Self {
+                message: Some(message.clone()),
+                ..Default::default()
+            },
+            error => Self {
+                code: text(&error["code"]),
+                r#type: text(&error["type"]).or_else(|| text(&error["status"])),
+                message: text(&error["message"]),
+            },
+        }
+    }
+
+    fn classify(&self, status: u16) -> ErrorKind {
+        let code = self.code.as_deref().unwrap_or_default();
+        let r#type = self.r#type.as_deref().unwrap_or_default();
+        let message = self.message.as_deref().unwrap_or_default().to_lowercase();
+        let tagged = |tags: &[&str]| tags.contains(&code) || tags.contains(&r#type);
+
+        if tagged(&["context_length_exceeded", "string_above_max_length", "request_too_large"])
+            || message.contains("context length")
+            || message.contains("context window")
+            || message.contains("prompt is too long")
+        {
+            ErrorKind::ContextLengthExceeded
+        } else if tagged(&["insufficient_quota", "billing_hard_limit_reached"]) {
+            ErrorKind::Quota
+        } else if tagged(&["content_filter", "content_policy_violation"]) {
+            ErrorKind::ContentFilter
+        } else if status == 401
+            || status == 403
+            || tagged(&["authentication_error", "permission_error", "invalid_api_key"])
+        {
+            ErrorKind::Auth
+        } else if status == 429 || tagged(&["rate_limit_error", "rate_limit_exceeded"]) {
+            ErrorKind::RateLimited
+        } else if status == 404 || tagged(&["not_found_error", "model_not_found"]) {
+            ErrorKind::NotFound
+        } else if status == 408 || status == 504 {
+            ErrorKind::Timeout
+        } else if status >= 500 || tagged(&["overloaded_error", "api_error", "server_error"]) {
+            ErrorKind::Server
+        } else if status >= 400 {
+            ErrorKind::InvalidRequest
+        } else {
+            ErrorKind::Other
+        }
+    }
+}
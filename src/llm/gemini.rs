@@ -0,0 +1,442 @@
+use async_trait::async_trait;
+use reqwest::Client;
+use serde_json::json;
+use std::collections::HashMap;
+use tokio::sync::mpsc::Sender;
+use tracing::warn;
+
+use crate::llm::{
+    models::{
+        ChatOptions, ChatResponse, ContentPart, FunctionCall, Message, MessageContent, StreamEvent, StreamResponse,
+        ToolCall, ToolDefinition, Usage,
+    },
+    registry, sse, ErrorKind, LlmError, LlmProvider,
+};
+
+const GEMINI_SAMPLING: &[&str] = &["top_p", "top_k", "stop", "seed", "presence_penalty", "frequency_penalty"];
+
+pub struct GeminiProvider {
+    client: Client,
+    api_key: String,
+    base_url: String,
+    default_model: String,
+}
+
+impl GeminiProvider {
+    pub fn new(api_key: String, base_url: String, default_model: String) -> Self {
+        Self {
+            client: Client::builder()
+                .timeout(std::time::Duration::from_secs(120))
+                .build()
+                .unwrap_or_else(|_| Client::new()),
+            api_key,
+            base_url,
+            default_model,
+        }
+    }
+
+    /// Text and `inlineData` parts for a message's content. Gemini only reads images it is
+    /// sent inline or from its own file store, so images given by URL are left out.
+    fn content_parts(content: &MessageContent) -> Vec<serde_json::Value> {
+        let parts = match content {
+            MessageContent::Text(text) => vec![ContentPart::text(text.as_str())],
+            MessageContent::Parts(parts) => parts.clone(),
+        };
+        parts
+            .into_iter()
+            .filter_map(|part| match part {
+                ContentPart::Text { text } if text.trim().is_empty() => None,
+                ContentPart::Text { text } => Some(json!({ "text": text })),
+                ContentPart::ImageUrl { image_url } => match image_url.as_base64() {
+                    Some((mime_type, data)) => Some(json!({ "inlineData": { "mimeType": mime_type, "data": data } })),
+                    None => {
+                        warn!("Gemini does not fetch image URLs, dropping {}", image_url.url);
+                        None
+                    }
+                },
+            })
+            .collect()
+    }
+
+    /// Builds the `generateContent` request body. Gemini takes the system prompt as a
+    /// `systemInstruction`, calls the assistant role `model`, and expresses tool traffic as
+    /// `functionCall` / `functionResponse` parts. Function responses are matched by name
+    /// rather than id, so tool results are resolved through the preceding assistant turn.
+    fn build_body(messages: &[Message], options: &ChatOptions) -> serde_json::Value {
+        let mut system = String::new();
+        let mut contents: Vec<(String, Vec<serde_json::Value>)> = Vec::new();
+        let mut call_names: HashMap<String, String> = HashMap::new();
+
+        for m in messages {
+            let (role, parts) = match m.role.as_str() {
+                "system" => {
+                    system.push_str(&m.content.text());
+                    system.push('\n');
+                    continue;
+                }
+                "tool" => {
+                    let id = m.tool_call_id.clone().unwrap_or_default();
+                    let name = call_names.get(&id).cloned().unwrap_or(id);
+                    // The response has to be an object; plain text results are wrapped
+                    let content = m.content.text();
+                    let response = serde_json::from_str::<serde_json::Value>(&content)
+                        .ok()
+                        .filter(|v| v.is_object())
+                        .unwrap_or_else(|| json!({ "content": content }));
+                    ("user", vec![json!({ "functionResponse": { "name": name, "response": response } })])
+                }
+                role => {
+                    let mut parts = Self::content_parts(&m.content);
+                    if role == "assistant" {
+                        for call in m.tool_calls.iter().flatten() {
+                            if let Some(id) = &call.id {
+                                call_names.insert(id.clone(), call.function.name.clone());
+                            }
+                            let args: serde_json::Value = serde_json::from_str(&call.function.arguments)
+                                .unwrap_or_else(|_| json!({}));
+                            parts.push(json!({ "functionCall": { "name": call.function.name, "args": args } }));
+                        }
+                    }
+                    (if role == "assistant" { "model" } else { "user" }, parts)
+                }
+            };
+
+            if parts.is_empty() {
+                continue;
+            }
+
+            match contents.last_mut() {
+                Some((last_role, last_parts)) if last_role == role => last_parts.extend(parts),
+                _ => contents.push((role.to_string(), parts)),
+            }
+        }
+
+        if let Some(opts_system) = &options.system_prompt {
+            system.push_str(opts_system);
+        }
+
+        let contents: Vec<serde_json::Value> = contents
+            .into_iter()
+            .map(|(role, parts)| json!({ "role": role, "parts": parts }))
+            .collect();
+
+        let mut generation_config = json!({ "temperature": options.temperature.unwrap_or(0.7) });
+        if let Some(max_tokens) = options.max_tokens {
+            generation_config["maxOutputTokens"] = json!(max_tokens);
+        }
+        let sampling = &options.sampling;
+        let fields = [
+            ("topP", sampling.top_p.map(|v| json!(v))),
+            ("topK", sampling.top_k.map(|v| json!(v))),
+            ("stopSequences", sampling.stop.as_ref().map(|v| json!(v))),
+            ("seed", sampling.seed.map(|v| json!(v))),
+            ("presencePenalty", sampling.presence_penalty.map(|v| json!(v))),
+            ("frequencyPenalty", sampling.frequency_penalty.map(|v| json!(v))),
+        ];
+        for (name, value) in fields {
+            if let Some(value) = value {
+                generation_config[name] = value;
+            }
+        }
+        if let Some(budget) = options.thinking_budget {
+            generation_config["thinkingConfig"] = json!({ "thinkingBudget": budget, "includeThoughts": true });
+        }
+
+        let mut body = json!({
+            "contents": contents,
+            "generationConfig": generation_config,
+        });
+
+        if !system.trim().is_empty() {
+            body["systemInstruction"] = json!({ "parts": [{ "text": system.trim() }] });
+        }
+
+        if let Some(tools) = options.tools.as_ref().filter(|t| !t.is_empty()) {
+            body["tools"] = json!([{
+                "functionDeclarations": tools.iter().map(Self::map_tool).collect::<Vec<_>>()
+            }]);
+            if let Some(config) = options.tool_choice.as_ref().and_then(Self::map_tool_choice) {
+                body["toolConfig"] = json!({ "functionCallingConfig": config });
+            }
+        }
+
+        body
+    }
+
+    fn map_tool(tool: &ToolDefinition) -> serde_json::Value {
+        json!({
+            "name": tool.function.name,
+            "description": tool.function.description,
+            "parameters": tool.function.parameters,
+        })
+    }
+
+    /// Translates an OpenAI `tool_choice` value into a Gemini `functionCallingConfig`.
+    fn map_tool_choice(choice: &serde_json::Value) -> Option<serde_json::Value> {
+        match choice {
+            serde_json::Value::String(s) => match s.as_str() {
+                "auto" => Some(json!({ "mode": "AUTO" })),
+                "required" => Some(json!({ "mode": "ANY" })),
+                "none" => Some(json!({ "mode": "NONE" })),
+                _ => None,
+            },
+            serde_json::Value::Object(_) => choice["function"]["name"]
+                .as_str()
+                .map(|name| json!({ "mode": "ANY", "allowedFunctionNames": [name] })),
+            _ => None,
+        }
+    }
+
+    /// Collects the text parts of the first candidate, its thought summaries and its
+    /// `functionCall` parts. Gemini does not always assign call ids, so missing ones are
+    /// derived from the call position.
+    fn parse_parts(json: &serde_json::Value, first_index: usize) -> (String, String, Vec<ToolCall>) {
+        let mut content = String::new();
+        let mut thinking = String::new();
+        let mut tool_calls = Vec::new();
+
+        let parts = json["candidates"][0]["content"]["parts"].as_array();
+        for part in parts.into_iter().flatten() {
+            if let Some(text) = part["text"].as_str() {
+                if part["thought"].as_bool() == Some(true) {
+                    thinking.push_str(text);
+                } else {
+                    content.push_str(text);
+                }
+            } else if let Some(call) = part.get("functionCall") {
+                let index = first_index + tool_calls.len();
+                tool_calls.push(ToolCall {
+                    id: Some(
+                        call["id"]
+                            .as_str()
+                            .map(|s| s.to_string())
+                            .unwrap_or_else(|| format!("call_{}", index)),
+                    ),
+                    r#type: Some("function".to_string()),
+                    function: FunctionCall {
+                        name: call["name"].as_str().unwrap_or_default().to_string(),
+                        arguments: if call["args"].is_object() {
+                            call["args"].to_string()
+                        } else {
+                            "{}".to_string()
+                        },
+                    },
+                });
+            }
+        }
+
+        (content, thinking, tool_calls)
+    }
+
+    fn parse_usage(json: &serde_json::Value) -> Option<Usage> {
+        let u = json.get("usageMetadata")?;
+        Some(Usage {
+            input_tokens: u["promptTokenCount"].as_u64().unwrap_or(0) as u32,
+            output_tokens: (u["candidatesTokenCount"].as_u64().unwrap_or(0)
+                + u["thoughtsTokenCount"].as_u64().unwrap_or(0)) as u32,
+            ..Default::default()
+        })
+    }
+
+    /// Gemini answers a blocked prompt with a 200 and no candidates.
+    fn blocked(&self, json: &serde_json::Value) -> Option<LlmError> {
+        let reason = json["promptFeedback"]["blockReason"].as_str()?;
+        Some(LlmError::Provider {
+            provider: self.name().to_string(),
+            status: 200,
+            kind: ErrorKind::ContentFilter,
+            message: format!("Gemini blocked the prompt: {}", reason),
+            retry_after: None,
+        })
+    }
+}
+
+#[async_trait]
+impl LlmProvider for GeminiProvider {
+    fn name(&self) -> &str {
+        "gemini"
+    }
+
+    async fn chat(&self, messages: &[Message], options: ChatOptions) -> Result<ChatResponse, LlmError> {
+        let model = options.model.as_deref().unwrap_or(&self.default_model);
+        options.sampling.check_supported(self.name(), GEMINI_SAMPLING)?;
+        let body = Self::build_body(messages, &options);
+
+        let response = self
+            .client
+            .post(format!("{}/models/{}:generateContent", self.base_url, model))
+            .header("x-goog-api-key", &self.api_key)
+            .json(&body)
+            .send()
+            .await
+            .map_err(LlmError::from)?;
+
+        if !response.status().is_success() {
+            return Err(LlmError::from_response(response, self.name(), "Gemini Error").await);
+        }
+
+        let json: serde_json::Value = response
+            .json()
+            .await
+            .map_err(LlmError::from)?;
+
+        if let Some(error) = self.blocked(&json) {
+            return Err(error);
+        }
+        // Not blocked and still empty, so the upstream reply itself is at fault
+        if !json["candidates"].is_array() {
+            return Err(LlmError::Provider {
+                provider: self.name().to_string(),
+                status: 200,
+                kind: ErrorKind::Server,
+                message: format!("Gemini returned no candidates: {}", json),
+                retry_after: None,
+            });
+        }
+
+        let (content, thinking, tool_calls) = Self::parse_parts(&json, 0);
+
+        Ok(ChatResponse {
+            content,
+            // The requested name, not `modelVersion`, is what the registry prices by
+            model: model.to_string(),
+            usage: Self::parse_usage(&json),
+            tool_calls: if tool_calls.is_empty() { None } else { Some(tool_calls) },
+            provider: None,
+            thinking: Some(thinking).filter(|t| !t.is_empty()),
+        })
+    }
+
+    async fn chat_streaming(
+        &self,
+        messages: &[Message],
+        options: ChatOptions,
+        tx: Sender<StreamEvent>,
+    ) -> Result<StreamResponse, LlmError> {
+        let model = options.model.as_deref().unwrap_or(&self.default_model);
+        options.sampling.check_supported(self.name(), GEMINI_SAMPLING)?;
+        let body = Self::build_body(messages, &options);
+
+        let response = self
+            .client
+            .post(format!("{}/models/{}:streamGenerateContent", self.base_url, model))
+            .query(&[("alt", "sse")])
+            .header("x-goog-api-key", &self.api_key)
+            .json(&body)
+            .send()
+            .await
+            .map_err(LlmError::from)?;
+
+        if !response.status().is_success() {
+            return Err(LlmError::from_response(response, self.name(), "Gemini Stream Error").await);
+        }
+
+        use futures_util::StreamExt;
+        let events = sse::events(response.bytes_stream());
+        futures_util::pin_mut!(events);
+
+        let mut tool_calls = Vec::new();
+        let mut usage = None;
+
+        while let Some(event) = events.next().await {
+            let event = event.map_err(LlmError::from)?;
+            let Ok(json) = serde_json::from_str::<serde_json::Value>(&event.data) else {
+                continue;
+            };
+
+            if let Some(error) = self.blocked(&json) {
+                return Err(error);
+            }
+
+            // Function calls arrive whole in a single chunk, only text is incremental
+            let (content, thinking, calls) = Self::parse_parts(&json, tool_calls.len());
+            tool_calls.extend(calls);
+            if !thinking.is_empty() && tx.send(StreamEvent::Thinking(thinking)).await.is_err() {
+                return Err(LlmError::Cancelled);
+            }
+            if !content.is_empty() && tx.send(StreamEvent::Text(content)).await.is_err() {
+                return Err(LlmError::Cancelled);
+            }
+
+            // Every chunk repeats the running totals, so the last one wins
+            if let Some(u) = Self::parse_usage(&json) {
+                usage = Some(u);
+            }
+        }
+
+        Ok(StreamResponse {
+            tool_calls: if tool_calls.is_empty() { None } else { Some(tool_calls) },
+            usage,
+            ..Default::default()
+        })
+    }
+
+    fn supported_models(&self) -> Vec<String> {
+        registry::bundled_models("gemini")
+    }
+
+    async fn discover_models(&self) -> Result<Vec<String>, LlmError> {
+        let response = self
+            .client
+            .get(format!("{}/models", self.base_url))
+            .query(&[("pageSize", "1000")])
+            .header("x-goog-api-key", &self.api_key)
+            .send()
+            .await
+            .map_err(LlmError::from)?;
+
+        if !response.status().is_success() {
+            return Ok(self.supported_models());
+        }
+
+        let json: serde_json::Value = response
+            .json()
+            .await
+            .map_err(LlmError::from)?;
+
+        // Embedding and other non-chat models do not support generateContent
+        let model_names: Vec<String> = json["models"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|m| {
+                m["supportedGenerationMethods"]
+                    .as_array()
+                    .is_some_and(|methods| methods.iter().any(|v| v == "generateContent"))
+            })
+            .filter_map(|m| m["name"].as_str())
+            .map(|name| name.strip_prefix("models/").unwrap_or(name).to_string())
+            .collect();
+
+        if model_names.is_empty() {
+            Ok(self.supported_models())
+        } else {
+            Ok(model_names)
+        }
+    }
+
+    async fn verify_connection(&self) -> Result<(), LlmError> {
+        let response = self
+            .client
+            .get(format!("{}/models", self.base_url))
+            .query(&[("pageSize", "1")])
+            .header("x-goog-api-key", &self.api_key)
+            .send()
+            .await
+            .map_err(LlmError::from)?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(LlmError::from_response(response, self.name(), "Gemini Error").await)
+        }
+    }
+
+    fn default_model(&self) -> String {
+        self.default_model.clone()
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
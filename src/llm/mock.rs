@@ -0,0 +1,268 @@
+//! A scripted provider for offline development and tests.
+//!
+//! Replies come from a YAML or JSON script whose rules are matched, in order, against the
+//! last user message:
+//!
+//! ```yaml
+//! rules:
+//!   - match: "(?i)weather in (\\w+)"
+//!     tool_calls:
+//!       - name: internet_search
+//!         arguments: { query: "weather" }
+//!   - match: "(?i)weather"
+//!     after_tool: true
+//!     content: "It is sunny."
+//!     chunks: ["It is ", "sunny."]
+//!     usage: { input_tokens: 12, output_tokens: 3 }
+//! fallback:
+//!   content: "I have no script for that."
+//! ```
+
+use async_trait::async_trait;
+use regex::Regex;
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+
+use crate::llm::{
+    models::{ChatOptions, ChatResponse, FunctionCall, Message, StreamEvent, StreamResponse, ToolCall, Usage},
+    LlmError, LlmProvider,
+};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MockScript {
+    #[serde(default)]
+    pub rules: Vec<MockRule>,
+    /// Used when no rule matches. Without one the provider echoes the user message.
+    #[serde(default)]
+    pub fallback: Option<MockReply>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MockRule {
+    /// Regex searched for in the last user message.
+    #[serde(rename = "match")]
+    pub pattern: String,
+    /// Whether the rule answers a tool result rather than the user message itself, which
+    /// lets a script drive a whole tool loop: call a tool first, then answer with its output.
+    #[serde(default)]
+    pub after_tool: bool,
+    #[serde(flatten)]
+    pub reply: MockReply,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MockReply {
+    #[serde(default)]
+    pub content: String,
+    /// How `content` is streamed. Defaults to one chunk per word.
+    #[serde(default)]
+    pub chunks: Option<Vec<String>>,
+    /// Reasoning returned apart from the answer, streamed as one event before `content`.
+    #[serde(default)]
+    pub thinking: Option<String>,
+    #[serde(default)]
+    pub tool_calls: Vec<MockToolCall>,
+    /// Defaults to word counts of the conversation and of the reply.
+    #[serde(default)]
+    pub usage: Option<Usage>,
+    /// Overrides the provider-wide latency for this reply.
+    #[serde(default)]
+    pub latency_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MockToolCall {
+    #[serde(default)]
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(default)]
+    pub arguments: serde_json::Value,
+}
+
+pub struct MockProvider {
+    rules: Vec<(Regex, MockRule)>,
+    fallback: Option<MockReply>,
+    default_model: String,
+    latency: Duration,
+    chunk_delay: Duration,
+}
+
+impl MockProvider {
+    pub fn new(script: MockScript, default_model: String) -> Result<Self, LlmError> {
+        let rules = script
+            .rules
+            .into_iter()
+            .map(|rule| match Regex::new(&rule.pattern) {
+                Ok(regex) => Ok((regex, rule)),
+                Err(e) => Err(LlmError::Api(format!(
+                    "Invalid mock rule pattern '{}': {}",
+                    rule.pattern, e
+                ))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            rules,
+            fallback: script.fallback,
+            default_model,
+            latency: Duration::ZERO,
+            chunk_delay: Duration::ZERO,
+        })
+    }
+
+    /// Loads the script from a `.yaml`, `.yml` or `.json` file.
+    pub fn from_file(path: &str, default_model: String) -> Result<Self, LlmError> {
+        let script = config::Config::builder()
+            .add_source(config::File::with_name(path))
+            .build()
+            .and_then(|c| c.try_deserialize::<MockScript>())
+            .map_err(|e| LlmError::Api(format!("Failed to load mock script '{}': {}", path, e)))?;
+        Self::new(script, default_model)
+    }
+
+    /// Waits `latency_ms` before every reply and `chunk_delay_ms` between streamed chunks.
+    pub fn with_latency(mut self, latency_ms: u64, chunk_delay_ms: u64) -> Self {
+        self.latency = Duration::from_millis(latency_ms);
+        self.chunk_delay = Duration::from_millis(chunk_delay_ms);
+        self
+    }
+
+    fn reply_for(&self, messages: &[Message]) -> MockReply {
+        let last_user = messages
+            .iter()
+            .rev()
+            .find(|m| m.role == "user")
+            .map(|m| m.content.text())
+            .unwrap_or_default();
+        let after_tool = messages.last().is_some_and(|m| m.role == "tool");
+
+        self.rules
+            .iter()
+            .find(|(regex, rule)| rule.after_tool == after_tool && regex.is_match(&last_user))
+            .map(|(_, rule)| rule.reply.clone())
+            .or_else(|| self.fallback.clone())
+            .unwrap_or_else(|| MockReply {
+                content: format!("Mock reply to: {}", last_user),
+                ..Default::default()
+            })
+    }
+
+    fn usage_for(messages: &[Message], reply: &MockReply) -> Usage {
+        reply.usage.clone().unwrap_or_else(|| Usage {
+            input_tokens: messages
+                .iter()
+                .map(|m| m.content.text().split_whitespace().count() as u32)
+                .sum(),
+            output_tokens: reply.content.split_whitespace().count() as u32,
+            ..Default::default()
+        })
+    }
+
+    fn tool_calls(reply: &MockReply) -> Option<Vec<ToolCall>> {
+        if reply.tool_calls.is_empty() {
+            return None;
+        }
+        Some(
+            reply
+                .tool_calls
+                .iter()
+                .enumerate()
+                .map(|(i, call)| ToolCall {
+                    id: Some(call.id.clone().unwrap_or_else(|| format!("call_mock_{}", i))),
+                    r#type: Some("function".to_string()),
+                    function: FunctionCall {
+                        name: call.name.clone(),
+                        arguments: if call.arguments.is_null() {
+                            "{}".to_string()
+                        } else {
+                            call.arguments.to_string()
+                        },
+                    },
+                })
+                .collect(),
+        )
+    }
+
+    async fn wait(&self, reply: &MockReply) {
+        let latency = reply.latency_ms.map(Duration::from_millis).unwrap_or(self.latency);
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for MockProvider {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    async fn chat(&self, messages: &[Message], options: ChatOptions) -> Result<ChatResponse, LlmError> {
+        let reply = self.reply_for(messages);
+        self.wait(&reply).await;
+
+        Ok(ChatResponse {
+            content: reply.content.clone(),
+            model: options.model.unwrap_or_else(|| self.default_model.clone()),
+            usage: Some(Self::usage_for(messages, &reply)),
+            tool_calls: Self::tool_calls(&reply),
+            provider: None,
+            thinking: reply.thinking.clone(),
+        })
+    }
+
+    async fn chat_streaming(
+        &self,
+        messages: &[Message],
+        _options: ChatOptions,
+        tx: Sender<StreamEvent>,
+    ) -> Result<StreamResponse, LlmError> {
+        let reply = self.reply_for(messages);
+        self.wait(&reply).await;
+
+        if let Some(thinking) = reply.thinking.clone() {
+            if tx.send(StreamEvent::Thinking(thinking)).await.is_err() {
+                return Err(LlmError::Cancelled);
+            }
+        }
+        let chunks = reply
+            .chunks
+            .clone()
+            .unwrap_or_else(|| reply.content.split_inclusive(' ').map(str::to_string).collect());
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            if i > 0 && !self.chunk_delay.is_zero() {
+                tokio::time::sleep(self.chunk_delay).await;
+            }
+            if tx.send(StreamEvent::Text(chunk)).await.is_err() {
+                return Err(LlmError::Cancelled);
+            }
+        }
+
+        Ok(StreamResponse {
+            tool_calls: Self::tool_calls(&reply),
+            usage: Some(Self::usage_for(messages, &reply)),
+            ..Default::default()
+        })
+    }
+
+    fn supported_models(&self) -> Vec<String> {
+        vec![self.default_model.clone()]
+    }
+
+    async fn discover_models(&self) -> Result<Vec<String>, LlmError> {
+        Ok(self.supported_models())
+    }
+
+    async fn verify_connection(&self) -> Result<(), LlmError> {
+        Ok(())
+    }
+
+    fn default_model(&self) -> String {
+        self.default_model.clone()
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
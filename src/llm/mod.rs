@@ -1,37 +1,44 @@
 pub mod anthropic;
+pub mod cassette;
+pub mod context;
 pub mod copilot;
+pub mod error;
+pub mod gemini;
+pub mod mock;
 pub mod stepbit_core;
 pub mod models;
 pub mod ollama;
 pub mod openai;
+pub mod registry;
+pub mod retry;
+pub mod schema;
+pub mod sse;
 
 use anthropic::AnthropicProvider;
+use cassette::RecordingProvider;
 use copilot::CopilotProvider;
+use gemini::GeminiProvider;
+use mock::MockProvider;
 use stepbit_core::StepbitCoreProvider;
 use ollama::OllamaProvider;
 use openai::OpenAiProvider;
+use registry::{ModelInfo, ModelRegistry};
+use retry::RetryingProvider;
 
 use async_trait::async_trait;
 use parking_lot::RwLock;
 use std::collections::HashMap;
 use std::sync::Arc;
-use thiserror::Error;
 use tokio::sync::mpsc::Sender;
+use tracing::warn;
 
-use crate::config::AppConfig;
-use models::{ChatOptions, ChatResponse, Message};
-
-#[derive(Debug, Error)]
-pub enum LlmError {
-    #[error("Network Error: {0}")]
-    Network(String),
-    #[error("API Error: {0}")]
-    Api(String),
-    #[error("Invalid Request")]
-    InvalidRequest,
-    #[error("Rate Limited")]
-    RateLimited,
-}
+use crate::config::{AppConfig, CassetteMode, PromptCachingConfig, ProviderKind, ProviderSpec};
+use crate::db::{credentials::{self, CredentialCipher}, service::DbService};
+pub use error::{ErrorKind, LlmError};
+use models::{ChatOptions, ChatResponse, Message, StreamEvent, StreamResponse};
+
+/// How many times a reply that does not match the requested `response_format` is asked for again.
+pub const MAX_FORMAT_RETRIES: usize = 2;
 
 #[async_trait]
 pub trait LlmProvider: Send + Sync {
@@ -43,12 +50,20 @@ pub trait LlmProvider: Send + Sync {
         options: ChatOptions,
     ) -> Result<ChatResponse, LlmError>;
 
+    /// Streams the reply to `tx` as answer text and, for models that reason natively,
+    /// thinking events ahead of the text.
     async fn chat_streaming(
         &self,
         messages: &[Message],
         options: ChatOptions,
-        tx: Sender<String>,
-    ) -> Result<Option<Vec<models::ToolCall>>, LlmError>;
+        tx: Sender<StreamEvent>,
+    ) -> Result<StreamResponse, LlmError>;
+
+    /// Embeds each input into a vector, in input order. `model` defaults to the provider's
+    /// own embedding model.
+    async fn embed(&self, _inputs: &[String], _model: Option<String>) -> Result<Vec<Vec<f32>>, LlmError> {
+        Err(LlmError::Unsupported(format!("{} does not support embeddings", self.name())))
+    }
 
     fn supported_models(&self) -> Vec<String>;
 
@@ -56,6 +71,11 @@ pub trait LlmProvider: Send + Sync {
         Ok(self.supported_models())
     }
 
+    /// Metadata the provider's API reports about `model`, for providers that expose any.
+    async fn describe_model(&self, _model: &str) -> Result<Option<ModelInfo>, LlmError> {
+        Ok(None)
+    }
+
     async fn verify_connection(&self) -> Result<(), LlmError> {
         Ok(())
     }
@@ -102,24 +122,85 @@ pub trait LlmProvider: Send + Sync {
 
 
 /// A manager that holds all available providers and handles dynamic switching.
+///
+/// Chat calls go to the provider named in `ChatOptions::provider` (the active one by
+/// default) first and, when it is unavailable, to each provider of the fallback chain in order.
+/// Providers can be added, replaced and removed while it serves requests.
 pub struct ProviderManager {
-    providers: HashMap<String, Arc<dyn LlmProvider>>,
+    providers: RwLock<HashMap<String, Arc<dyn LlmProvider>>>,
     active_provider_id: RwLock<String>,
     active_model_id: RwLock<Option<String>>,
+    fallback_ids: Vec<String>,
+    embedding_provider_id: Option<String>,
+    embedding_model: Option<String>,
+    models: ModelRegistry,
 }
 
 impl ProviderManager {
     pub fn new(providers: HashMap<String, Arc<dyn LlmProvider>>, default_id: String) -> Self {
         Self {
-            providers,
+            providers: RwLock::new(providers),
             active_provider_id: RwLock::new(default_id),
             active_model_id: RwLock::new(None),
+            fallback_ids: Vec::new(),
+            embedding_provider_id: None,
+            embedding_model: None,
+            models: ModelRegistry::new(),
+        }
+    }
+
+    /// Sets the ordered provider ids tried after the active one fails.
+    pub fn with_fallback(mut self, fallback_ids: Vec<String>) -> Self {
+        self.fallback_ids = fallback_ids;
+        self
+    }
+
+    /// Sends `embed` calls to `provider_id` with `model` as the default model, whichever
+    /// provider is active for chat. Without it embeddings go to the active provider.
+    pub fn with_embeddings(mut self, provider_id: String, model: Option<String>) -> Self {
+        self.embedding_provider_id = Some(provider_id);
+        self.embedding_model = model;
+        self
+    }
+
+    /// Model metadata from the config, overriding the bundled table and what providers report.
+    pub fn with_model_overrides(mut self, overrides: Vec<ModelInfo>) -> Self {
+        self.models = self.models.with_overrides(overrides);
+        self
+    }
+
+    pub fn model_registry(&self) -> &ModelRegistry {
+        &self.models
+    }
+
+    /// Registry entries for `models` of provider `id`, asking the provider to describe the
+    /// ones it has not described yet.
+    pub async fn describe_models(&self, id: &str, models: &[String]) -> Vec<ModelInfo> {
+        if let Some(provider) = self.get_provider(id) {
+            for model in models {
+                if self.models.is_discovered(id, model) {
+                    continue;
+                }
+                match provider.describe_model(model).await {
+                    Ok(Some(info)) => self.models.enrich(id, info),
+                    Ok(None) => {}
+                    Err(e) => warn!("Could not describe model '{}' of provider '{}': {}", model, id, e),
+                }
+            }
         }
+        models.iter().map(|model| self.models.get(id, model)).collect()
+    }
+
+    pub fn get_embedding_provider_id(&self) -> String {
+        self.embedding_provider_id
+            .clone()
+            .unwrap_or_else(|| self.get_active_provider_id())
     }
 
     pub fn set_active_provider(&self, id: &str) -> Result<(), String> {
-        if self.providers.contains_key(id) {
-            let mut active_id = self.active_provider_id.write();
+        // Locked before the providers, like everywhere else, so that it cannot be removed meanwhile
+        let mut active_id = self.active_provider_id.write();
+        if self.providers.read().contains_key(id) {
             *active_id = id.to_string();
             Ok(())
         } else {
@@ -141,20 +222,105 @@ impl ProviderManager {
     }
 
     pub fn list_providers(&self) -> Vec<String> {
-        self.providers.keys().cloned().collect()
+        self.providers.read().keys().cloned().collect()
     }
 
     pub fn get_provider(&self, id: &str) -> Option<Arc<dyn LlmProvider>> {
-        self.providers.get(id).cloned()
+        self.providers.read().get(id).cloned()
+    }
+
+    /// Adds `provider` under `id`, replacing the provider registered under it if any. Calls
+    /// already in flight finish on the provider they started with.
+    pub fn register_provider(&self, id: &str, provider: Arc<dyn LlmProvider>) {
+        self.providers.write().insert(id.to_string(), provider);
+        // What the previous provider reported may not hold for the new one
+        self.models.forget(id);
+    }
+
+    /// Removes provider `id`. The active provider cannot be removed, another one has to be
+    /// activated first.
+    pub fn remove_provider(&self, id: &str) -> Result<(), String> {
+        let active_id = self.active_provider_id.read();
+        if *active_id == id {
+            return Err(format!("Provider '{}' is active and cannot be removed", id));
+        }
+        match self.providers.write().remove(id) {
+            Some(_) => {
+                self.models.forget(id);
+                Ok(())
+            }
+            None => Err(format!("Provider '{}' not found in registry", id)),
+        }
     }
 
     fn get_active_provider(&self) -> Arc<dyn LlmProvider> {
         let id = self.active_provider_id.read();
         self.providers
+            .read()
             .get(&*id)
             .cloned()
             .expect("Active provider must exist")
     }
+
+    /// The provider asked for in `options` (or the active one when none or an unknown id
+    /// was given) followed by the registered fallbacks, without duplicates.
+    fn provider_chain(&self, options: &ChatOptions) -> Vec<(String, Arc<dyn LlmProvider>)> {
+        let primary = match &options.provider {
+            Some(id) if self.providers.read().contains_key(id) => id.clone(),
+            Some(id) => {
+                warn!("Provider '{}' is not registered, using the active provider", id);
+                self.get_active_provider_id()
+            }
+            None => self.get_active_provider_id(),
+        };
+
+        let mut ids = vec![primary];
+        for id in &self.fallback_ids {
+            if !ids.contains(id) {
+                ids.push(id.clone());
+            }
+        }
+        let providers = self.providers.read();
+        ids.into_iter()
+            .filter_map(|id| providers.get(&id).cloned().map(|p| (id, p)))
+            .collect()
+    }
+
+    /// The options sent to the `index`-th provider of the chain. Fallbacks answer with their
+    /// own default model since the requested one is unlikely to exist there. The global
+    /// model override only applies when the caller did not bind the call to a provider.
+    fn options_for(&self, index: usize, options: &ChatOptions) -> ChatOptions {
+        let mut options = options.clone();
+        if index > 0 {
+            options.model = None;
+        } else if options.provider.is_none() {
+            if let Some(model) = self.get_active_model_id() {
+                options.model = Some(model);
+            }
+        }
+        options
+    }
+
+    /// One chat call through the provider chain, falling back on retryable errors.
+    async fn chat_once(&self, messages: &[Message], options: ChatOptions) -> Result<ChatResponse, LlmError> {
+        let mut last_error = None;
+
+        for (index, (id, provider)) in self.provider_chain(&options).into_iter().enumerate() {
+            match provider.chat(messages, self.options_for(index, &options)).await {
+                Ok(mut response) => {
+                    response.provider = Some(id);
+                    return Ok(response);
+                }
+                Err(e) if e.is_retryable() => {
+                    warn!("Provider '{}' failed, trying the next fallback: {}", id, e);
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| LlmError::Api("No LLM provider available".to_string())))
+    }
 }
 
 #[async_trait]
@@ -165,29 +331,102 @@ impl LlmProvider for ProviderManager {
         "provider-manager"
     }
 
+    /// Asks again, up to [`MAX_FORMAT_RETRIES`] times, when a reply does not match the
+    /// requested `response_format`. Replies that call tools are returned as they are, the
+    /// format applies to the final answer.
     async fn chat(
         &self,
         messages: &[Message],
-        mut options: ChatOptions,
+        options: ChatOptions,
     ) -> Result<ChatResponse, LlmError> {
-        if let Some(model) = self.get_active_model_id() {
-            options.model = Some(model);
+        let Some(format) = options.response_format.clone() else {
+            return self.chat_once(messages, options).await;
+        };
+
+        let mut messages = messages.to_vec();
+        for attempt in 0..=MAX_FORMAT_RETRIES {
+            let response = self.chat_once(&messages, options.clone()).await?;
+            if response.tool_calls.as_ref().is_some_and(|calls| !calls.is_empty()) {
+                return Ok(response);
+            }
+            let reason = match format.validate(&response.content) {
+                Ok(()) => return Ok(response),
+                Err(reason) => reason,
+            };
+            if attempt == MAX_FORMAT_RETRIES {
+                return Err(LlmError::Api(format!(
+                    "Reply did not match the requested format after {} attempts: {}",
+                    MAX_FORMAT_RETRIES + 1,
+                    reason
+                )));
+            }
+
+            warn!("Reply did not match the requested format, asking again: {}", reason);
+            messages.push(Message {
+                role: "assistant".to_string(),
+                content: response.content.into(),
+                tool_calls: None,
+                tool_call_id: None,
+                thinking: None,
+            });
+            messages.push(Message {
+                role: "user".to_string(),
+                content: format!(
+                    "Your reply does not match the required format: {}. Reply again with only the JSON document, without any other text.",
+                    reason
+                )
+                .into(),
+                tool_calls: None,
+                tool_call_id: None,
+                thinking: None,
+            });
         }
-        self.get_active_provider().chat(messages, options).await
+        unreachable!("the last attempt always returns")
     }
 
     async fn chat_streaming(
         &self,
         messages: &[Message],
-        mut options: ChatOptions,
-        tx: Sender<String>,
-    ) -> Result<Option<Vec<models::ToolCall>>, LlmError> {
-        if let Some(model) = self.get_active_model_id() {
-            options.model = Some(model);
+        options: ChatOptions,
+        tx: Sender<StreamEvent>,
+    ) -> Result<StreamResponse, LlmError> {
+        let mut last_error = None;
+
+        for (index, (id, provider)) in self.provider_chain(&options).into_iter().enumerate() {
+            let options = self.options_for(index, &options);
+            let model = options.model.clone().unwrap_or_else(|| provider.default_model());
+
+            let (result, started) = stream_and_track(provider.as_ref(), messages, options, &tx).await;
+
+            match result {
+                Ok(mut response) => {
+                    response.provider = Some(id);
+                    response.model = Some(model);
+                    return Ok(response);
+                }
+                Err(e) if e.is_retryable() && !started => {
+                    warn!("Provider '{}' failed before streaming, trying the next fallback: {}", id, e);
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
         }
-        self.get_active_provider()
-            .chat_streaming(messages, options, tx)
-            .await
+
+        Err(last_error.unwrap_or_else(|| LlmError::Api("No LLM provider available".to_string())))
+    }
+
+    async fn embed(&self, inputs: &[String], model: Option<String>) -> Result<Vec<Vec<f32>>, LlmError> {
+        let id = self.get_embedding_provider_id();
+        let provider = self
+            .get_provider(&id)
+            .ok_or_else(|| LlmError::Api(format!("Embedding provider '{}' not found in registry", id)))?;
+        let model = model.or_else(|| {
+            self.embedding_provider_id
+                .is_some()
+                .then(|| self.embedding_model.clone())
+                .flatten()
+        });
+        provider.embed(inputs, model).await
     }
 
     fn supported_models(&self) -> Vec<String> {
@@ -198,6 +437,10 @@ impl LlmProvider for ProviderManager {
         self.get_active_provider().discover_models().await
     }
 
+    async fn describe_model(&self, model: &str) -> Result<Option<ModelInfo>, LlmError> {
+        self.get_active_provider().describe_model(model).await
+    }
+
     async fn verify_connection(&self) -> Result<(), LlmError> {
         self.get_active_provider().verify_connection().await
     }
@@ -249,6 +492,63 @@ impl LlmProvider for ProviderManager {
     }
 }
 
+/// Runs `chat_streaming` while relaying its events to `tx`, and reports whether any event
+/// reached the caller. Once one has, the call can no longer be retried or handed to another
+/// provider without garbling the reply.
+pub(crate) async fn stream_and_track(
+    provider: &dyn LlmProvider,
+    messages: &[Message],
+    options: ChatOptions,
+    tx: &Sender<StreamEvent>,
+) -> (Result<StreamResponse, LlmError>, bool) {
+    let (inner_tx, mut inner_rx) = tokio::sync::mpsc::channel(100);
+    let outer_tx = tx.clone();
+    let relay = async move {
+        let mut started = false;
+        while let Some(event) = inner_rx.recv().await {
+            started = true;
+            if outer_tx.send(event).await.is_err() {
+                break;
+            }
+        }
+        started
+    };
+
+    tokio::join!(provider.chat_streaming(messages, options, inner_tx), relay)
+}
+
+/// Binds a new session to the provider and model that are active right now, so that later
+/// switches of the global default do not affect it. Values already present in `metadata`
+/// are kept, and nothing is bound when `llm` is not a [`ProviderManager`].
+pub fn bind_session_defaults(llm: &dyn LlmProvider, metadata: &mut serde_json::Value) {
+    let Some(manager) = llm.as_any().downcast_ref::<ProviderManager>() else {
+        return;
+    };
+    if !metadata.is_object() {
+        *metadata = serde_json::json!({});
+    }
+
+    let provider_id = match metadata.get("provider").and_then(|v| v.as_str()) {
+        Some(id) => id.to_string(),
+        None => {
+            let id = manager.get_active_provider_id();
+            metadata["provider"] = serde_json::json!(id);
+            id
+        }
+    };
+    if metadata.get("model").and_then(|v| v.as_str()).is_none() {
+        let model = if provider_id == manager.get_active_provider_id() {
+            manager.get_active_model_id()
+        } else {
+            None
+        };
+        let model = model.or_else(|| manager.get_provider(&provider_id).map(|p| p.default_model()));
+        if let Some(model) = model {
+            metadata["model"] = serde_json::json!(model);
+        }
+    }
+}
+
 /// A registry or factory trait to initialize providers from config.
 pub struct ProviderFactory;
 
@@ -259,10 +559,13 @@ impl ProviderFactory {
         if let Some(cfg) = &config.llm.openai {
             providers.insert(
                 "openai".to_string(),
-                Arc::new(OpenAiProvider::new(
-                    cfg.api_key.clone(),
-                    cfg.api_base.clone(),
-                    cfg.default_model.clone(),
+                Arc::new(RetryingProvider::new(
+                    Arc::new(OpenAiProvider::new(
+                        cfg.api_key.clone(),
+                        cfg.api_base.clone(),
+                        cfg.default_model.clone(),
+                    )),
+                    cfg.retry.clone(),
                 )),
             );
         }
@@ -270,10 +573,16 @@ impl ProviderFactory {
         if let Some(cfg) = &config.llm.anthropic {
             providers.insert(
                 "anthropic".to_string(),
-                Arc::new(AnthropicProvider::new(
-                    cfg.api_key.clone(),
-                    cfg.api_base.clone(),
-                    cfg.default_model.clone(),
+                Arc::new(RetryingProvider::new(
+                    Arc::new(
+                        AnthropicProvider::new(
+                            cfg.api_key.clone(),
+                            cfg.api_base.clone(),
+                            cfg.default_model.clone(),
+                        )
+                        .with_prompt_caching(cfg.prompt_caching.clone()),
+                    ),
+                    cfg.retry.clone(),
                 )),
             );
         }
@@ -281,9 +590,12 @@ impl ProviderFactory {
         if let Some(cfg) = &config.llm.ollama {
             providers.insert(
                 "ollama".to_string(),
-                Arc::new(OllamaProvider::new(
-                    cfg.base_url.clone(),
-                    cfg.default_model.clone(),
+                Arc::new(RetryingProvider::new(
+                    Arc::new(OllamaProvider::new(
+                        cfg.base_url.clone(),
+                        cfg.default_model.clone(),
+                    )),
+                    cfg.retry.clone(),
                 )),
             );
         }
@@ -291,10 +603,30 @@ impl ProviderFactory {
         if let Some(cfg) = &config.llm.copilot {
             providers.insert(
                 "copilot".to_string(),
-                Arc::new(CopilotProvider::new(
-                    cfg.api_key.clone(),
-                    cfg.api_base.clone(),
-                    cfg.default_model.clone(),
+                Arc::new(RetryingProvider::new(
+                    Arc::new(
+                        CopilotProvider::new(
+                            cfg.oauth_token().unwrap_or_default(),
+                            cfg.api_base.clone(),
+                            cfg.default_model.clone(),
+                        )
+                        .with_token_url(&cfg.token_url),
+                    ),
+                    cfg.retry.clone(),
+                )),
+            );
+        }
+
+        if let Some(cfg) = &config.llm.gemini {
+            providers.insert(
+                "gemini".to_string(),
+                Arc::new(RetryingProvider::new(
+                    Arc::new(GeminiProvider::new(
+                        cfg.api_key.clone(),
+                        cfg.api_base.clone(),
+                        cfg.default_model.clone(),
+                    )),
+                    cfg.retry.clone(),
                 )),
             );
         }
@@ -302,21 +634,134 @@ impl ProviderFactory {
         if let Some(cfg) = &config.llm.stepbit_core {
             providers.insert(
                 "stepbit-core".to_string(),
-                Arc::new(StepbitCoreProvider::new(
-                    cfg.base_url.clone(),
-                    cfg.default_model.clone(),
-                    cfg.api_key.clone(),
+                Arc::new(RetryingProvider::new(
+                    Arc::new(StepbitCoreProvider::new(
+                        cfg.base_url.clone(),
+                        cfg.default_model.clone(),
+                        cfg.api_key.clone(),
+                    )),
+                    cfg.retry.clone(),
+                )),
+            );
+        }
+
+        if let Some(cfg) = &config.llm.mock {
+            match MockProvider::from_file(&cfg.script, cfg.default_model.clone()) {
+                Ok(provider) => {
+                    providers.insert(
+                        "mock".to_string(),
+                        Arc::new(provider.with_latency(cfg.latency_ms, cfg.chunk_delay_ms)),
+                    );
+                }
+                Err(e) => warn!("Mock provider is not available: {}", e),
+            }
+        }
+
+        for cfg in &config.llm.custom {
+            if providers.contains_key(&cfg.id) {
+                warn!("Custom provider '{}' clashes with an existing provider id and is ignored", cfg.id);
+                continue;
+            }
+            providers.insert(
+                cfg.id.clone(),
+                Arc::new(RetryingProvider::new(
+                    Arc::new(
+                        OpenAiProvider::new(
+                            cfg.api_key.clone(),
+                            cfg.api_base.clone(),
+                            cfg.default_model.clone(),
+                        )
+                        .named(&cfg.id)
+                        .with_headers(&cfg.headers),
+                    ),
+                    cfg.retry.clone(),
                 )),
             );
         }
 
         let default_id = config.llm.provider.clone();
-        Arc::new(ProviderManager::new(providers, default_id))
+        let mut manager = ProviderManager::new(providers, default_id)
+            .with_fallback(config.llm.fallback.clone())
+            .with_model_overrides(config.llm.models.clone());
+        if let Some(embedding) = &config.llm.embedding {
+            manager = manager.with_embeddings(embedding.provider.clone(), embedding.model.clone());
+        }
+        let manager: Arc<dyn LlmProvider> = Arc::new(manager);
+
+        match &config.llm.cassette {
+            Some(cassette) if cassette.mode == CassetteMode::Record => {
+                Arc::new(RecordingProvider::record(manager, &cassette.path))
+            }
+            Some(cassette) => match RecordingProvider::replay(&cassette.path) {
+                Ok(replay) => Arc::new(replay),
+                Err(e) => {
+                    warn!("Cassette replay is not available, using live providers: {}", e);
+                    manager
+                }
+            },
+            None => manager,
+        }
     }
 
     pub fn create_default(config: &AppConfig) -> Option<Arc<dyn LlmProvider>> {
         Some(Self::create_all(config))
     }
+
+    /// Builds provider `id` from a spec added through the API, with the same retry wrapping
+    /// as the providers from the config file.
+    pub fn create(id: &str, spec: &ProviderSpec) -> Result<Arc<dyn LlmProvider>, String> {
+        if !spec.headers.is_empty() && spec.kind != ProviderKind::OpenAi {
+            return Err("Custom headers are only supported by OpenAI-compatible providers".to_string());
+        }
+        let api_base = spec.api_base();
+        let api_key = spec.api_key.clone().unwrap_or_default();
+        let model = spec.default_model.clone();
+
+        let provider: Arc<dyn LlmProvider> = match spec.kind {
+            ProviderKind::OpenAi => Arc::new(
+                OpenAiProvider::new(api_key, api_base, model)
+                    .named(id)
+                    .with_headers(&spec.headers),
+            ),
+            ProviderKind::Anthropic => Arc::new(
+                AnthropicProvider::new(api_key, api_base, model)
+                    .with_prompt_caching(PromptCachingConfig::default()),
+            ),
+            ProviderKind::Ollama => Arc::new(OllamaProvider::new(api_base, model)),
+            ProviderKind::Gemini => Arc::new(GeminiProvider::new(api_key, api_base, model)),
+            ProviderKind::Copilot => Arc::new(CopilotProvider::new(api_key, api_base, model)),
+            ProviderKind::StepbitCore => Arc::new(StepbitCoreProvider::new(api_base, model, spec.api_key.clone())),
+        };
+        Ok(Arc::new(RetryingProvider::new(provider, spec.retry.clone())))
+    }
+
+    /// Registers the providers stored through the API with the manager behind `llm`, over
+    /// the config file's providers with the same id, and returns how many were registered.
+    /// Stored providers that cannot be built or decrypted are skipped.
+    pub fn register_stored(
+        llm: &dyn LlmProvider,
+        conn: &duckdb::Connection,
+        cipher: Option<&CredentialCipher>,
+    ) -> duckdb::Result<usize> {
+        let Some(manager) = llm.as_any().downcast_ref::<ProviderManager>() else {
+            return Ok(0);
+        };
+
+        let mut registered = 0;
+        for stored in DbService::list_providers(conn)? {
+            let provider = credentials::open_provider(&stored, cipher)
+                .map_err(|e| e.to_string())
+                .and_then(|spec| Self::create(&stored.id, &spec));
+            match provider {
+                Ok(provider) => {
+                    manager.register_provider(&stored.id, provider);
+                    registered += 1;
+                }
+                Err(e) => warn!("Stored provider '{}' is not available: {}", stored.id, e),
+            }
+        }
+        Ok(registered)
+    }
 }
 
 /// Helper method to extract a JSON tool call array from a raw text stream buffer.
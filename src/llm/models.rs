@@ -1,13 +1,143 @@
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use tracing::warn;
+
+use crate::llm::LlmError;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub role: String,
-    pub content: String,
+    pub content: MessageContent,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_calls: Option<Vec<ToolCall>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_call_id: Option<String>,
+    /// Signed reasoning that led to the assistant's `tool_calls`, handed back to the provider
+    /// for the rest of the tool loop. It is never serialized, so history read back from the
+    /// database goes without it.
+    #[serde(default, skip_serializing)]
+    pub thinking: Option<ThinkingBlock>,
+}
+
+/// A reasoning block as the provider returned it. Anthropic only takes reasoning back
+/// with the signature it was issued with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ThinkingBlock {
+    pub thinking: String,
+    pub signature: String,
+}
+
+/// What a message says: plain text, or a list of parts when it carries images.
+/// Serializes like OpenAI's `content`, as a string or an array of typed parts.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+/// An image given by URL, or inline as a `data:<media type>;base64,<data>` URL.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageUrl {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl MessageContent {
+    /// The text of the message, with the text parts of multimodal content joined by newlines.
+    pub fn text(&self) -> Cow<'_, str> {
+        match self {
+            MessageContent::Text(text) => Cow::Borrowed(text),
+            MessageContent::Parts(parts) => Cow::Owned(
+                parts
+                    .iter()
+                    .filter_map(|p| match p {
+                        ContentPart::Text { text } => Some(text.as_str()),
+                        ContentPart::ImageUrl { .. } => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+        }
+    }
+
+    pub fn images(&self) -> Vec<&ImageUrl> {
+        match self {
+            MessageContent::Text(_) => vec![],
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .filter_map(|p| match p {
+                    ContentPart::ImageUrl { image_url } => Some(image_url),
+                    ContentPart::Text { .. } => None,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn has_images(&self) -> bool {
+        !self.images().is_empty()
+    }
+}
+
+impl Default for MessageContent {
+    fn default() -> Self {
+        MessageContent::Text(String::new())
+    }
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        MessageContent::Text(text)
+    }
+}
+
+impl From<&str> for MessageContent {
+    fn from(text: &str) -> Self {
+        MessageContent::Text(text.to_string())
+    }
+}
+
+impl std::fmt::Display for MessageContent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text())
+    }
+}
+
+impl ContentPart {
+    pub fn text(text: impl Into<String>) -> Self {
+        ContentPart::Text { text: text.into() }
+    }
+
+    pub fn image_url(url: impl Into<String>) -> Self {
+        ContentPart::ImageUrl {
+            image_url: ImageUrl {
+                url: url.into(),
+                detail: None,
+            },
+        }
+    }
+
+    /// An inline image from base64 `data` of the given media type, e.g. `image/png`.
+    pub fn image_base64(media_type: &str, data: &str) -> Self {
+        Self::image_url(format!("data:{};base64,{}", media_type, data))
+    }
+}
+
+impl ImageUrl {
+    /// The media type and base64 data of an inline image, `None` for a remote URL.
+    pub fn as_base64(&self) -> Option<(&str, &str)> {
+        let rest = self.url.strip_prefix("data:")?;
+        let (media_type, data) = rest.split_once(";base64,")?;
+        Some((media_type, data))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -48,6 +178,191 @@ pub struct ChatOptions {
     pub tools: Option<Vec<ToolDefinition>>,
     pub tool_choice: Option<serde_json::Value>,
     pub user: Option<String>,
+    /// Id of the registered provider that should answer, e.g. the one bound to the
+    /// session. `None` uses the manager's active provider.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    /// Tokens the model may spend on native reasoning before answering. Providers and
+    /// models without a thinking mode ignore it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking_budget: Option<u32>,
+    #[serde(flatten)]
+    pub sampling: SamplingParams,
+}
+
+/// Sampling settings besides temperature and max_tokens, named as in OpenAI's API.
+/// Providers pass on the ones their API knows and reject the others.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SamplingParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    /// Accepts a single string as well as a list, like OpenAI does.
+    #[serde(default, skip_serializing_if = "Option::is_none", deserialize_with = "one_or_many")]
+    pub stop: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat_penalty: Option<f32>,
+}
+
+impl SamplingParams {
+    /// Names of the parameters that are set.
+    pub fn names(&self) -> Vec<&'static str> {
+        [
+            ("top_p", self.top_p.is_some()),
+            ("top_k", self.top_k.is_some()),
+            ("stop", self.stop.is_some()),
+            ("seed", self.seed.is_some()),
+            ("presence_penalty", self.presence_penalty.is_some()),
+            ("frequency_penalty", self.frequency_penalty.is_some()),
+            ("repeat_penalty", self.repeat_penalty.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(name, set)| set.then_some(name))
+        .collect()
+    }
+
+    /// Fails with [`LlmError::Unsupported`] naming every set parameter missing from `supported`.
+    pub fn check_supported(&self, provider: &str, supported: &[&str]) -> Result<(), LlmError> {
+        let unsupported: Vec<&str> = self.names().into_iter().filter(|n| !supported.contains(n)).collect();
+        if unsupported.is_empty() {
+            Ok(())
+        } else {
+            Err(LlmError::Unsupported(format!(
+                "{} does not support {}",
+                provider,
+                unsupported.join(", ")
+            )))
+        }
+    }
+
+    /// Reads the parameters set in a session's metadata one key at a time, so a malformed
+    /// value is skipped with a warning instead of discarding the valid ones next to it.
+    pub fn from_metadata(metadata: &serde_json::Value) -> Self {
+        metadata
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter_map(|(name, value)| {
+                // Keys that are not sampling parameters parse as empty params
+                match serde_json::from_value::<SamplingParams>(serde_json::json!({ name: value })) {
+                    Ok(params) => Some(params),
+                    Err(e) => {
+                        warn!("Ignoring session setting '{}': {}", name, e);
+                        None
+                    }
+                }
+            })
+            .fold(SamplingParams::default(), SamplingParams::or)
+    }
+
+    /// Fills the parameters that are not set from `other`.
+    pub fn or(self, other: SamplingParams) -> Self {
+        Self {
+            top_p: self.top_p.or(other.top_p),
+            top_k: self.top_k.or(other.top_k),
+            stop: self.stop.or(other.stop),
+            seed: self.seed.or(other.seed),
+            presence_penalty: self.presence_penalty.or(other.presence_penalty),
+            frequency_penalty: self.frequency_penalty.or(other.frequency_penalty),
+            repeat_penalty: self.repeat_penalty.or(other.repeat_penalty),
+        }
+    }
+}
+
+fn one_or_many<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<String>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(Option::<OneOrMany>::deserialize(deserializer)?.map(|stop| match stop {
+        OneOrMany::One(s) => vec![s],
+        OneOrMany::Many(v) => v,
+    }))
+}
+
+/// The shape the reply must take, in OpenAI's `response_format` form.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    JsonObject,
+    JsonSchema { json_schema: JsonSchemaFormat },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonSchemaFormat {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub schema: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+impl ResponseFormat {
+    pub fn json_schema(name: &str, schema: serde_json::Value) -> Self {
+        ResponseFormat::JsonSchema {
+            json_schema: JsonSchemaFormat {
+                name: name.to_string(),
+                description: None,
+                schema,
+                strict: None,
+            },
+        }
+    }
+
+    /// The schema replies must follow, any object for `json_object` and `None` for text.
+    pub fn schema(&self) -> Option<serde_json::Value> {
+        match self {
+            ResponseFormat::Text => None,
+            ResponseFormat::JsonObject => Some(serde_json::json!({ "type": "object" })),
+            ResponseFormat::JsonSchema { json_schema } => Some(json_schema.schema.clone()),
+        }
+    }
+
+    /// Checks that `content` is a JSON document of the requested shape.
+    pub fn validate(&self, content: &str) -> Result<(), String> {
+        let Some(schema) = self.schema() else {
+            return Ok(());
+        };
+        let value: serde_json::Value =
+            serde_json::from_str(content.trim()).map_err(|e| format!("the reply is not valid JSON ({})", e))?;
+        crate::llm::schema::validate(&value, &schema)
+    }
+}
+
+impl ChatOptions {
+    /// Applies the provider, model and sampling settings a session is bound to through its
+    /// metadata. Values already set on the options, e.g. chosen for a single request, take
+    /// precedence.
+    pub fn for_session(mut self, metadata: &serde_json::Value) -> Self {
+        let field = |name: &str| metadata.get(name).and_then(|v| v.as_str()).map(str::to_string);
+        if self.provider.is_none() {
+            self.provider = field("provider");
+        }
+        if self.model.is_none() {
+            self.model = field("model");
+        }
+        if self.temperature.is_none() {
+            self.temperature = metadata.get("temperature").and_then(|v| v.as_f64()).map(|t| t as f32);
+        }
+        if self.max_tokens.is_none() {
+            self.max_tokens = metadata.get("max_tokens").and_then(|v| v.as_u64()).map(|t| t as u32);
+        }
+        self.sampling = self.sampling.or(SamplingParams::from_metadata(metadata));
+        self
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -56,12 +371,61 @@ pub struct ChatResponse {
     pub model: String,
     pub usage: Option<Usage>,
     pub tool_calls: Option<Vec<ToolCall>>,
+    /// Id of the provider that answered, filled in by `ProviderManager`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    /// The model's reasoning, when the provider returns it apart from the answer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A piece of a streamed reply.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "content", rename_all = "snake_case")]
+pub enum StreamEvent {
+    /// Part of the answer.
+    Text(String),
+    /// Part of the model's reasoning, sent before the answer it leads to.
+    Thinking(String),
+}
+
+impl StreamEvent {
+    /// The answer text carried by the event, `None` for thinking.
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            StreamEvent::Text(text) => Some(text),
+            StreamEvent::Thinking(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Usage {
+    /// Input tokens billed at the normal price. Cached input is counted separately.
     pub input_tokens: u32,
     pub output_tokens: u32,
+    /// Input tokens read from the provider's prompt cache.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_read_tokens: Option<u32>,
+    /// Input tokens written to the provider's prompt cache.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_write_tokens: Option<u32>,
+}
+
+/// What a streamed completion resolves to once all its events have been sent.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct StreamResponse {
+    pub tool_calls: Option<Vec<ToolCall>>,
+    pub usage: Option<Usage>,
+    /// Id of the provider that answered, filled in by `ProviderManager`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    /// Model that produced the stream, filled in by `ProviderManager`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// The signed reasoning before `tool_calls`, to be sent back on the tool call message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<ThinkingBlock>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
@@ -1,10 +1,70 @@
 use async_trait::async_trait;
 use reqwest::Client;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use tokio::sync::mpsc::Sender;
+use tracing::warn;
+
+use crate::llm::{models::{ChatOptions, ChatResponse, Message, ResponseFormat, StreamEvent, StreamResponse, ToolCall, FunctionCall, Usage}, openai::apply_sampling, registry::ModelInfo, sse, LlmError, LlmProvider};
+
+/// Downloads can run far longer than a chat call.
+const PULL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(6 * 60 * 60);
+
+/// A model stored on the Ollama server, from `/api/tags`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct InstalledModel {
+    pub name: String,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub digest: Option<String>,
+    #[serde(default)]
+    pub modified_at: Option<String>,
+}
+
+/// A model loaded in memory, from `/api/ps`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RunningModel {
+    pub name: String,
+    #[serde(default)]
+    pub size: u64,
+    /// Bytes of the model held in GPU memory.
+    #[serde(default)]
+    pub size_vram: u64,
+    /// When the server unloads the model unless it is used again.
+    #[serde(default)]
+    pub expires_at: Option<String>,
+    #[serde(default)]
+    pub context_length: Option<u32>,
+}
+
+/// What `/api/show` reports about a model.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ModelDetails {
+    pub name: String,
+    /// The Modelfile `PARAMETER` lines, e.g. `stop "<|eot_id|>"`.
+    pub parameters: Option<String>,
+    pub template: Option<String>,
+    pub context_length: Option<u32>,
+    pub capabilities: Vec<String>,
+    pub family: Option<String>,
+    pub parameter_size: Option<String>,
+    pub quantization_level: Option<String>,
+}
 
-use crate::llm::{models::{ChatOptions, ChatResponse, Message, ToolCall, FunctionCall}, LlmError, LlmProvider};
+/// One progress line of `/api/pull`. Download steps carry the layer digest and byte counts.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PullProgress {
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed: Option<u64>,
+}
 
+#[derive(Clone)]
 pub struct OllamaProvider {
     client: Client,
     base_url: String,
@@ -22,6 +82,182 @@ impl OllamaProvider {
             default_model,
         }
     }
+
+    /// Ollama wants plain text `content` with images as a separate array of base64 strings.
+    /// It cannot fetch images itself, so images given by URL are left out.
+    fn wire_messages(messages: &[Message]) -> Vec<serde_json::Value> {
+        messages
+            .iter()
+            .map(|m| {
+                let mut value = serde_json::to_value(m).unwrap_or_default();
+                value["content"] = json!(m.content.text());
+                let images: Vec<&str> = m
+                    .content
+                    .images()
+                    .into_iter()
+                    .filter_map(|image| {
+                        let data = image.as_base64().map(|(_, data)| data);
+                        if data.is_none() {
+                            warn!("Ollama does not fetch image URLs, dropping {}", image.url);
+                        }
+                        data
+                    })
+                    .collect();
+                if !images.is_empty() {
+                    value["images"] = json!(images);
+                }
+                value
+            })
+            .collect()
+    }
+
+    /// Ollama's `format` is either `"json"` or the JSON schema itself.
+    fn map_format(options: &ChatOptions) -> Option<serde_json::Value> {
+        match options.response_format.as_ref()? {
+            ResponseFormat::Text => None,
+            ResponseFormat::JsonObject => Some(json!("json")),
+            ResponseFormat::JsonSchema { json_schema } => Some(json_schema.schema.clone()),
+        }
+    }
+
+    /// Keys of `/api/show`'s `model_info` are prefixed with the architecture, e.g. `llama.context_length`.
+    fn context_length(show: &serde_json::Value) -> Option<u32> {
+        show["model_info"]
+            .as_object()
+            .and_then(|info| info.iter().find(|(key, _)| key.ends_with(".context_length")))
+            .and_then(|(_, value)| value.as_u64())
+            .map(|length| length as u32)
+    }
+
+    /// Models stored on the server, with their size on disk.
+    pub async fn list_models(&self) -> Result<Vec<InstalledModel>, LlmError> {
+        let response = self
+            .client
+            .get(format!("{}/api/tags", self.base_url))
+            .send()
+            .await
+            .map_err(LlmError::from)?;
+
+        if !response.status().is_success() {
+            return Err(LlmError::from_response(response, self.name(), "Ollama List Error").await);
+        }
+
+        let json: serde_json::Value = response.json().await.map_err(LlmError::from)?;
+        Ok(serde_json::from_value(json["models"].clone()).unwrap_or_default())
+    }
+
+    /// Models currently loaded in memory.
+    pub async fn running_models(&self) -> Result<Vec<RunningModel>, LlmError> {
+        let response = self
+            .client
+            .get(format!("{}/api/ps", self.base_url))
+            .send()
+            .await
+            .map_err(LlmError::from)?;
+
+        if !response.status().is_success() {
+            return Err(LlmError::from_response(response, self.name(), "Ollama Running Models Error").await);
+        }
+
+        let json: serde_json::Value = response.json().await.map_err(LlmError::from)?;
+        Ok(serde_json::from_value(json["models"].clone()).unwrap_or_default())
+    }
+
+    pub async fn show_model(&self, model: &str) -> Result<ModelDetails, LlmError> {
+        let response = self
+            .client
+            .post(format!("{}/api/show", self.base_url))
+            .json(&json!({ "model": model }))
+            .send()
+            .await
+            .map_err(LlmError::from)?;
+
+        if !response.status().is_success() {
+            return Err(LlmError::from_response(response, self.name(), "Ollama Show Error").await);
+        }
+
+        let json: serde_json::Value = response.json().await.map_err(LlmError::from)?;
+        let text = |value: &serde_json::Value| value.as_str().map(str::to_string);
+
+        Ok(ModelDetails {
+            name: model.to_string(),
+            parameters: text(&json["parameters"]),
+            template: text(&json["template"]),
+            context_length: Self::context_length(&json),
+            capabilities: serde_json::from_value(json["capabilities"].clone()).unwrap_or_default(),
+            family: text(&json["details"]["family"]),
+            parameter_size: text(&json["details"]["parameter_size"]),
+            quantization_level: text(&json["details"]["quantization_level"]),
+        })
+    }
+
+    /// Downloads `model`, sending each progress line to `tx`. Ollama reports failures that
+    /// happen once the download started as an `error` line rather than an HTTP status.
+    pub async fn pull_model(&self, model: &str, tx: Sender<PullProgress>) -> Result<(), LlmError> {
+        let response = self
+            .client
+            .post(format!("{}/api/pull", self.base_url))
+            .timeout(PULL_TIMEOUT)
+            .json(&json!({ "model": model, "stream": true }))
+            .send()
+            .await
+            .map_err(LlmError::from)?;
+
+        if !response.status().is_success() {
+            return Err(LlmError::from_response(response, self.name(), "Ollama Pull Error").await);
+        }
+
+        use futures_util::StreamExt;
+        let lines = sse::lines(response.bytes_stream());
+        futures_util::pin_mut!(lines);
+
+        while let Some(line) = lines.next().await {
+            let line = line.map_err(LlmError::from)?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let json: serde_json::Value = match serde_json::from_str(&line) {
+                Ok(json) => json,
+                Err(_) => continue,
+            };
+            if let Some(error) = json["error"].as_str() {
+                return Err(LlmError::Api(format!("Ollama Pull Error: {}", error)));
+            }
+            if let Ok(progress) = serde_json::from_value::<PullProgress>(json) {
+                if tx.send(progress).await.is_err() {
+                    return Err(LlmError::Cancelled);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn delete_model(&self, model: &str) -> Result<(), LlmError> {
+        let response = self
+            .client
+            .delete(format!("{}/api/delete", self.base_url))
+            .json(&json!({ "model": model }))
+            .send()
+            .await
+            .map_err(LlmError::from)?;
+
+        if !response.status().is_success() {
+            return Err(LlmError::from_response(response, self.name(), "Ollama Delete Error").await);
+        }
+        Ok(())
+    }
+
+    /// Ollama reports token counts on the final (`done: true`) response object.
+    fn parse_usage(json: &serde_json::Value) -> Option<Usage> {
+        if json.get("prompt_eval_count").is_none() && json.get("eval_count").is_none() {
+            return None;
+        }
+        Some(Usage {
+            input_tokens: json["prompt_eval_count"].as_u64().unwrap_or(0) as u32,
+            output_tokens: json["eval_count"].as_u64().unwrap_or(0) as u32,
+            ..Default::default()
+        })
+    }
 }
 
 #[async_trait]
@@ -37,15 +273,16 @@ impl LlmProvider for OllamaProvider {
         if let Some(system) = &options.system_prompt {
             final_messages.insert(0, Message {
                 role: "system".to_string(),
-                content: system.clone(),
+                content: system.clone().into(),
                 tool_calls: None,
                 tool_call_id: None,
+                thinking: None,
             });
         }
 
         let mut body = json!({
             "model": model,
-            "messages": final_messages,
+            "messages": Self::wire_messages(&final_messages),
             "stream": false,
             "options": {
                 "temperature": options.temperature.unwrap_or(0.7),
@@ -56,6 +293,15 @@ impl LlmProvider for OllamaProvider {
         if let Some(tools) = &options.tools {
             body["tools"] = json!(tools);
         }
+        if let Some(format) = Self::map_format(&options) {
+            body["format"] = format;
+        }
+        // Thinking is on or off, there is no budget
+        if options.thinking_budget.is_some() {
+            body["think"] = json!(true);
+        }
+        // Ollama's model options use the same names and cover every sampling parameter
+        apply_sampling(&mut body["options"], &options.sampling);
 
         let response = self
             .client
@@ -63,18 +309,16 @@ impl LlmProvider for OllamaProvider {
             .json(&body)
             .send()
             .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+            .map_err(LlmError::from)?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let text = response.text().await.unwrap_or_default();
-            return Err(LlmError::Api(format!("Ollama Error {}: {}", status, text)));
+            return Err(LlmError::from_response(response, self.name(), "Ollama Error").await);
         }
 
         let json: serde_json::Value = response
             .json()
             .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+            .map_err(LlmError::from)?;
 
         let message = &json["message"];
         let mut content = message["content"]
@@ -139,8 +383,10 @@ impl LlmProvider for OllamaProvider {
         Ok(ChatResponse {
             content,
             model: model.to_string(),
-            usage: None,
+            usage: Self::parse_usage(&json),
             tool_calls,
+            provider: None,
+            thinking: message["thinking"].as_str().filter(|t| !t.is_empty()).map(str::to_string),
         })
     }
 
@@ -148,23 +394,24 @@ impl LlmProvider for OllamaProvider {
         &self,
         messages: &[Message],
         options: ChatOptions,
-        tx: Sender<String>,
-    ) -> Result<Option<Vec<ToolCall>>, LlmError> {
+        tx: Sender<StreamEvent>,
+    ) -> Result<StreamResponse, LlmError> {
         let model = options.model.as_deref().unwrap_or(&self.default_model);
 
         let mut final_messages: Vec<Message> = messages.to_vec();
         if let Some(system) = &options.system_prompt {
             final_messages.insert(0, Message {
                 role: "system".to_string(),
-                content: system.clone(),
+                content: system.clone().into(),
                 tool_calls: None,
                 tool_call_id: None,
+                thinking: None,
             });
         }
 
         let mut body = json!({
             "model": model,
-            "messages": final_messages,
+            "messages": Self::wire_messages(&final_messages),
             "stream": true,
             "options": {
                 "temperature": options.temperature.unwrap_or(0.7),
@@ -175,6 +422,14 @@ impl LlmProvider for OllamaProvider {
         if let Some(tools) = &options.tools {
             body["tools"] = json!(tools);
         }
+        if let Some(format) = Self::map_format(&options) {
+            body["format"] = format;
+        }
+        // Thinking is on or off, there is no budget
+        if options.thinking_budget.is_some() {
+            body["think"] = json!(true);
+        }
+        apply_sampling(&mut body["options"], &options.sampling);
 
         let response = self
             .client
@@ -182,54 +437,105 @@ impl LlmProvider for OllamaProvider {
             .json(&body)
             .send()
             .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+            .map_err(LlmError::from)?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let text = response.text().await.unwrap_or_default();
-            return Err(LlmError::Api(format!("Ollama Stream Error {}: {}", status, text)));
+            return Err(LlmError::from_response(response, self.name(), "Ollama Stream Error").await);
         }
 
-        let mut stream = response.bytes_stream();
         use futures_util::StreamExt;
-        
+        // Ollama streams newline-delimited JSON rather than SSE
+        let lines = sse::lines(response.bytes_stream());
+        futures_util::pin_mut!(lines);
+
         let mut full_text = String::new();
-        
-        while let Some(chunk) = stream.next().await {
-            let bytes = chunk.map_err(|e| LlmError::Network(e.to_string()))?;
-            if let Ok(text) = String::from_utf8(bytes.to_vec()) {
-                for line in text.lines() {
-                    if line.trim().is_empty() {
-                        continue;
+        let mut usage = None;
+
+        while let Some(line) = lines.next().await {
+            let line = line.map_err(LlmError::from)?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Ok(json) = serde_json::from_str::<serde_json::Value>(&line) {
+                if let Some(thinking) = json["message"]["thinking"].as_str().filter(|t| !t.is_empty()) {
+                    if tx.send(StreamEvent::Thinking(thinking.to_string())).await.is_err() {
+                        return Err(LlmError::Cancelled);
                     }
-                    if let Ok(json) = serde_json::from_str::<serde_json::Value>(line) {
-                        if let Some(content) = json["message"]["content"].as_str() {
-                            full_text.push_str(content);
-                            let _ = tx.send(content.to_string()).await;
-                        }
+                }
+                if let Some(content) = json["message"]["content"].as_str() {
+                    full_text.push_str(content);
+                    if tx.send(StreamEvent::Text(content.to_string())).await.is_err() {
+                        return Err(LlmError::Cancelled);
                     }
                 }
+                if json["done"].as_bool() == Some(true) {
+                    usage = Self::parse_usage(&json);
+                }
             }
         }
 
-        if let Some((tools, _)) = crate::llm::extract_streaming_tool_call(&full_text) {
-            return Ok(Some(tools));
-        }
+        let tool_calls = crate::llm::extract_streaming_tool_call(&full_text).map(|(tools, _)| tools);
 
-        Ok(None)
+        Ok(StreamResponse { tool_calls, usage, ..Default::default() })
     }
 
     fn supported_models(&self) -> Vec<String> {
         vec!["llama3.2", "mistral", "ministral-3:8b"].into_iter().map(|s| s.to_string()).collect()
     }
 
+    async fn embed(&self, inputs: &[String], model: Option<String>) -> Result<Vec<Vec<f32>>, LlmError> {
+        if inputs.is_empty() {
+            return Ok(vec![]);
+        }
+        let model = model.unwrap_or_else(|| "nomic-embed-text".to_string());
+
+        let response = self
+            .client
+            .post(format!("{}/api/embed", self.base_url))
+            .json(&json!({ "model": model, "input": inputs }))
+            .send()
+            .await
+            .map_err(LlmError::from)?;
+
+        if !response.status().is_success() {
+            return Err(LlmError::from_response(response, self.name(), "Ollama Embeddings Error").await);
+        }
+
+        let json: serde_json::Value = response
+            .json()
+            .await
+            .map_err(LlmError::from)?;
+
+        let vectors: Vec<Vec<f32>> = json["embeddings"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|e| {
+                e.as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|v| v.as_f64().map(|f| f as f32))
+                    .collect()
+            })
+            .collect();
+
+        if vectors.len() != inputs.len() {
+            return Err(LlmError::Api(format!(
+                "Expected {} embeddings but received {}",
+                inputs.len(),
+                vectors.len()
+            )));
+        }
+        Ok(vectors)
+    }
+
     async fn discover_models(&self) -> Result<Vec<String>, LlmError> {
         let response = self
             .client
             .get(format!("{}/api/tags", self.base_url))
             .send()
             .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+            .map_err(LlmError::from)?;
 
         if !response.status().is_success() {
             return Ok(self.supported_models());
@@ -238,7 +544,7 @@ impl LlmProvider for OllamaProvider {
         let json: serde_json::Value = response
             .json()
             .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+            .map_err(LlmError::from)?;
 
         if let Some(models) = json["models"].as_array() {
             let model_names: Vec<String> = models
@@ -253,13 +559,49 @@ impl LlmProvider for OllamaProvider {
         Ok(self.supported_models())
     }
 
+    /// Reads the context length and capabilities from `/api/show`. Local models cost nothing.
+    async fn describe_model(&self, model: &str) -> Result<Option<ModelInfo>, LlmError> {
+        let response = self
+            .client
+            .post(format!("{}/api/show", self.base_url))
+            .json(&json!({ "model": model }))
+            .send()
+            .await
+            .map_err(LlmError::from)?;
+
+        if !response.status().is_success() {
+            return Ok(None);
+        }
+
+        let json: serde_json::Value = response
+            .json()
+            .await
+            .map_err(LlmError::from)?;
+
+        let context_length = Self::context_length(&json);
+        // Older servers do not list capabilities
+        let capabilities = json["capabilities"].as_array();
+        let has = |name: &str| capabilities.map(|caps| caps.iter().any(|c| c == name));
+
+        Ok(Some(ModelInfo {
+            context_length,
+            supports_tools: has("tools"),
+            supports_vision: has("vision"),
+            supports_thinking: has("thinking"),
+            supports_streaming: Some(true),
+            input_price_per_million: Some(0.0),
+            output_price_per_million: Some(0.0),
+            ..ModelInfo::new("ollama", model)
+        }))
+    }
+
     async fn verify_connection(&self) -> Result<(), LlmError> {
         let response = self
             .client
             .get(format!("{}/api/tags", self.base_url))
             .send()
             .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+            .map_err(LlmError::from)?;
 
         if response.status().is_success() {
             Ok(())
@@ -1,12 +1,150 @@
 use async_trait::async_trait;
-use reqwest::Client;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, Method, RequestBuilder};
 use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
 use tokio::sync::mpsc::Sender;
+use tracing::warn;
 
-use crate::llm::{models::{ChatOptions, ChatResponse, Message, Usage, ToolCall}, LlmError, LlmProvider};
+use crate::llm::{models::{ChatOptions, ChatResponse, FunctionCall, Message, SamplingParams, StreamEvent, StreamResponse, Usage, ToolCall}, registry, sse, LlmError, LlmProvider};
+
+/// Assembles OpenAI `delta.tool_calls` fragments. Each fragment is keyed by `index`; the id and
+/// function name arrive on the first fragment and the arguments are split across the rest.
+#[derive(Default)]
+pub(crate) struct ToolCallDeltas {
+    calls: BTreeMap<u64, ToolCall>,
+}
+
+impl ToolCallDeltas {
+    pub(crate) fn push(&mut self, deltas: &serde_json::Value) {
+        for delta in deltas.as_array().into_iter().flatten() {
+            let index = delta["index"].as_u64().unwrap_or(0);
+            let call = self.calls.entry(index).or_insert_with(|| ToolCall {
+                id: None,
+                r#type: Some("function".to_string()),
+                function: FunctionCall {
+                    name: String::new(),
+                    arguments: String::new(),
+                },
+            });
+
+            if let Some(id) = delta["id"].as_str() {
+                call.id = Some(id.to_string());
+            }
+            if let Some(name) = delta["function"]["name"].as_str() {
+                if call.function.name.is_empty() {
+                    call.function.name = name.to_string();
+                }
+            }
+            if let Some(arguments) = delta["function"]["arguments"].as_str() {
+                call.function.arguments.push_str(arguments);
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> Option<Vec<ToolCall>> {
+        let calls: Vec<ToolCall> = self
+            .calls
+            .into_values()
+            .filter(|c| !c.function.name.is_empty())
+            .map(|mut c| {
+                if c.function.arguments.trim().is_empty() {
+                    c.function.arguments = "{}".to_string();
+                }
+                c
+            })
+            .collect();
+
+        if calls.is_empty() {
+            None
+        } else {
+            Some(calls)
+        }
+    }
+}
+
+/// The sampling parameters of OpenAI's chat completions API.
+pub(crate) const OPENAI_SAMPLING: &[&str] = &["top_p", "stop", "seed", "presence_penalty", "frequency_penalty"];
+
+/// Adds the sampling parameters that are set to an OpenAI-style request body. The field
+/// names of [`SamplingParams`] are OpenAI's, so they are copied as they are.
+pub(crate) fn apply_sampling(body: &mut serde_json::Value, sampling: &SamplingParams) {
+    if let Ok(serde_json::Value::Object(params)) = serde_json::to_value(sampling) {
+        for (name, value) in params {
+            body[name] = value;
+        }
+    }
+}
+
+/// OpenAI's reasoning models take an effort level rather than a token budget.
+pub(crate) fn reasoning_effort(budget: u32) -> &'static str {
+    match budget {
+        0..=2047 => "low",
+        2048..=8191 => "medium",
+        _ => "high",
+    }
+}
+
+/// Turns a request body into one for a reasoning model. Those reject `temperature` and
+/// `max_tokens`, and count their reasoning against `max_completion_tokens` instead.
+pub(crate) fn apply_reasoning(body: &mut serde_json::Value, options: &ChatOptions, budget: u32) {
+    body["reasoning_effort"] = json!(reasoning_effort(budget));
+    body["max_completion_tokens"] = json!(options.max_tokens.unwrap_or(4096) + budget);
+    if let Some(body) = body.as_object_mut() {
+        body.remove("max_tokens");
+        body.remove("temperature");
+    }
+}
+
+/// Reasoning text of a message or delta. OpenAI-compatible servers disagree on the field:
+/// DeepSeek and vLLM use `reasoning_content`, OpenRouter uses `reasoning`.
+pub(crate) fn reasoning_text(message: &serde_json::Value) -> Option<&str> {
+    message["reasoning_content"]
+        .as_str()
+        .or_else(|| message["reasoning"].as_str())
+        .filter(|text| !text.is_empty())
+}
+
+/// Token usage of an OpenAI-style completion, or of the final chunk of a stream that asked for it.
+pub(crate) fn parse_usage(json: &serde_json::Value) -> Option<Usage> {
+    let u = json.get("usage").filter(|u| u.is_object())?;
+    Some(Usage {
+        input_tokens: u["prompt_tokens"].as_u64().unwrap_or(0) as u32,
+        output_tokens: u["completion_tokens"].as_u64().unwrap_or(0) as u32,
+        ..Default::default()
+    })
+}
+
+/// Reads the vectors of an OpenAI-style `/embeddings` response, ordered by their `index`.
+pub(crate) fn parse_embeddings(json: &serde_json::Value, expected: usize) -> Result<Vec<Vec<f32>>, LlmError> {
+    let mut data: Vec<&serde_json::Value> = json["data"].as_array().into_iter().flatten().collect();
+    data.sort_by_key(|d| d["index"].as_u64().unwrap_or(0));
+
+    let vectors: Vec<Vec<f32>> = data
+        .iter()
+        .map(|d| {
+            d["embedding"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|v| v.as_f64().map(|f| f as f32))
+                .collect()
+        })
+        .collect();
+
+    if vectors.len() != expected {
+        return Err(LlmError::Api(format!(
+            "Expected {} embeddings but received {}",
+            expected,
+            vectors.len()
+        )));
+    }
+    Ok(vectors)
+}
 
 pub struct OpenAiProvider {
     client: Client,
+    name: String,
     api_key: String,
     base_url: String,
     default_model: String,
@@ -15,33 +153,87 @@ pub struct OpenAiProvider {
 impl OpenAiProvider {
     pub fn new(api_key: String, base_url: String, default_model: String) -> Self {
         Self {
-            client: Client::builder()
-                .timeout(std::time::Duration::from_secs(60))
-                .build()
-                .unwrap_or_else(|_| Client::new()),
+            client: Self::build_client(HeaderMap::new()),
+            name: "openai".to_string(),
             api_key,
             base_url,
             default_model,
         }
     }
+
+    /// Registers this instance under `name`, for OpenAI-compatible servers such as
+    /// llama.cpp, vLLM or LM Studio. Named instances list every model their server offers.
+    pub fn named(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Sends `headers` with every request, e.g. an organization or routing header.
+    /// Entries that are not valid HTTP headers are skipped.
+    pub fn with_headers(mut self, headers: &HashMap<String, String>) -> Self {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            match (HeaderName::try_from(name.as_str()), HeaderValue::try_from(value.as_str())) {
+                (Ok(name), Ok(value)) => {
+                    map.insert(name, value);
+                }
+                _ => warn!("Ignoring invalid header '{}' for provider '{}'", name, self.name),
+            }
+        }
+        self.client = Self::build_client(map);
+        self
+    }
+
+    fn build_client(headers: HeaderMap) -> Client {
+        Client::builder()
+            .timeout(std::time::Duration::from_secs(60))
+            .default_headers(headers)
+            .build()
+            .unwrap_or_else(|_| Client::new())
+    }
+
+    fn is_stock_openai(&self) -> bool {
+        self.name == "openai"
+    }
+
+    /// OpenAI itself has no top_k or repeat_penalty. Compatible servers (vLLM, llama.cpp,
+    /// LM Studio) generally do, so named instances pass everything on.
+    fn check_sampling(&self, sampling: &SamplingParams) -> Result<(), LlmError> {
+        if self.is_stock_openai() {
+            sampling.check_supported(self.name(), OPENAI_SAMPLING)?;
+        }
+        Ok(())
+    }
+
+    /// Local servers usually run without a key, so no Authorization header is sent then.
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request = self.client.request(method, format!("{}{}", self.base_url, path));
+        if self.api_key.is_empty() {
+            request
+        } else {
+            request.bearer_auth(&self.api_key)
+        }
+    }
 }
 
 #[async_trait]
 impl LlmProvider for OpenAiProvider {
     fn name(&self) -> &str {
-        "openai"
+        &self.name
     }
 
     async fn chat(&self, messages: &[Message], options: ChatOptions) -> Result<ChatResponse, LlmError> {
         let model = options.model.as_deref().unwrap_or(&self.default_model);
+        self.check_sampling(&options.sampling)?;
 
         let mut final_messages: Vec<Message> = messages.to_vec();
         if let Some(system) = &options.system_prompt {
             final_messages.insert(0, Message {
                 role: "system".to_string(),
-                content: system.clone(),
+                content: system.clone().into(),
                 tool_calls: None,
                 tool_call_id: None,
+                thinking: None,
             });
         }
 
@@ -58,30 +250,30 @@ impl LlmProvider for OpenAiProvider {
         if let Some(choice) = &options.tool_choice {
             body["tool_choice"] = json!(choice);
         }
+        if let Some(format) = &options.response_format {
+            body["response_format"] = json!(format);
+        }
+        if let Some(budget) = options.thinking_budget {
+            apply_reasoning(&mut body, &options, budget);
+        }
+        apply_sampling(&mut body, &options.sampling);
 
         let response = self
-            .client
-            .post(format!("{}/chat/completions", self.base_url))
-            .header("Authorization", format!("Bearer {}", self.api_key))
+            .request(Method::POST, "/chat/completions")
             .header("Content-Type", "application/json")
             .json(&body)
             .send()
             .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+            .map_err(LlmError::from)?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let text = response.text().await.unwrap_or_default();
-            if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
-                return Err(LlmError::RateLimited);
-            }
-            return Err(LlmError::Api(format!("OpenAI Error {}: {}", status, text)));
+            return Err(LlmError::from_response(response, self.name(), "OpenAI Error").await);
         }
 
         let json: serde_json::Value = response
             .json()
             .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+            .map_err(LlmError::from)?;
 
         let message = &json["choices"][0]["message"];
         let content = message["content"]
@@ -92,20 +284,15 @@ impl LlmProvider for OpenAiProvider {
         let tool_calls: Option<Vec<ToolCall>> = message.get("tool_calls")
             .and_then(|tc| serde_json::from_value(tc.clone()).ok());
 
-        let usage = if let Some(u) = json.get("usage") {
-            Some(Usage {
-                input_tokens: u["prompt_tokens"].as_u64().unwrap_or(0) as u32,
-                output_tokens: u["completion_tokens"].as_u64().unwrap_or(0) as u32,
-            })
-        } else {
-            None
-        };
+        let usage = parse_usage(&json);
 
         Ok(ChatResponse {
             content,
             model: model.to_string(),
             usage,
             tool_calls,
+            provider: None,
+            thinking: reasoning_text(message).map(str::to_string),
         })
     }
 
@@ -113,17 +300,19 @@ impl LlmProvider for OpenAiProvider {
         &self,
         messages: &[Message],
         options: ChatOptions,
-        tx: Sender<String>,
-    ) -> Result<Option<Vec<ToolCall>>, LlmError> {
+        tx: Sender<StreamEvent>,
+    ) -> Result<StreamResponse, LlmError> {
         let model = options.model.as_deref().unwrap_or(&self.default_model);
+        self.check_sampling(&options.sampling)?;
 
         let mut final_messages: Vec<Message> = messages.to_vec();
         if let Some(system) = &options.system_prompt {
             final_messages.insert(0, Message {
                 role: "system".to_string(),
-                content: system.clone(),
+                content: system.clone().into(),
                 tool_calls: None,
                 tool_call_id: None,
+                thinking: None,
             });
         }
 
@@ -131,6 +320,7 @@ impl LlmProvider for OpenAiProvider {
             "model": model,
             "messages": final_messages,
             "stream": true,
+            "stream_options": { "include_usage": true },
             "temperature": options.temperature.unwrap_or(0.7),
             "max_tokens": options.max_tokens.unwrap_or(4096),
         });
@@ -141,63 +331,111 @@ impl LlmProvider for OpenAiProvider {
         if let Some(choice) = &options.tool_choice {
             body["tool_choice"] = json!(choice);
         }
+        if let Some(format) = &options.response_format {
+            body["response_format"] = json!(format);
+        }
+        if let Some(budget) = options.thinking_budget {
+            apply_reasoning(&mut body, &options, budget);
+        }
+        apply_sampling(&mut body, &options.sampling);
 
         let response = self
-            .client
-            .post(format!("{}/chat/completions", self.base_url))
-            .header("Authorization", format!("Bearer {}", self.api_key))
+            .request(Method::POST, "/chat/completions")
             .header("Content-Type", "application/json")
             .json(&body)
             .send()
             .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+            .map_err(LlmError::from)?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let text = response.text().await.unwrap_or_default();
-            if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
-                return Err(LlmError::RateLimited);
-            }
-            return Err(LlmError::Api(format!("OpenAI Stream Error {}: {}", status, text)));
+            return Err(LlmError::from_response(response, self.name(), "OpenAI Stream Error").await);
         }
 
-        let mut stream = response.bytes_stream();
         use futures_util::StreamExt;
+        let events = sse::events(response.bytes_stream());
+        futures_util::pin_mut!(events);
+
+        let mut tool_deltas = ToolCallDeltas::default();
+        let mut usage = None;
 
-        while let Some(chunk) = stream.next().await {
-            let bytes = chunk.map_err(|e| LlmError::Network(e.to_string()))?;
-            if let Ok(text) = String::from_utf8(bytes.to_vec()) {
-                for line in text.lines() {
-                    let line = line.trim();
-                    if line.is_empty() || line == "data: [DONE]" {
-                        continue;
-                    }
-                    if let Some(data) = line.strip_prefix("data: ") {
-                        if let Ok(json) = serde_json::from_str::<serde_json::Value>(data) {
-                            if let Some(content) = json["choices"][0]["delta"]["content"].as_str() {
-                                let _ = tx.send(content.to_string()).await;
-                            }
-                        }
-                    }
+        while let Some(event) = events.next().await {
+            let event = event.map_err(LlmError::from)?;
+            if event.data == "[DONE]" {
+                break;
+            }
+            let Ok(json) = serde_json::from_str::<serde_json::Value>(&event.data) else {
+                continue;
+            };
+
+            let delta = &json["choices"][0]["delta"];
+            if let Some(thinking) = reasoning_text(delta) {
+                if tx.send(StreamEvent::Thinking(thinking.to_string())).await.is_err() {
+                    return Err(LlmError::Cancelled);
+                }
+            }
+            if let Some(content) = delta["content"].as_str() {
+                if tx.send(StreamEvent::Text(content.to_string())).await.is_err() {
+                    return Err(LlmError::Cancelled);
                 }
             }
+            tool_deltas.push(&delta["tool_calls"]);
+            // With include_usage the last chunk carries usage and no choices
+            if let Some(u) = parse_usage(&json) {
+                usage = Some(u);
+            }
         }
 
-        Ok(None)
+        Ok(StreamResponse {
+            tool_calls: tool_deltas.finish(),
+            usage,
+            ..Default::default()
+        })
     }
 
     fn supported_models(&self) -> Vec<String> {
-        vec!["gpt-4o", "gpt-4-turbo", "gpt-3.5-turbo"].into_iter().map(|s| s.to_string()).collect()
+        if !self.is_stock_openai() {
+            return vec![self.default_model.clone()];
+        }
+        registry::bundled_models("openai")
+    }
+
+    async fn embed(&self, inputs: &[String], model: Option<String>) -> Result<Vec<Vec<f32>>, LlmError> {
+        if inputs.is_empty() {
+            return Ok(vec![]);
+        }
+        // Named OpenAI-compatible servers have no standard embedding model name
+        let model = model.unwrap_or_else(|| {
+            if self.is_stock_openai() {
+                "text-embedding-3-small".to_string()
+            } else {
+                self.default_model.clone()
+            }
+        });
+
+        let response = self
+            .request(Method::POST, "/embeddings")
+            .json(&json!({ "model": model, "input": inputs }))
+            .send()
+            .await
+            .map_err(LlmError::from)?;
+
+        if !response.status().is_success() {
+            return Err(LlmError::from_response(response, self.name(), "OpenAI Embeddings Error").await);
+        }
+
+        let json: serde_json::Value = response
+            .json()
+            .await
+            .map_err(LlmError::from)?;
+        parse_embeddings(&json, inputs.len())
     }
 
     async fn discover_models(&self) -> Result<Vec<String>, LlmError> {
         let response = self
-            .client
-            .get(format!("{}/models", self.base_url))
-            .header("Authorization", format!("Bearer {}", self.api_key))
+            .request(Method::GET, "/models")
             .send()
             .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+            .map_err(LlmError::from)?;
 
         if !response.status().is_success() {
             return Ok(self.supported_models());
@@ -206,13 +444,13 @@ impl LlmProvider for OpenAiProvider {
         let json: serde_json::Value = response
             .json()
             .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+            .map_err(LlmError::from)?;
 
         if let Some(data) = json["data"].as_array() {
             let model_names: Vec<String> = data
                 .iter()
                 .filter_map(|m| m["id"].as_str().map(|s| s.to_string()))
-                .filter(|id| id.contains("gpt"))
+                .filter(|id| !self.is_stock_openai() || id.contains("gpt"))
                 .collect();
             if !model_names.is_empty() {
                 return Ok(model_names);
@@ -224,18 +462,16 @@ impl LlmProvider for OpenAiProvider {
 
     async fn verify_connection(&self) -> Result<(), LlmError> {
         let response = self
-            .client
-            .get(format!("{}/models", self.base_url))
-            .header("Authorization", format!("Bearer {}", self.api_key))
+            .request(Method::GET, "/models")
             .send()
             .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+            .map_err(LlmError::from)?;
 
         if response.status().is_success() {
             Ok(())
         } else {
             let text = response.text().await.unwrap_or_default();
-            Err(LlmError::Api(format!("OpenAI connection failed: {}", text)))
+            Err(LlmError::Api(format!("{} connection failed: {}", self.name, text)))
         }
     }
 
@@ -0,0 +1,259 @@
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::llm::{
+    models::{ChatOptions, Usage},
+    LlmProvider, ProviderManager,
+};
+
+/// What is known about a model. Every field besides the ids may be unknown.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModelInfo {
+    pub id: String,
+    /// Id of the provider serving the model, as registered in `ProviderManager`.
+    pub provider: String,
+    pub context_length: Option<u32>,
+    pub max_output_tokens: Option<u32>,
+    pub supports_tools: Option<bool>,
+    pub supports_vision: Option<bool>,
+    /// Whether the model can reason natively before answering, see `ChatOptions::thinking_budget`.
+    pub supports_thinking: Option<bool>,
+    pub supports_streaming: Option<bool>,
+    /// USD per million input tokens.
+    pub input_price_per_million: Option<f64>,
+    /// USD per million output tokens.
+    pub output_price_per_million: Option<f64>,
+    /// USD per million input tokens read from the prompt cache; the input price when unknown.
+    pub cache_read_price_per_million: Option<f64>,
+    /// USD per million input tokens written to the prompt cache; the input price when unknown.
+    pub cache_write_price_per_million: Option<f64>,
+}
+
+impl ModelInfo {
+    pub fn new(provider: &str, id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            provider: provider.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Fills the fields this one leaves unknown from `fallback`.
+    pub fn or(self, fallback: &ModelInfo) -> Self {
+        Self {
+            id: self.id,
+            provider: self.provider,
+            context_length: self.context_length.or(fallback.context_length),
+            max_output_tokens: self.max_output_tokens.or(fallback.max_output_tokens),
+            supports_tools: self.supports_tools.or(fallback.supports_tools),
+            supports_vision: self.supports_vision.or(fallback.supports_vision),
+            supports_thinking: self.supports_thinking.or(fallback.supports_thinking),
+            supports_streaming: self.supports_streaming.or(fallback.supports_streaming),
+            input_price_per_million: self.input_price_per_million.or(fallback.input_price_per_million),
+            output_price_per_million: self.output_price_per_million.or(fallback.output_price_per_million),
+            cache_read_price_per_million: self.cache_read_price_per_million.or(fallback.cache_read_price_per_million),
+            cache_write_price_per_million: self.cache_write_price_per_million.or(fallback.cache_write_price_per_million),
+        }
+    }
+
+    /// What `usage` cost in USD, when both prices are known.
+    pub fn cost(&self, usage: &Usage) -> Option<f64> {
+        let input_price = self.input_price_per_million?;
+        let input = input_price * usage.input_tokens as f64;
+        let output = self.output_price_per_million? * usage.output_tokens as f64;
+        let cache_read = self.cache_read_price_per_million.unwrap_or(input_price)
+            * usage.cache_read_tokens.unwrap_or(0) as f64;
+        let cache_write = self.cache_write_price_per_million.unwrap_or(input_price)
+            * usage.cache_write_tokens.unwrap_or(0) as f64;
+        Some((input + output + cache_read + cache_write) / 1_000_000.0)
+    }
+}
+
+/// The table shipped with the binary, see `model_table.json`.
+pub fn bundled() -> &'static [ModelInfo] {
+    static TABLE: OnceLock<Vec<ModelInfo>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        serde_json::from_str(include_str!("model_table.json")).expect("bundled model table is valid JSON")
+    })
+}
+
+/// Ids of the bundled models of `provider`, in table order.
+pub fn bundled_models(provider: &str) -> Vec<String> {
+    bundled()
+        .iter()
+        .filter(|info| info.provider == provider)
+        .map(|info| info.id.clone())
+        .collect()
+}
+
+/// What `llm` knows about `model` of `provider`: the registry of a `ProviderManager`, the
+/// bundled table for a lone provider.
+pub fn lookup(llm: &dyn LlmProvider, provider: &str, model: &str) -> ModelInfo {
+    match llm.as_any().downcast_ref::<ProviderManager>() {
+        Some(manager) => manager.model_registry().get(provider, model),
+        None => ModelRegistry::new().get(provider, model),
+    }
+}
+
+/// Provider id and model a call with `options` will most likely go to.
+pub fn target(llm: &dyn LlmProvider, options: &ChatOptions) -> (String, String) {
+    let manager = llm.as_any().downcast_ref::<ProviderManager>();
+    let provider = options
+        .provider
+        .clone()
+        .or_else(|| manager.map(|m| m.get_active_provider_id()))
+        .unwrap_or_else(|| llm.name().to_string());
+    let model = options
+        .model
+        .clone()
+        .or_else(|| manager.filter(|_| options.provider.is_none()).and_then(|m| m.get_active_model_id()))
+        .or_else(|| manager.and_then(|m| m.get_provider(&provider)).map(|p| p.default_model()))
+        .unwrap_or_else(|| llm.default_model());
+    (provider, model)
+}
+
+/// Like [`lookup`] for the [`target`] of `options`, asking the provider about the model
+/// first if it has not described it yet.
+pub async fn describe_target(llm: &dyn LlmProvider, options: &ChatOptions) -> ModelInfo {
+    let (provider, model) = target(llm, options);
+    if let Some(manager) = llm.as_any().downcast_ref::<ProviderManager>() {
+        let described = manager.describe_models(&provider, std::slice::from_ref(&model)).await;
+        return described.into_iter().next().unwrap_or_else(|| ModelInfo::new(&provider, &model));
+    }
+    let known = ModelRegistry::new().get(&provider, &model);
+    match llm.describe_model(&model).await {
+        Ok(Some(described)) => ModelInfo {
+            id: model,
+            provider,
+            ..described
+        }
+        .or(&known),
+        _ => known,
+    }
+}
+
+type Key = (String, String);
+
+fn key(provider: &str, model: &str) -> Key {
+    (provider.to_string(), model.to_string())
+}
+
+/// Model metadata by provider and model id. Entries from the config win over what providers
+/// report about their models, which wins over the bundled table.
+pub struct ModelRegistry {
+    bundled: HashMap<Key, ModelInfo>,
+    overrides: HashMap<Key, ModelInfo>,
+    discovered: RwLock<HashMap<Key, ModelInfo>>,
+}
+
+impl Default for ModelRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ModelRegistry {
+    pub fn new() -> Self {
+        Self {
+            bundled: bundled()
+                .iter()
+                .map(|info| (key(&info.provider, &info.id), info.clone()))
+                .collect(),
+            overrides: HashMap::new(),
+            discovered: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_overrides(mut self, overrides: Vec<ModelInfo>) -> Self {
+        for info in overrides {
+            self.overrides.insert(key(&info.provider, &info.id), info);
+        }
+        self
+    }
+
+    /// Everything known about `model`; unknown models get an entry with only the ids set.
+    pub fn get(&self, provider: &str, model: &str) -> ModelInfo {
+        let key = key(provider, model);
+        let mut info = ModelInfo::new(provider, model);
+        if let Some(configured) = self.overrides.get(&key) {
+            info = info.or(configured);
+        }
+        if let Some(discovered) = self.discovered.read().get(&key) {
+            info = info.or(discovered);
+        }
+        if let Some(bundled) = self.bundled.get(&key) {
+            info = info.or(bundled);
+        }
+        info
+    }
+
+    /// Records what `provider` reported about one of its models.
+    pub fn enrich(&self, provider: &str, mut info: ModelInfo) {
+        info.provider = provider.to_string();
+        self.discovered.write().insert(key(provider, &info.id), info);
+    }
+
+    pub fn is_discovered(&self, provider: &str, model: &str) -> bool {
+        self.discovered.read().contains_key(&key(provider, model))
+    }
+
+    /// Drops what `provider` reported about its models, so they are described again.
+    pub fn forget(&self, provider: &str) {
+        self.discovered.write().retain(|(id, _), _| id != provider);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundled_table_parses() {
+        assert!(bundled_models("anthropic").contains(&"claude-3-5-sonnet-20241022".to_string()));
+        assert!(bundled().iter().all(|info| !info.id.is_empty() && !info.provider.is_empty()));
+    }
+
+    #[test]
+    fn test_config_wins_over_discovered_over_bundled() {
+        let registry = ModelRegistry::new().with_overrides(vec![ModelInfo {
+            input_price_per_million: Some(1.0),
+            ..ModelInfo::new("openai", "gpt-4o")
+        }]);
+        registry.enrich(
+            "openai",
+            ModelInfo {
+                context_length: Some(64000),
+                input_price_per_million: Some(2.0),
+                ..ModelInfo::new("", "gpt-4o")
+            },
+        );
+
+        let info = registry.get("openai", "gpt-4o");
+        assert_eq!(info.input_price_per_million, Some(1.0));
+        assert_eq!(info.context_length, Some(64000));
+        assert_eq!(info.output_price_per_million, Some(10.0));
+        assert_eq!(info.provider, "openai");
+
+        assert_eq!(registry.get("ollama", "llama3.2"), ModelInfo::new("ollama", "llama3.2"));
+    }
+
+    #[test]
+    fn test_cost_needs_both_prices() {
+        let usage = Usage {
+            input_tokens: 1_000_000,
+            output_tokens: 500_000,
+            ..Default::default()
+        };
+        let info = ModelRegistry::new().get("openai", "gpt-4o");
+        assert_eq!(info.cost(&usage), Some(2.5 + 5.0));
+
+        let unpriced = ModelInfo {
+            input_price_per_million: Some(1.0),
+            ..ModelInfo::new("custom", "model")
+        };
+        assert_eq!(unpriced.cost(&usage), None);
+    }
+}
@@ -0,0 +1,346 @@
+//! Retries transient provider failures with exponential backoff.
+
+use async_trait::async_trait;
+use reqwest::header::HeaderMap;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::Sender;
+use tracing::warn;
+
+use crate::config::RetryConfig;
+use crate::llm::{
+    models::{
+        ChatOptions, ChatResponse, McpToolDefinition, Message, PipelineExecuteResult,
+        ReasoningGraph, StreamEvent, StreamResponse, ToolDefinition,
+    },
+    registry::ModelInfo,
+    stream_and_track, LlmError, LlmProvider,
+};
+
+/// Wraps a provider so that `chat`, `embed`, `discover_models` and the part of `chat_streaming`
+/// before the first chunk are retried when they fail with a transient error.
+pub struct RetryingProvider {
+    inner: Arc<dyn LlmProvider>,
+    policy: RetryConfig,
+}
+
+impl RetryingProvider {
+    pub fn new(inner: Arc<dyn LlmProvider>, policy: RetryConfig) -> Self {
+        Self { inner, policy }
+    }
+
+    /// How long to wait after failed attempt number `attempt`, or `None` to give up.
+    fn delay_for(&self, attempt: u32, error: &LlmError) -> Option<Duration> {
+        if attempt >= self.policy.max_attempts || !error.is_retryable() {
+            return None;
+        }
+
+        let max_delay = Duration::from_millis(self.policy.max_delay_ms);
+        if let Some(hint) = error.retry_after() {
+            // Waiting less than asked would only burn an attempt
+            return (hint <= max_delay).then_some(hint);
+        }
+
+        let backoff = self
+            .policy
+            .base_delay_ms
+            .saturating_mul(1u64 << (attempt - 1).min(20));
+        Some(jittered(Duration::from_millis(backoff).min(max_delay), self.policy.jitter))
+    }
+
+    async fn with_retries<T, F, Fut>(&self, operation: &str, mut call: F) -> Result<T, LlmError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, LlmError>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let error = match call().await {
+                Ok(value) => return Ok(value),
+                Err(e) => e,
+            };
+            let Some(delay) = self.delay_for(attempt, &error) else {
+                return Err(error);
+            };
+            warn!(
+                "{} {} failed (attempt {}/{}), retrying in {:?}: {}",
+                self.inner.name(),
+                operation,
+                attempt,
+                self.policy.max_attempts,
+                delay,
+                error
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for RetryingProvider {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    async fn chat(
+        &self,
+        messages: &[Message],
+        options: ChatOptions,
+    ) -> Result<ChatResponse, LlmError> {
+        self.with_retries("chat", || self.inner.chat(messages, options.clone()))
+            .await
+    }
+
+    async fn chat_streaming(
+        &self,
+        messages: &[Message],
+        options: ChatOptions,
+        tx: Sender<StreamEvent>,
+    ) -> Result<StreamResponse, LlmError> {
+        let mut attempt = 1;
+        loop {
+            let (result, started) =
+                stream_and_track(self.inner.as_ref(), messages, options.clone(), &tx).await;
+            let error = match result {
+                Err(e) if !started => e,
+                // Once output reached the caller a retry would repeat it
+                result => return result,
+            };
+            let Some(delay) = self.delay_for(attempt, &error) else {
+                return Err(error);
+            };
+            warn!(
+                "{} chat_streaming failed before the first event (attempt {}/{}), retrying in {:?}: {}",
+                self.inner.name(),
+                attempt,
+                self.policy.max_attempts,
+                delay,
+                error
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    fn supported_models(&self) -> Vec<String> {
+        self.inner.supported_models()
+    }
+
+    async fn embed(&self, inputs: &[String], model: Option<String>) -> Result<Vec<Vec<f32>>, LlmError> {
+        self.with_retries("embed", || self.inner.embed(inputs, model.clone()))
+            .await
+    }
+
+    async fn discover_models(&self) -> Result<Vec<String>, LlmError> {
+        self.with_retries("discover_models", || self.inner.discover_models())
+            .await
+    }
+
+    async fn describe_model(&self, model: &str) -> Result<Option<ModelInfo>, LlmError> {
+        self.inner.describe_model(model).await
+    }
+
+    async fn verify_connection(&self) -> Result<(), LlmError> {
+        self.inner.verify_connection().await
+    }
+
+    async fn cancel(&self, session_id: &str) -> Result<(), LlmError> {
+        self.inner.cancel(session_id).await
+    }
+
+    fn default_model(&self) -> String {
+        self.inner.default_model()
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        // Callers downcast to the concrete provider, not to this wrapper
+        self.inner.as_any()
+    }
+
+    fn tools(&self) -> Vec<ToolDefinition> {
+        self.inner.tools()
+    }
+
+    async fn get_mcp_tools(&self) -> Result<Vec<McpToolDefinition>, LlmError> {
+        self.inner.get_mcp_tools().await
+    }
+
+    async fn execute_reasoning(
+        &self,
+        graph: ReasoningGraph,
+    ) -> Result<HashMap<String, serde_json::Value>, LlmError> {
+        self.inner.execute_reasoning(graph).await
+    }
+
+    async fn execute_reasoning_streaming(
+        &self,
+        graph: ReasoningGraph,
+        tx: Sender<serde_json::Value>,
+    ) -> Result<(), LlmError> {
+        self.inner.execute_reasoning_streaming(graph, tx).await
+    }
+
+    async fn execute_pipeline(
+        &self,
+        pipeline: serde_json::Value,
+        question: String,
+    ) -> Result<PipelineExecuteResult, LlmError> {
+        self.inner.execute_pipeline(pipeline, question).await
+    }
+}
+
+/// Spreads `delay` by up to `jitter` (a fraction of it) in either direction.
+fn jittered(delay: Duration, jitter: f64) -> Duration {
+    let jitter = jitter.clamp(0.0, 1.0);
+    // A fresh RandomState is randomly keyed, which is plenty to desynchronise clients
+    let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+    delay.mul_f64(1.0 - jitter + 2.0 * jitter * random)
+}
+
+/// Reads how long the server asked us to wait from `Retry-After` (seconds or an HTTP date)
+/// or the `x-ratelimit-reset` family (Unix time, seconds, or durations such as `6m0s`).
+pub(crate) fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+    };
+
+    if let Some(value) = header("retry-after") {
+        if let Ok(seconds) = value.parse::<f64>() {
+            return Duration::try_from_secs_f64(seconds).ok();
+        }
+        if let Ok(date) = chrono::DateTime::parse_from_rfc2822(value) {
+            return Some(until(date.timestamp()));
+        }
+    }
+
+    if let Some(value) = header("x-ratelimit-reset") {
+        if let Ok(number) = value.parse::<f64>() {
+            // Large values are a Unix timestamp (GitHub), small ones a delay in seconds
+            return if number > 1e9 {
+                Some(until(number as i64))
+            } else {
+                Duration::try_from_secs_f64(number).ok()
+            };
+        }
+        if let Some(duration) = parse_duration(value) {
+            return Some(duration);
+        }
+    }
+
+    ["x-ratelimit-reset-requests", "x-ratelimit-reset-tokens"]
+        .into_iter()
+        .filter_map(|name| header(name).and_then(parse_duration))
+        .max()
+}
+
+fn until(timestamp: i64) -> Duration {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    Duration::from_secs((timestamp - now).max(0) as u64)
+}
+
+/// Parses Go-style durations as used by OpenAI's rate limit headers, e.g. `1s`, `6m0s`, `20ms`.
+fn parse_duration(value: &str) -> Option<Duration> {
+    if value.is_empty() {
+        return None;
+    }
+
+    let mut total = 0.0;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let number_end = rest.find(|c: char| !(c.is_ascii_digit() || c == '.'))?;
+        let number: f64 = rest[..number_end].parse().ok()?;
+        rest = &rest[number_end..];
+
+        let unit_end = rest.find(|c: char| c.is_ascii_digit()).unwrap_or(rest.len());
+        let scale = match &rest[..unit_end] {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            "ms" => 0.001,
+            _ => return None,
+        };
+        total += number * scale;
+        rest = &rest[unit_end..];
+    }
+    Duration::try_from_secs_f64(total).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+        map
+    }
+
+    #[test]
+    fn test_retry_after_header_forms() {
+        assert_eq!(retry_after(&headers(&[("retry-after", "3")])), Some(Duration::from_secs(3)));
+        assert_eq!(
+            retry_after(&headers(&[("retry-after", "Wed, 21 Oct 2015 07:28:00 GMT")])),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(
+            retry_after(&headers(&[("x-ratelimit-reset", "1.5")])),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(
+            retry_after(&headers(&[
+                ("x-ratelimit-reset-requests", "20ms"),
+                ("x-ratelimit-reset-tokens", "6m0s"),
+            ])),
+            Some(Duration::from_secs(360))
+        );
+        assert_eq!(retry_after(&headers(&[("retry-after", "soon")])), None);
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn test_delay_backs_off_and_respects_limits() {
+        let provider = RetryingProvider::new(
+            Arc::new(crate::llm::ollama::OllamaProvider::new(
+                "http://localhost:0".to_string(),
+                "llama3.2".to_string(),
+            )),
+            RetryConfig {
+                max_attempts: 4,
+                base_delay_ms: 100,
+                max_delay_ms: 250,
+                jitter: 0.0,
+            },
+        );
+        let network = LlmError::Network("reset".to_string());
+
+        assert_eq!(provider.delay_for(1, &network), Some(Duration::from_millis(100)));
+        assert_eq!(provider.delay_for(2, &network), Some(Duration::from_millis(200)));
+        assert_eq!(provider.delay_for(3, &network), Some(Duration::from_millis(250)));
+        assert_eq!(provider.delay_for(4, &network), None);
+        assert_eq!(provider.delay_for(1, &LlmError::Api("bad".to_string())), None);
+
+        let rate_limited = |millis| LlmError::Provider {
+            provider: "openai".to_string(),
+            status: 429,
+            kind: crate::llm::ErrorKind::RateLimited,
+            message: "slow down".to_string(),
+            retry_after: Some(Duration::from_millis(millis)),
+        };
+        assert_eq!(provider.delay_for(1, &rate_limited(50)), Some(Duration::from_millis(50)));
+        assert_eq!(provider.delay_for(1, &rate_limited(1000)), None);
+    }
+}
@@ -0,0 +1,159 @@
+//! A small JSON Schema validator for checking structured output. It covers the keywords
+//! response schemas use in practice: `type`, `enum`, `const`, `anyOf`/`oneOf`, `properties`,
+//! `required`, `additionalProperties`, `items` and the length and range bounds. Other
+//! keywords, `$ref` included, are ignored rather than rejected.
+
+use serde_json::Value;
+
+/// Checks `value` against `schema`, describing the first mismatch found.
+pub fn validate(value: &Value, schema: &Value) -> Result<(), String> {
+    check(value, schema, "$")
+}
+
+fn check(value: &Value, schema: &Value, path: &str) -> Result<(), String> {
+    // `true` and `{}` accept anything
+    let Some(schema) = schema.as_object() else {
+        return Ok(());
+    };
+
+    if let Some(types) = schema.get("type") {
+        let matches = match types {
+            Value::String(t) => type_matches(value, t),
+            Value::Array(ts) => ts.iter().filter_map(Value::as_str).any(|t| type_matches(value, t)),
+            _ => true,
+        };
+        if !matches {
+            return Err(format!("{} should be of type {}", path, types));
+        }
+    }
+    if let Some(Value::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(value) {
+            return Err(format!("{} should be one of {}", path, Value::Array(allowed.clone())));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if value != expected {
+            return Err(format!("{} should be {}", path, expected));
+        }
+    }
+    if let Some(Value::Array(options)) = schema.get("anyOf").or_else(|| schema.get("oneOf")) {
+        if !options.iter().any(|option| check(value, option, path).is_ok()) {
+            return Err(format!("{} does not match any of the allowed schemas", path));
+        }
+    }
+
+    match value {
+        Value::Object(map) => {
+            let required = schema.get("required").and_then(Value::as_array);
+            for name in required.into_iter().flatten().filter_map(Value::as_str) {
+                if !map.contains_key(name) {
+                    return Err(format!("{} is missing required property '{}'", path, name));
+                }
+            }
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for (key, item) in map {
+                let item_path = format!("{}.{}", path, key);
+                match (properties.and_then(|p| p.get(key)), schema.get("additionalProperties")) {
+                    (Some(property), _) => check(item, property, &item_path)?,
+                    (None, Some(Value::Bool(false))) => {
+                        return Err(format!("{} has unexpected property '{}'", path, key));
+                    }
+                    (None, Some(additional)) => check(item, additional, &item_path)?,
+                    (None, None) => {}
+                }
+            }
+        }
+        Value::Array(items) => {
+            within(items.len() as f64, schema, "minItems", "maxItems", path, "items")?;
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    check(item, item_schema, &format!("{}[{}]", path, i))?;
+                }
+            }
+        }
+        Value::String(s) => within(s.chars().count() as f64, schema, "minLength", "maxLength", path, "characters")?,
+        Value::Number(n) => within(n.as_f64().unwrap_or_default(), schema, "minimum", "maximum", path, "")?,
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn type_matches(value: &Value, t: &str) -> bool {
+    match t {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|f| f.fract() == 0.0),
+        _ => true,
+    }
+}
+
+/// Checks `actual` against the inclusive lower and upper bound keywords of the schema.
+fn within(actual: f64, schema: &serde_json::Map<String, Value>, min: &str, max: &str, path: &str, unit: &str) -> Result<(), String> {
+    let unit = if unit.is_empty() { String::new() } else { format!(" {}", unit) };
+    if let Some(bound) = schema.get(min).and_then(Value::as_f64) {
+        if actual < bound {
+            return Err(format!("{} should have at least {}{}", path, bound, unit));
+        }
+    }
+    if let Some(bound) = schema.get(max).and_then(Value::as_f64) {
+        if actual > bound {
+            return Err(format!("{} should have at most {}{}", path, bound, unit));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn person() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string", "minLength": 1 },
+                "age": { "type": "integer", "minimum": 0 },
+                "tags": { "type": "array", "items": { "enum": ["admin", "user"] } },
+                "email": { "type": ["string", "null"] }
+            },
+            "required": ["name", "age"],
+            "additionalProperties": false
+        })
+    }
+
+    #[test]
+    fn test_accepts_matching_values() {
+        assert!(validate(&json!({ "name": "Ada", "age": 36, "tags": ["admin"], "email": null }), &person()).is_ok());
+        assert!(validate(&json!({ "name": "Ada", "age": 36.0 }), &person()).is_ok());
+        assert!(validate(&json!([1, "two"]), &json!({})).is_ok());
+    }
+
+    #[test]
+    fn test_reports_the_first_mismatch_with_its_path() {
+        let cases = [
+            (json!({ "name": "Ada" }), "$ is missing required property 'age'"),
+            (json!({ "name": "Ada", "age": "36" }), "$.age should be of type \"integer\""),
+            (json!({ "name": "Ada", "age": -1 }), "$.age should have at least 0"),
+            (json!({ "name": "", "age": 1 }), "$.name should have at least 1 characters"),
+            (json!({ "name": "Ada", "age": 1, "tags": ["root"] }), "$.tags[0] should be one of [\"admin\",\"user\"]"),
+            (json!({ "name": "Ada", "age": 1, "extra": true }), "$ has unexpected property 'extra'"),
+            (json!("Ada"), "$ should be of type \"object\""),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(validate(&value, &person()).unwrap_err(), expected);
+        }
+    }
+
+    #[test]
+    fn test_any_of() {
+        let schema = json!({ "anyOf": [{ "type": "string" }, { "type": "number" }] });
+        assert!(validate(&json!(1), &schema).is_ok());
+        assert!(validate(&json!(true), &schema).is_err());
+    }
+}
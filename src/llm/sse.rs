@@ -0,0 +1,350 @@
+//! Incremental decoder for `text/event-stream` response bodies.
+//!
+//! Network chunks can split an event, a line, a CRLF pair or a multi-byte UTF-8
+//! character at any byte, so input is buffered as raw bytes and only complete
+//! lines are ever decoded.
+
+use futures_util::{Stream, StreamExt};
+
+/// A single dispatched server-sent event.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SseEvent {
+    /// The `event:` field, `None` for the default `message` type.
+    pub event: Option<String>,
+    /// Every `data:` line of the event joined with `\n`.
+    pub data: String,
+    /// The last `id:` seen on the stream, which carries over to later events.
+    pub id: Option<String>,
+}
+
+/// Splits a byte stream into lines terminated by `\n`, `\r\n` or `\r`.
+#[derive(Debug, Default)]
+pub struct LineDecoder {
+    buffer: Vec<u8>,
+    // The previous chunk ended on `\r`, so a leading `\n` completes that CRLF.
+    skip_lf: bool,
+}
+
+impl LineDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds a chunk and returns every line it completed, without terminators.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        let mut bytes = chunk;
+        if self.skip_lf && !bytes.is_empty() {
+            self.skip_lf = false;
+            if bytes[0] == b'\n' {
+                bytes = &bytes[1..];
+            }
+        }
+
+        let mut lines = Vec::new();
+        let mut start = 0;
+        let mut i = 0;
+        while i < bytes.len() {
+            if bytes[i] == b'\n' || bytes[i] == b'\r' {
+                self.buffer.extend_from_slice(&bytes[start..i]);
+                lines.push(self.take_line());
+                if bytes[i] == b'\r' {
+                    match bytes.get(i + 1) {
+                        Some(b'\n') => i += 1,
+                        Some(_) => {}
+                        None => self.skip_lf = true,
+                    }
+                }
+                start = i + 1;
+            }
+            i += 1;
+        }
+        self.buffer.extend_from_slice(&bytes[start..]);
+        lines
+    }
+
+    /// Returns the trailing line of a body that did not end with a newline.
+    pub fn finish(&mut self) -> Option<String> {
+        self.skip_lf = false;
+        if self.buffer.is_empty() {
+            None
+        } else {
+            Some(self.take_line())
+        }
+    }
+
+    fn take_line(&mut self) -> String {
+        String::from_utf8_lossy(&std::mem::take(&mut self.buffer)).into_owned()
+    }
+}
+
+/// Turns raw body chunks into [`SseEvent`]s following the WHATWG event stream rules.
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    lines: LineDecoder,
+    event: Option<String>,
+    data: Vec<String>,
+    last_id: Option<String>,
+    started: bool,
+}
+
+impl SseDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds a chunk and returns every event it completed.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.lines
+            .push(chunk)
+            .into_iter()
+            .filter_map(|line| self.process_line(line))
+            .collect()
+    }
+
+    /// Flushes the end of the body. Unlike the spec, a final event that is missing its
+    /// blank line is still dispatched, since some servers close right after `data: [DONE]`.
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        if let Some(line) = self.lines.finish() {
+            // A trailing line is never blank, so it cannot dispatch on its own
+            let _ = self.process_line(line);
+        }
+        self.dispatch()
+    }
+
+    fn process_line(&mut self, mut line: String) -> Option<SseEvent> {
+        if !self.started {
+            self.started = true;
+            if line.starts_with('\u{feff}') {
+                line.drain(..'\u{feff}'.len_utf8());
+            }
+        }
+
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(':') {
+            return None;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line.as_str(), ""),
+        };
+        match field {
+            "event" => self.event = (!value.is_empty()).then(|| value.to_string()),
+            "data" => self.data.push(value.to_string()),
+            "id" if !value.contains('\0') => self.last_id = Some(value.to_string()),
+            // `retry` and unknown fields carry nothing we act on
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        if self.data.is_empty() {
+            return None;
+        }
+        Some(SseEvent {
+            event,
+            data: std::mem::take(&mut self.data).join("\n"),
+            id: self.last_id.clone(),
+        })
+    }
+}
+
+/// Decodes a response body stream, e.g. `reqwest::Response::bytes_stream()`, into events.
+pub fn events<S, B, E>(body: S) -> impl Stream<Item = Result<SseEvent, E>>
+where
+    S: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+{
+    async_stream::stream! {
+        let mut decoder = SseDecoder::new();
+        futures_util::pin_mut!(body);
+        while let Some(chunk) = body.next().await {
+            match chunk {
+                Ok(bytes) => {
+                    for event in decoder.push(bytes.as_ref()) {
+                        yield Ok(event);
+                    }
+                }
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            }
+        }
+        if let Some(event) = decoder.finish() {
+            yield Ok(event);
+        }
+    }
+}
+
+/// Decodes a newline-delimited body, such as Ollama's NDJSON stream, into lines.
+pub fn lines<S, B, E>(body: S) -> impl Stream<Item = Result<String, E>>
+where
+    S: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+{
+    async_stream::stream! {
+        let mut decoder = LineDecoder::new();
+        futures_util::pin_mut!(body);
+        while let Some(chunk) = body.next().await {
+            match chunk {
+                Ok(bytes) => {
+                    for line in decoder.push(bytes.as_ref()) {
+                        yield Ok(line);
+                    }
+                }
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            }
+        }
+        if let Some(line) = decoder.finish() {
+            yield Ok(line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn decode_all(chunks: &[&[u8]]) -> Vec<SseEvent> {
+        let mut decoder = SseDecoder::new();
+        let mut events: Vec<SseEvent> = chunks.iter().flat_map(|c| decoder.push(c)).collect();
+        events.extend(decoder.finish());
+        events
+    }
+
+    fn data(data: &str) -> SseEvent {
+        SseEvent {
+            data: data.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_fields_and_multiline_data() {
+        let events = decode_all(&[
+            b": keep-alive\n\nevent: delta\nid: 7\ndata: first\ndata:second\n\ndata\n\ndata: third\n\n",
+        ]);
+        assert_eq!(
+            events,
+            vec![
+                SseEvent {
+                    event: Some("delta".to_string()),
+                    data: "first\nsecond".to_string(),
+                    id: Some("7".to_string()),
+                },
+                SseEvent {
+                    event: None,
+                    data: "".to_string(),
+                    id: Some("7".to_string()),
+                },
+                SseEvent {
+                    event: None,
+                    data: "third".to_string(),
+                    id: Some("7".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_event_without_data_is_not_dispatched() {
+        assert!(decode_all(&[b"event: ping\n\n"]).is_empty());
+        // The event type does not leak into the next event
+        assert_eq!(decode_all(&[b"event: ping\n\ndata: x\n\n"]), vec![data("x")]);
+    }
+
+    #[test]
+    fn test_crlf_split_across_chunks() {
+        let events = decode_all(&[b"data: a\r", b"\n\r", b"\ndata: b\r\r"]);
+        assert_eq!(events, vec![data("a"), data("b")]);
+    }
+
+    #[test]
+    fn test_utf8_split_across_chunks() {
+        let bytes = "data: héllo ✓\n\n".as_bytes();
+        let events = decode_all(&[&bytes[..8], &bytes[8..15], &bytes[15..]]);
+        assert_eq!(events, vec![data("héllo ✓")]);
+    }
+
+    #[test]
+    fn test_bom_and_unterminated_final_event() {
+        let events = decode_all(&["\u{feff}data: one\n\ndata: [DONE]".as_bytes()]);
+        assert_eq!(events, vec![data("one"), data("[DONE]")]);
+    }
+
+    #[test]
+    fn test_line_decoder_keeps_partial_line() {
+        let mut lines = LineDecoder::new();
+        assert_eq!(lines.push(b"{\"a\":1}\n{\"b\""), vec!["{\"a\":1}"]);
+        assert_eq!(lines.push(b":2}\n"), vec!["{\"b\":2}"]);
+        assert_eq!(lines.finish(), None);
+    }
+
+    #[tokio::test]
+    async fn test_events_stream_adapter() {
+        let chunks: Vec<Result<&[u8], ()>> = vec![Ok(b"data: x\n"), Ok(b"\ndata: y"), Err(()), Ok(b"\n\n")];
+        let events: Vec<_> = events(futures_util::stream::iter(chunks)).collect().await;
+        assert_eq!(events, vec![Ok(data("x")), Err(())]);
+    }
+
+    fn event_strategy() -> impl Strategy<Value = (Option<String>, Vec<String>, Option<String>)> {
+        (
+            proptest::option::of("[a-z_]{1,12}"),
+            proptest::collection::vec("[^\r\n]{0,20}", 1..4),
+            proptest::option::of("[a-zA-Z0-9]{1,8}"),
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn prop_random_chunk_boundaries(
+            raw_events in proptest::collection::vec(event_strategy(), 0..6),
+            newline in prop::sample::select(vec!["\n", "\r\n", "\r"]),
+            splits in proptest::collection::vec(any::<prop::sample::Index>(), 0..10),
+        ) {
+            let mut body = String::new();
+            let mut expected = Vec::new();
+            let mut last_id = None;
+            for (event, lines, id) in &raw_events {
+                if let Some(event) = event {
+                    body.push_str(&format!("event: {}{}", event, newline));
+                }
+                if let Some(id) = id {
+                    body.push_str(&format!("id: {}{}", id, newline));
+                    last_id = Some(id.clone());
+                }
+                for line in lines {
+                    body.push_str(&format!("data: {}{}", line, newline));
+                }
+                body.push_str(newline);
+                expected.push(SseEvent {
+                    event: event.clone(),
+                    data: lines.join("\n"),
+                    id: last_id.clone(),
+                });
+            }
+
+            let bytes = body.as_bytes();
+            let mut cuts: Vec<usize> = splits.iter().map(|i| i.index(bytes.len() + 1)).collect();
+            cuts.sort_unstable();
+            let mut chunks = Vec::new();
+            let mut start = 0;
+            for cut in cuts {
+                chunks.push(&bytes[start..cut]);
+                start = cut;
+            }
+            chunks.push(&bytes[start..]);
+
+            prop_assert_eq!(decode_all(&chunks), expected);
+        }
+    }
+}
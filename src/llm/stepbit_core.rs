@@ -8,8 +8,9 @@ use tracing::{debug, error, info};
 use std::collections::HashMap;
 
 use crate::llm::{
-    models::{ChatOptions, ChatResponse, Message},
-    LlmError, LlmProvider,
+    models::{ChatOptions, ChatResponse, Message, StreamEvent, StreamResponse, Usage},
+    openai::{apply_sampling, parse_usage},
+    sse, LlmError, LlmProvider,
 };
 
 pub struct StepbitCoreProvider {
@@ -56,7 +57,7 @@ impl StepbitCoreProvider {
             let res = req
                 .send()
                 .await
-                .map_err(|e| LlmError::Network(e.to_string()))?;
+                .map_err(LlmError::from)?;
 
             if res.status().is_success() {
                 self.handle_token_rotation(&res);
@@ -87,13 +88,21 @@ impl StepbitCoreProvider {
         let res = req
             .send()
             .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+            .map_err(LlmError::from)?;
         if res.status().is_success() {
             self.handle_token_rotation(&res);
         }
         Ok(res)
     }
 
+    fn finish_stream(full_text: &str, usage: Option<Usage>) -> StreamResponse {
+        StreamResponse {
+            tool_calls: crate::llm::extract_streaming_tool_call(full_text).map(|(tools, _)| tools),
+            usage,
+            ..Default::default()
+        }
+    }
+
     fn handle_token_rotation(&self, res: &reqwest::Response) {
         if let Some(next_token) = res.headers().get("X-Next-Token") {
             if let Ok(token_str) = next_token.to_str() {
@@ -124,35 +133,36 @@ impl LlmProvider for StepbitCoreProvider {
                 0,
                 Message {
                     role: "system".to_string(),
-                    content: system.clone(),
+                    content: system.clone().into(),
                     tool_calls: None,
                     tool_call_id: None,
+                    thinking: None,
                 },
             );
         }
 
-        let body = json!({
+        let mut body = json!({
             "model": model,
             "messages": final_messages,
             "stream": false,
             "max_tokens": options.max_tokens.unwrap_or(4096),
             "temperature": options.temperature.unwrap_or(0.7),
         });
+        // The engine samples locally and takes every parameter
+        apply_sampling(&mut body, &options.sampling);
 
         let response = self
             .authenticated_request(reqwest::Method::POST, "/v1/chat/completions", Some(body))
             .await?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let text = response.text().await.unwrap_or_default();
-            return Err(LlmError::Api(format!("stepbit-core Error {}: {}", status, text)));
+            return Err(LlmError::from_response(response, self.name(), "stepbit-core Error").await);
         }
 
         let json: serde_json::Value = response
             .json()
             .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+            .map_err(LlmError::from)?;
 
         let content = json["choices"][0]["message"]["content"]
             .as_str()
@@ -162,8 +172,10 @@ impl LlmProvider for StepbitCoreProvider {
         Ok(ChatResponse {
             content,
             model: model.to_string(),
-            usage: None,
+            usage: parse_usage(&json),
             tool_calls: None, // stepbit-core doesn't support tools yet in its ChatCompletionResponse
+            provider: None,
+            thinking: None,
         })
     }
 
@@ -171,8 +183,8 @@ impl LlmProvider for StepbitCoreProvider {
         &self,
         messages: &[Message],
         options: ChatOptions,
-        tx: Sender<String>,
-    ) -> Result<Option<Vec<crate::llm::models::ToolCall>>, LlmError> {
+        tx: Sender<StreamEvent>,
+    ) -> Result<StreamResponse, LlmError> {
         let model = options.model.as_deref().unwrap_or(&self.default_model);
 
         let mut final_messages: Vec<Message> = messages.to_vec();
@@ -181,137 +193,129 @@ impl LlmProvider for StepbitCoreProvider {
                 0,
                 Message {
                     role: "system".to_string(),
-                    content: system.clone(),
+                    content: system.clone().into(),
                     tool_calls: None,
                     tool_call_id: None,
+                    thinking: None,
                 },
             );
         }
 
-        let body = json!({
+        let mut body = json!({
             "model": model,
             "messages": final_messages,
             "stream": true,
             "max_tokens": options.max_tokens.unwrap_or(4096),
             "temperature": options.temperature.unwrap_or(0.7),
         });
+        apply_sampling(&mut body, &options.sampling);
 
         let response = self
             .authenticated_request(reqwest::Method::POST, "/v1/chat/completions", Some(body))
             .await?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let text = response.text().await.unwrap_or_default();
-            return Err(LlmError::Api(format!(
-                "stepbit-core Stream Error {}: {}",
-                status, text
-            )));
+            return Err(LlmError::from_response(response, self.name(), "stepbit-core Stream Error").await);
         }
 
-        let mut stream = response.bytes_stream();
-        let mut buffer = String::new();
+        let events = sse::events(response.bytes_stream());
+        futures_util::pin_mut!(events);
         let mut buffer_full = String::new();
+        let mut usage = None;
 
         loop {
-            let next_chunk =
-                tokio::time::timeout(std::time::Duration::from_secs(30), stream.next()).await;
+            let next_event =
+                tokio::time::timeout(std::time::Duration::from_secs(30), events.next()).await;
 
-            let chunk = match next_chunk {
-                Ok(Some(result)) => result.map_err(|e| LlmError::Network(e.to_string()))?,
+            let event = match next_event {
+                Ok(Some(result)) => result.map_err(LlmError::from)?,
                 Ok(None) => break, // Stream ended naturally
                 Err(_) => {
                     error!("stepbit-core stream timed out after 30s");
-                    return Err(LlmError::Network("Stream timeout".to_string()));
+                    return Err(LlmError::Timeout("stepbit-core stream timed out after 30s".to_string()));
                 }
             };
 
-            let chunk_str = String::from_utf8_lossy(&chunk);
-            debug!("Received chunk from stepbit-core: {:?}", chunk_str);
-            buffer.push_str(&chunk_str);
+            debug!("Received event from stepbit-core: {:?}", event.data);
 
-            while let Some(line_end) = buffer.find('\n') {
-                let line = buffer[..line_end].trim().to_string();
-                buffer.drain(..=line_end);
+            if event.data == "[DONE]" {
+                info!(
+                    "SSE Stream reached [DONE] signal for session {:?}",
+                    options.user
+                );
+                return Ok(Self::finish_stream(&buffer_full, usage));
+            }
 
-                if line.is_empty() {
-                    continue;
+            if let Ok(json) = serde_json::from_str::<serde_json::Value>(&event.data) {
+                if let Some(u) = parse_usage(&json) {
+                    usage = Some(u);
                 }
 
-                if line == "data: [DONE]" || line.contains("[DONE]") {
+                // Check for the specific {type: 'done'} message that should REALLY end the stream
+                if json.get("type").and_then(|t| t.as_str()) == Some("done") {
                     info!(
-                        "SSE Stream reached [DONE] signal for session {:?}",
+                        "SSE Stream reached explicit done signal (type: done) for session {:?}",
                         options.user
                     );
-                    if let Some((tools, _)) = crate::llm::extract_streaming_tool_call(&buffer_full) {
-                        return Ok(Some(tools));
-                    }
-                    return Ok(None);
+                    return Ok(Self::finish_stream(&buffer_full, usage));
                 }
 
-                if line.starts_with("data: ") {
-                    let data = &line[6..];
-                    if let Ok(json) = serde_json::from_str::<serde_json::Value>(data) {
-                        // Check for the specific {type: 'done'} message that should REALLY end the stream
-                        if json.get("type").and_then(|t| t.as_str()) == Some("done") {
-                            info!(
-                                "SSE Stream reached explicit done signal (type: done) for session {:?}",
-                                options.user
-                            );
-                            if let Some((tools, _)) = crate::llm::extract_streaming_tool_call(&buffer_full) {
-                                return Ok(Some(tools));
+                if let Some(choices) = json["choices"].as_array() {
+                    if let Some(choice) = choices.get(0) {
+                        if let Some(content) = choice["delta"]["content"].as_str() {
+                            buffer_full.push_str(content);
+                            if tx.send(StreamEvent::Text(content.to_string())).await.is_err() {
+                                return Err(LlmError::Cancelled);
                             }
-                            return Ok(None);
                         }
-
-                        if let Some(choices) = json["choices"].as_array() {
-                            if let Some(choice) = choices.get(0) {
-                                if let Some(content) = choice["delta"]["content"].as_str() {
-                                    buffer_full.push_str(content);
-                                    let _ = tx.send(content.to_string()).await;
-                                }
-                                if let Some(reason) = choice["finish_reason"].as_str() {
-                                    debug!("Stream chunk finish_reason: {}", reason);
-                                    // Previously we might have hit 'done: true' and bailed.
-                                    // stepbit-core sends 'done: true' when a microbatch finishes or EOS.
-                                    // We should only stop if it's EOS or explicitly Told to.
-                                    // Actually, we'll let the loop continue until [DONE] or type: done.
-                                }
-                            }
+                        if let Some(reason) = choice["finish_reason"].as_str() {
+                            debug!("Stream chunk finish_reason: {}", reason);
+                            // Previously we might have hit 'done: true' and bailed.
+                            // stepbit-core sends 'done: true' when a microbatch finishes or EOS.
+                            // We should only stop if it's EOS or explicitly Told to.
+                            // Actually, we'll let the loop continue until [DONE] or type: done.
                         }
                     }
                 }
             }
         }
 
-        // Handle trailing data if any
-        let final_line = buffer.trim().to_string();
-        if !final_line.is_empty() {
-            if final_line == "data: [DONE]" || final_line.contains("[DONE]") {
-                info!("SSE Stream reached [DONE] in trailing data");
-                if let Some((tools, _)) = crate::llm::extract_streaming_tool_call(&buffer_full) {
-                    return Ok(Some(tools));
-                }
-                return Ok(None);
-            }
-        }
-
         info!(
             "StepbitCoreProvider::chat_streaming loop finished naturally for session {:?}",
             options.user
         );
         
-        if let Some((tools, _)) = crate::llm::extract_streaming_tool_call(&buffer_full) {
-            return Ok(Some(tools));
-        }
-        
-        Ok(None)
+        Ok(Self::finish_stream(&buffer_full, usage))
     }
 
     fn supported_models(&self) -> Vec<String> {
         vec![self.default_model.clone()]
     }
 
+    async fn embed(&self, inputs: &[String], model: Option<String>) -> Result<Vec<Vec<f32>>, LlmError> {
+        if inputs.is_empty() {
+            return Ok(vec![]);
+        }
+        let body = json!({
+            "model": model.unwrap_or_else(|| self.default_model.clone()),
+            "input": inputs,
+        });
+
+        let response = self
+            .authenticated_request(reqwest::Method::POST, "/v1/embeddings", Some(body))
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(LlmError::from_response(response, self.name(), "stepbit-core Embeddings Error").await);
+        }
+
+        let json: serde_json::Value = response
+            .json()
+            .await
+            .map_err(LlmError::from)?;
+        crate::llm::openai::parse_embeddings(&json, inputs.len())
+    }
+
     async fn discover_models(&self) -> Result<Vec<String>, LlmError> {
         let response = self
             .authenticated_request(reqwest::Method::GET, "/v1/models", None)
@@ -324,7 +328,7 @@ impl LlmProvider for StepbitCoreProvider {
         let json: serde_json::Value = response
             .json()
             .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+            .map_err(LlmError::from)?;
 
         if let Some(models) = json["data"].as_array() {
             let model_names: Vec<String> = models
@@ -366,15 +370,13 @@ impl LlmProvider for StepbitCoreProvider {
             .await?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let text = response.text().await.unwrap_or_default();
-            return Err(LlmError::Api(format!("stepbit-core MCP Error {}: {}", status, text)));
+            return Err(LlmError::from_response(response, self.name(), "stepbit-core MCP Error").await);
         }
 
         let json: serde_json::Value = response
             .json()
             .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+            .map_err(LlmError::from)?;
 
         let tools: Vec<crate::llm::models::McpToolDefinition> = serde_json::from_value(json["tools"].clone())
             .map_err(|e| LlmError::Api(format!("Failed to parse tools: {}", e)))?;
@@ -395,15 +397,13 @@ impl LlmProvider for StepbitCoreProvider {
             .await?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let text = response.text().await.unwrap_or_default();
-            return Err(LlmError::Api(format!("stepbit-core Reasoning Error {}: {}", status, text)));
+            return Err(LlmError::from_response(response, self.name(), "stepbit-core Reasoning Error").await);
         }
 
         let json: serde_json::Value = response
             .json()
             .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+            .map_err(LlmError::from)?;
 
         let results: HashMap<String, serde_json::Value> = serde_json::from_value(json["results"].clone())
             .map_err(|e| LlmError::Api(format!("Failed to parse reasoning results: {}", e)))?;
@@ -425,33 +425,16 @@ impl LlmProvider for StepbitCoreProvider {
             .await?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let text = response.text().await.unwrap_or_default();
-            return Err(LlmError::Api(format!("stepbit-core Reasoning Stream Error {}: {}", status, text)));
+            return Err(LlmError::from_response(response, self.name(), "stepbit-core Reasoning Stream Error").await);
         }
 
-        let mut stream = response.bytes_stream();
-        let mut buffer = String::new();
-
-        while let Some(chunk_result) = stream.next().await {
-            let chunk = chunk_result.map_err(|e| LlmError::Network(e.to_string()))?;
-            let chunk_str = String::from_utf8_lossy(&chunk);
-            buffer.push_str(&chunk_str);
-
-            while let Some(line_end) = buffer.find('\n') {
-                let line = buffer[..line_end].trim().to_string();
-                buffer.drain(..=line_end);
+        let events = sse::events(response.bytes_stream());
+        futures_util::pin_mut!(events);
 
-                if line.is_empty() {
-                    continue;
-                }
-
-                if line.starts_with("data: ") {
-                    let data = &line[6..];
-                    if let Ok(json) = serde_json::from_str::<serde_json::Value>(data) {
-                        let _ = tx.send(json).await;
-                    }
-                }
+        while let Some(event) = events.next().await {
+            let event = event.map_err(LlmError::from)?;
+            if let Ok(json) = serde_json::from_str::<serde_json::Value>(&event.data) {
+                let _ = tx.send(json).await;
             }
         }
 
@@ -473,15 +456,13 @@ impl LlmProvider for StepbitCoreProvider {
             .await?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let text = response.text().await.unwrap_or_default();
-            return Err(LlmError::Api(format!("stepbit-core Pipeline Error {}: {}", status, text)));
+            return Err(LlmError::from_response(response, self.name(), "stepbit-core Pipeline Error").await);
         }
 
         let json: serde_json::Value = response
             .json()
             .await
-            .map_err(|e| LlmError::Network(e.to_string()))?;
+            .map_err(LlmError::from)?;
 
         let result: crate::llm::models::PipelineExecuteResult = serde_json::from_value(json)
             .map_err(|e| LlmError::Api(format!("Failed to parse pipeline result: {}", e)))?;
@@ -3,7 +3,7 @@ use actix_web::{web, App, HttpResponse, HttpServer, Responder};
 use actix_cors::Cors;
 use clap::Parser;
 use stepbit::config::AppConfig;
-use stepbit::db;
+use stepbit::db::{self, credentials::CredentialCipher};
 use stepbit::api::middleware::ApiKeyAuth;
 use stepbit::llm::ProviderFactory;
 use stepbit::cli::{commands::{Cli, Commands}, run_cli};
@@ -72,6 +72,21 @@ async fn main() -> std::io::Result<()> {
         }
     };
 
+    // Providers added through the API, over the ones from config.yaml with the same id
+    let credential_cipher = match CredentialCipher::from_env() {
+        Ok(cipher) => cipher,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Ok(conn) = db_pool.lock() {
+        match ProviderFactory::register_stored(llm_provider.as_ref(), &conn, credential_cipher.as_ref()) {
+            Ok(count) => info!("Registered {} stored providers", count),
+            Err(e) => warn!("Failed to load stored providers: {}", e),
+        }
+    }
+
     let host = config.server.host.clone();
     let port = config.server.port;
 
@@ -88,6 +103,7 @@ async fn main() -> std::io::Result<()> {
             .app_data(web::Data::new(config.clone()))
             .app_data(web::Data::new(db_pool.clone()))
             .app_data(web::Data::new(llm_provider.clone()))
+            .app_data(web::Data::new(credential_cipher.clone()))
             .wrap(cors)
             .wrap(ApiKeyAuth)
             .service(
@@ -97,6 +113,7 @@ async fn main() -> std::io::Result<()> {
                     .configure(stepbit::api::config_routes::configure)
                     .configure(stepbit::api::skills_routes::configure)
                     .configure(stepbit::api::pipeline_routes::configure)
+                    .configure(stepbit::api::ollama_routes::configure)
                     .service(stepbit::api::routes_openai::openai_chat_completions)
             )
             .configure(stepbit::api::websocket::configure)
@@ -0,0 +1,20 @@
+//! Helpers shared by the integration tests, pulled in with `mod common;`. Not every test
+//! file uses every helper.
+#![allow(dead_code)]
+
+use stepbit::llm::models::Message;
+
+pub fn message(role: &str, content: &str) -> Message {
+    Message {
+        role: role.to_string(),
+        content: content.into(),
+        tool_calls: None,
+        tool_call_id: None,
+        thinking: None,
+    }
+}
+
+/// A conversation of a single user message.
+pub fn user_message(content: &str) -> Vec<Message> {
+    vec![message("user", content)]
+}
@@ -1,8 +1,13 @@
 #[cfg(test)]
 mod tests {
     use stepbit::db::connection;
+    use stepbit::db::credentials::{self, CredentialCipher};
+    use stepbit::db::models::MessageUsage;
     use stepbit::db::service::DbService;
-    use stepbit::config::DatabaseConfig;
+    use stepbit::config::{DatabaseConfig, ProviderSpec};
+    use stepbit::llm::{LlmProvider, ProviderFactory, ProviderManager};
+    use std::collections::HashMap;
+    use std::sync::Arc;
     use serde_json::json;
     
     // In memory database just for tests
@@ -36,7 +41,21 @@ mod tests {
                 model VARCHAR,
                 token_count INTEGER,
                 created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                metadata JSON DEFAULT '{}'
+                metadata JSON DEFAULT '{}',
+                provider VARCHAR,
+                input_tokens INTEGER,
+                output_tokens INTEGER,
+                cost DOUBLE,
+                cache_read_tokens INTEGER,
+                cache_write_tokens INTEGER
+            );
+
+            CREATE TABLE IF NOT EXISTS providers (
+                id VARCHAR PRIMARY KEY,
+                settings JSON NOT NULL,
+                credentials VARCHAR,
+                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
             );
             "#
         ).unwrap();
@@ -94,4 +113,79 @@ mod tests {
         let empty_history = DbService::get_messages(&conn, session.id, 10, 0).unwrap();
         assert_eq!(empty_history.len(), 0);
     }
+
+    #[test]
+    fn test_assistant_usage_is_summed_per_provider_and_model() {
+        let conn = get_test_db();
+        let session = DbService::insert_session(&conn, "Spend", json!({})).unwrap();
+        let other = DbService::insert_session(&conn, "Other", json!({})).unwrap();
+
+        let usage = MessageUsage {
+            provider: Some("openai".to_string()),
+            input_tokens: Some(1000),
+            output_tokens: Some(200),
+            cache_read_tokens: Some(3000),
+            cache_write_tokens: None,
+            cost: Some(0.0045),
+        };
+        let reply = DbService::insert_assistant_message(&conn, session.id, "Hi", "gpt-4o", &usage, json!({})).unwrap();
+        assert_eq!(reply.usage, usage);
+        assert_eq!(reply.token_count, Some(1200));
+        DbService::insert_assistant_message(&conn, session.id, "Hi again", "gpt-4o", &usage, json!({})).unwrap();
+        DbService::insert_assistant_message(&conn, other.id, "Elsewhere", "gpt-4o", &usage, json!({})).unwrap();
+        DbService::insert_message(&conn, session.id, "user", "Hello!", None, None, json!({})).unwrap();
+
+        let stats = DbService::get_session_stats(&conn, session.id).unwrap();
+        assert_eq!(stats.total_messages, 3);
+        assert_eq!(stats.input_tokens, 2000);
+        assert_eq!(stats.output_tokens, 400);
+        assert_eq!(stats.cache_read_tokens, 6000);
+        assert_eq!(stats.cache_write_tokens, 0);
+        assert!((stats.total_cost - 0.009).abs() < 1e-9);
+        assert_eq!(stats.spend.len(), 1);
+        assert_eq!(stats.spend[0].provider.as_deref(), Some("openai"));
+        assert_eq!(stats.spend[0].model.as_deref(), Some("gpt-4o"));
+        assert_eq!(stats.spend[0].messages, 2);
+
+        let everything = DbService::get_spend(&conn, None).unwrap();
+        assert_eq!(everything[0].messages, 3);
+    }
+
+    #[test]
+    fn test_stored_providers_are_encrypted_and_registered() {
+        let conn = get_test_db();
+        let cipher = CredentialCipher::new("AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=").unwrap();
+        let spec = |key: &str| -> ProviderSpec {
+            serde_json::from_value(json!({
+                "kind": "openai",
+                "api_base": "http://localhost:8080/v1",
+                "default_model": "qwen2.5",
+                "api_key": key
+            }))
+            .unwrap()
+        };
+
+        for key in ["sk-old", "sk-new"] {
+            let (settings, sealed) = credentials::seal_provider("vllm", &spec(key), Some(&cipher)).unwrap();
+            DbService::upsert_provider(&conn, "vllm", &settings, sealed.as_deref()).unwrap();
+        }
+        let stored = DbService::list_providers(&conn).unwrap();
+        assert_eq!(stored.len(), 1);
+        assert!(!stored[0].credentials.as_deref().unwrap().contains("sk-new"));
+        let opened = credentials::open_provider(&stored[0], Some(&cipher)).unwrap();
+        assert_eq!(opened.api_key.as_deref(), Some("sk-new"));
+
+        let manager: Arc<dyn LlmProvider> = Arc::new(ProviderManager::new(HashMap::new(), "vllm".to_string()));
+        // A different master key leaves the provider out rather than failing the startup
+        let registered = ProviderFactory::register_stored(manager.as_ref(), &conn, Some(&CredentialCipher::new("AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=").unwrap())).unwrap();
+        assert_eq!(registered, 0);
+        let registered = ProviderFactory::register_stored(manager.as_ref(), &conn, Some(&cipher)).unwrap();
+        assert_eq!(registered, 1);
+        let manager = manager.as_any().downcast_ref::<ProviderManager>().unwrap();
+        assert_eq!(manager.get_provider("vllm").unwrap().default_model(), "qwen2.5");
+
+        assert!(DbService::delete_provider(&conn, "vllm").unwrap());
+        assert!(!DbService::delete_provider(&conn, "vllm").unwrap());
+        assert!(DbService::get_provider(&conn, "vllm").unwrap().is_none());
+    }
 }
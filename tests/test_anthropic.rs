@@ -0,0 +1,244 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::user_message;
+    use stepbit::llm::anthropic::AnthropicProvider;
+    use stepbit::llm::{
+        models::{ChatOptions, FunctionCall, FunctionDefinition, Message, ToolCall, ToolDefinition},
+        LlmProvider,
+    };
+    use serde_json::json;
+    use wiremock::matchers::{body_partial_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn search_tool() -> ToolDefinition {
+        ToolDefinition {
+            r#type: "function".to_string(),
+            function: FunctionDefinition {
+                name: "internet_search".to_string(),
+                description: "Search the web".to_string(),
+                parameters: json!({
+                    "type": "object",
+                    "properties": { "query": { "type": "string" } },
+                    "required": ["query"]
+                }),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_chat_returns_tool_use() {
+        let mock_server = MockServer::start().await;
+        let provider = AnthropicProvider::new(
+            "test-key".to_string(),
+            mock_server.uri(),
+            "claude-3-5-sonnet-20241022".to_string(),
+        );
+
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .and(header("x-api-key", "test-key"))
+            .and(body_partial_json(json!({
+                "tools": [{
+                    "name": "internet_search",
+                    "description": "Search the web",
+                    "input_schema": { "type": "object" }
+                }],
+                "tool_choice": { "type": "auto" }
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id": "msg_01",
+                "type": "message",
+                "role": "assistant",
+                "content": [
+                    { "type": "text", "text": "Let me look that up." },
+                    {
+                        "type": "tool_use",
+                        "id": "toolu_01",
+                        "name": "internet_search",
+                        "input": { "query": "rust actix" }
+                    }
+                ],
+                "stop_reason": "tool_use",
+                "usage": { "input_tokens": 12, "output_tokens": 7 }
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let messages = user_message("Find actix docs");
+        let options = ChatOptions {
+            tools: Some(vec![search_tool()]),
+            tool_choice: Some(json!("auto")),
+            ..Default::default()
+        };
+
+        let response = provider.chat(&messages, options).await.unwrap();
+        assert_eq!(response.content, "Let me look that up.");
+
+        let tool_calls = response.tool_calls.unwrap();
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].id.as_deref(), Some("toolu_01"));
+        assert_eq!(tool_calls[0].function.name, "internet_search");
+        assert_eq!(tool_calls[0].function.arguments, "{\"query\":\"rust actix\"}");
+        assert_eq!(response.usage.unwrap().input_tokens, 12);
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_chat_maps_tool_results() {
+        let mock_server = MockServer::start().await;
+        let provider = AnthropicProvider::new(
+            "test-key".to_string(),
+            mock_server.uri(),
+            "claude-3-5-sonnet-20241022".to_string(),
+        );
+
+        // The assistant tool call becomes a tool_use block and both tool results are
+        // merged into a single user turn made of tool_result blocks.
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .and(body_partial_json(json!({
+                "system": "Be brief.",
+                "messages": [
+                    { "role": "user", "content": [{ "type": "text", "text": "Compare both" }] },
+                    {
+                        "role": "assistant",
+                        "content": [
+                            { "type": "tool_use", "id": "toolu_a", "name": "internet_search", "input": { "query": "a" } },
+                            { "type": "tool_use", "id": "toolu_b", "name": "internet_search", "input": { "query": "b" } }
+                        ]
+                    },
+                    {
+                        "role": "user",
+                        "content": [
+                            { "type": "tool_result", "tool_use_id": "toolu_a", "content": "result a" },
+                            { "type": "tool_result", "tool_use_id": "toolu_b", "content": "result b" }
+                        ]
+                    }
+                ]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "content": [{ "type": "text", "text": "A is better." }],
+                "usage": { "input_tokens": 30, "output_tokens": 4 }
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let call = |id: &str, query: &str| ToolCall {
+            id: Some(id.to_string()),
+            r#type: Some("function".to_string()),
+            function: FunctionCall {
+                name: "internet_search".to_string(),
+                arguments: json!({ "query": query }).to_string(),
+            },
+        };
+        let messages = vec![
+            Message {
+                role: "system".to_string(),
+                content: "Be brief.".into(),
+                tool_calls: None,
+                tool_call_id: None,
+                thinking: None,
+            },
+            Message {
+                role: "user".to_string(),
+                content: "Compare both".into(),
+                tool_calls: None,
+                tool_call_id: None,
+                thinking: None,
+            },
+            Message {
+                role: "assistant".to_string(),
+                content: "".into(),
+                tool_calls: Some(vec![call("toolu_a", "a"), call("toolu_b", "b")]),
+                tool_call_id: None,
+                thinking: None,
+            },
+            Message {
+                role: "tool".to_string(),
+                content: "result a".into(),
+                tool_calls: None,
+                tool_call_id: Some("toolu_a".to_string()),
+                thinking: None,
+            },
+            Message {
+                role: "tool".to_string(),
+                content: "result b".into(),
+                tool_calls: None,
+                tool_call_id: Some("toolu_b".to_string()),
+                thinking: None,
+            },
+        ];
+
+        let options = ChatOptions {
+            tools: Some(vec![search_tool()]),
+            ..Default::default()
+        };
+
+        let response = provider.chat(&messages, options).await.unwrap();
+        assert_eq!(response.content, "A is better.");
+        assert!(response.tool_calls.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_streaming_assembles_split_tool_call() {
+        let mock_server = MockServer::start().await;
+        let provider = AnthropicProvider::new(
+            "test-key".to_string(),
+            mock_server.uri(),
+            "claude-3-5-sonnet-20241022".to_string(),
+        );
+
+        let events = [
+            json!({ "type": "message_start", "message": { "id": "msg_01", "usage": { "input_tokens": 20, "output_tokens": 1 } } }),
+            json!({ "type": "content_block_start", "index": 0, "content_block": { "type": "text", "text": "" } }),
+            json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "text_delta", "text": "Searching" } }),
+            json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "text_delta", "text": " now." } }),
+            json!({ "type": "content_block_stop", "index": 0 }),
+            json!({ "type": "content_block_start", "index": 1, "content_block": { "type": "tool_use", "id": "toolu_42", "name": "internet_search", "input": {} } }),
+            json!({ "type": "content_block_delta", "index": 1, "delta": { "type": "input_json_delta", "partial_json": "" } }),
+            json!({ "type": "content_block_delta", "index": 1, "delta": { "type": "input_json_delta", "partial_json": "{\"query\": \"ru" } }),
+            json!({ "type": "content_block_delta", "index": 1, "delta": { "type": "input_json_delta", "partial_json": "st actix\"}" } }),
+            json!({ "type": "content_block_stop", "index": 1 }),
+            json!({ "type": "message_delta", "delta": { "stop_reason": "tool_use" }, "usage": { "output_tokens": 15 } }),
+            json!({ "type": "message_stop" }),
+        ];
+        let body: String = events
+            .iter()
+            .map(|e| format!("event: {}\ndata: {}\n\n", e["type"].as_str().unwrap(), e))
+            .collect();
+
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .and(body_partial_json(json!({ "stream": true })))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&mock_server)
+            .await;
+
+        let messages = user_message("Find actix docs");
+        let options = ChatOptions {
+            tools: Some(vec![search_tool()]),
+            ..Default::default()
+        };
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let result = provider.chat_streaming(&messages, options, tx).await.unwrap();
+        let tool_calls = result.tool_calls.unwrap();
+
+        let mut text = String::new();
+        while let Some(chunk) = rx.recv().await {
+            text.push_str(chunk.text().unwrap_or_default());
+        }
+        assert_eq!(text, "Searching now.");
+
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].id.as_deref(), Some("toolu_42"));
+        assert_eq!(tool_calls[0].function.name, "internet_search");
+        let args: serde_json::Value = serde_json::from_str(&tool_calls[0].function.arguments).unwrap();
+        assert_eq!(args, json!({ "query": "rust actix" }));
+
+        let usage = result.usage.unwrap();
+        assert_eq!(usage.input_tokens, 20);
+        assert_eq!(usage.output_tokens, 15);
+    }
+}
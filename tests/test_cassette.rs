@@ -0,0 +1,167 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::user_message;
+    use std::sync::Arc;
+
+    use serde_json::json;
+    use stepbit::llm::cassette::RecordingProvider;
+    use stepbit::llm::openai::OpenAiProvider;
+    use stepbit::llm::{
+        extract_streaming_tool_call,
+        models::{ChatOptions, Message},
+        LlmError, LlmProvider,
+    };
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn collect(
+        provider: &dyn LlmProvider,
+        messages: &[Message],
+    ) -> (Vec<String>, stepbit::llm::models::StreamResponse) {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let result = provider
+            .chat_streaming(messages, ChatOptions::default(), tx)
+            .await
+            .unwrap();
+        let mut chunks = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            chunks.extend(chunk.text().map(str::to_string));
+        }
+        (chunks, result)
+    }
+
+    #[tokio::test]
+    async fn test_recorded_session_replays_offline() {
+        let cassette = std::env::temp_dir().join(format!("stepbit-{}.cassette.json", uuid::Uuid::new_v4()));
+
+        {
+            let mock_server = MockServer::start().await;
+            Mock::given(method("POST"))
+                .and(path("/chat/completions"))
+                .and(body_partial_json(json!({ "stream": true })))
+                .respond_with(ResponseTemplate::new(200).set_body_raw(
+                    [
+                        json!({ "choices": [{ "index": 0, "delta": { "content": "Hel" } }] }),
+                        json!({ "choices": [{ "index": 0, "delta": { "content": "lo!" } }] }),
+                        json!({ "choices": [], "usage": { "prompt_tokens": 3, "completion_tokens": 2 } }),
+                    ]
+                    .iter()
+                    .map(|c| format!("data: {}\n\n", c))
+                    .collect::<String>()
+                        + "data: [DONE]\n\n",
+                    "text/event-stream",
+                ))
+                .mount(&mock_server)
+                .await;
+            Mock::given(method("POST"))
+                .and(path("/chat/completions"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "model": "gpt-4o",
+                    "choices": [{
+                        "message": {
+                            "role": "assistant",
+                            "content": "",
+                            "tool_calls": [{
+                                "id": "call_1",
+                                "type": "function",
+                                "function": { "name": "internet_search", "arguments": "{\"query\":\"rust\"}" }
+                            }]
+                        }
+                    }]
+                })))
+                .mount(&mock_server)
+                .await;
+
+            let recorder = RecordingProvider::record(
+                Arc::new(OpenAiProvider::new("sk-test".to_string(), mock_server.uri(), "gpt-4o".to_string())),
+                &cassette,
+            );
+            let (chunks, _) = collect(&recorder, &user_message("Hi")).await;
+            assert_eq!(chunks, vec!["Hel", "lo!"]);
+            recorder
+                .chat(&user_message("Search rust"), ChatOptions::default())
+                .await
+                .unwrap();
+            assert_eq!(recorder.cassette().provider, "openai");
+        }
+
+        // The mock server is gone, everything now comes from the file
+        let replay = RecordingProvider::replay(&cassette).unwrap();
+        std::fs::remove_file(&cassette).ok();
+
+        let (chunks, result) = collect(&replay, &user_message("Hi")).await;
+        assert_eq!(chunks, vec!["Hel", "lo!"]);
+        let usage = result.usage.unwrap();
+        assert_eq!((usage.input_tokens, usage.output_tokens), (3, 2));
+
+        let response = replay
+            .chat(&user_message("Search rust"), ChatOptions::default())
+            .await
+            .unwrap();
+        let tool_calls = response.tool_calls.unwrap();
+        assert_eq!(tool_calls[0].id.as_deref(), Some("call_1"));
+        assert_eq!(tool_calls[0].function.arguments, "{\"query\":\"rust\"}");
+        assert_eq!(response.model, "gpt-4o");
+
+        // Each interaction is served once, and unknown conversations are reported
+        let error = replay
+            .chat(&user_message("Hi"), ChatOptions::default())
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("No unused interaction"));
+    }
+
+    #[tokio::test]
+    async fn test_recording_stops_the_inner_stream_when_the_client_leaves() {
+        let cassette = std::env::temp_dir().join(format!("stepbit-{}.cassette.json", uuid::Uuid::new_v4()));
+        let mock_server = MockServer::start().await;
+        // More chunks than the relay channel holds, so the inner provider has to wait on it
+        let body = (0..500)
+            .map(|i| format!("data: {}\n\n", json!({ "choices": [{ "index": 0, "delta": { "content": i.to_string() } }] })))
+            .collect::<String>()
+            + "data: [DONE]\n\n";
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&mock_server)
+            .await;
+
+        let recorder = RecordingProvider::record(
+            Arc::new(OpenAiProvider::new("sk-test".to_string(), mock_server.uri(), "gpt-4o".to_string())),
+            &cassette,
+        );
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+        let client = tokio::spawn(async move {
+            rx.recv().await;
+        });
+
+        let result = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            recorder.chat_streaming(&user_message("Count"), ChatOptions::default(), tx),
+        )
+        .await
+        .expect("the inner stream kept waiting on the relay");
+        client.await.unwrap();
+        assert!(matches!(result, Err(LlmError::Cancelled)));
+        assert!(recorder.cassette().interactions.is_empty());
+        std::fs::remove_file(&cassette).ok();
+    }
+
+    #[tokio::test]
+    async fn test_replayed_chunks_feed_raw_tool_call_extraction() {
+        let replay = RecordingProvider::replay("tests/fixtures/raw_tool_call.cassette.json").unwrap();
+
+        let (chunks, result) = collect(&replay, &user_message("What is new in actix-web?")).await;
+        assert_eq!(chunks.len(), 5);
+        assert_eq!(result.provider.as_deref(), Some("stepbit-core"));
+
+        // The tool call JSON is split across chunks and only parses once complete
+        assert!(extract_streaming_tool_call(&chunks[..3].concat()).is_none());
+        let (tools, text_before) = extract_streaming_tool_call(&chunks.concat()).unwrap();
+        assert_eq!(text_before, "Let me check.");
+        assert_eq!(tools[0].function.name, "internet_search");
+        assert_eq!(tools[0].function.arguments, "{\"query\":\"actix-web release\"}");
+    }
+}
@@ -0,0 +1,140 @@
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use stepbit::config::ChatConfig;
+    use stepbit::db::models::Message as DbMessage;
+    use stepbit::llm::context::{ContextBuilder, Summary};
+    use stepbit::llm::models::ChatOptions;
+    use stepbit::llm::openai::OpenAiProvider;
+    use wiremock::matchers::{body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn chat_config(max_history_messages: u32) -> ChatConfig {
+        ChatConfig {
+            max_history_messages,
+            system_prompt: String::new(),
+            max_tool_result_tokens: 50,
+            default_context_tokens: 8192,
+            summarize_history: true,
+            thinking_budget: 4096,
+        }
+    }
+
+    fn message(id: i64, role: &str, content: &str) -> DbMessage {
+        DbMessage {
+            id,
+            session_id: uuid::Uuid::nil(),
+            role: role.to_string(),
+            content: content.to_string(),
+            model: None,
+            token_count: None,
+            created_at: chrono::Utc::now(),
+            metadata: json!({}),
+            usage: Default::default(),
+        }
+    }
+
+    fn history() -> Vec<DbMessage> {
+        vec![
+            message(1, "user", "I want to visit Lyon in May."),
+            message(2, "assistant", "Lyon is lovely in May."),
+            message(3, "user", "What is the weather like?"),
+            DbMessage {
+                metadata: json!({ "tool_calls": [{
+                    "id": "call_1",
+                    "type": "function",
+                    "function": { "name": "internet_search", "arguments": "{}" }
+                }] }),
+                ..message(4, "assistant", "")
+            },
+            DbMessage {
+                metadata: json!({ "tool_call_id": "call_1" }),
+                ..message(5, "tool", &"sunny ".repeat(200))
+            },
+            message(6, "assistant", "Mostly sunny, around 20 degrees."),
+            message(7, "user", "And in June?"),
+        ]
+    }
+
+    fn provider(server: &MockServer, model: &str) -> OpenAiProvider {
+        OpenAiProvider::new("sk-test".to_string(), server.uri(), model.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_window_keeps_recent_turns_and_elides_tool_results() {
+        let mock_server = MockServer::start().await;
+        let llm = provider(&mock_server, "gpt-4o");
+        let config = chat_config(3);
+        let builder = ContextBuilder::new(&llm, &config);
+
+        // The limit would open the window on the tool result, its call is not there
+        let (start, messages) = builder.window(&history(), 100_000, 3);
+        assert_eq!(start, 5);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].content.text(), "Mostly sunny, around 20 degrees.");
+
+        let (start, messages) = builder.window(&history(), 100_000, 4);
+        assert_eq!(start, 3);
+        assert_eq!(messages[0].tool_calls.as_ref().unwrap().len(), 1);
+        let tool_result = messages[1].content.text();
+        assert_eq!(messages[1].tool_call_id.as_deref(), Some("call_1"));
+        assert!(tool_result.contains("characters elided"));
+        assert!(tool_result.len() < 300);
+
+        // A tight budget still keeps the newest message
+        let (start, _) = builder.window(&history(), 1, 10);
+        assert_eq!(start, 6);
+    }
+
+    #[tokio::test]
+    async fn test_budget_follows_the_model_context_length() {
+        let mock_server = MockServer::start().await;
+        let config = chat_config(50);
+        let options = ChatOptions {
+            max_tokens: Some(1000),
+            ..Default::default()
+        };
+
+        let known = provider(&mock_server, "gpt-4o");
+        assert_eq!(ContextBuilder::new(&known, &config).budget(&options), 128000 - 1000);
+
+        let unknown = provider(&mock_server, "some-local-model");
+        assert_eq!(ContextBuilder::new(&unknown, &config).budget(&options), 8192 - 1000);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_history_is_folded_into_the_summary() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_string_contains("running summary"))
+            .and(body_string_contains("The user likes trains."))
+            .and(body_string_contains("I want to visit Lyon in May."))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "model": "gpt-4o",
+                "choices": [{ "message": {
+                    "role": "assistant",
+                    "content": "The user likes trains and plans to visit Lyon in May."
+                } }]
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let llm = provider(&mock_server, "gpt-4o");
+        let config = chat_config(4);
+        let previous = Summary {
+            content: "The user likes trains.".to_string(),
+            through_id: 0,
+        };
+        let context = ContextBuilder::new(&llm, &config)
+            .build_from(&history(), Some(previous), &ChatOptions::default())
+            .await;
+
+        // Folding takes a batch, leaving room for the next turns
+        assert_eq!(context.messages.len(), 2);
+        let summary = context.summary.unwrap();
+        assert_eq!(summary.through_id, 5);
+        assert_eq!(summary.content, "The user likes trains and plans to visit Lyon in May.");
+    }
+}
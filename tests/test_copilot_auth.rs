@@ -0,0 +1,191 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::user_message;
+    use serde_json::json;
+    use stepbit::llm::copilot::{self, CopilotProvider};
+    use stepbit::llm::{
+        models::ChatOptions,
+        ErrorKind, LlmProvider,
+    };
+    use wiremock::matchers::{body_partial_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn session_token(token: &str, expires_in: i64) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(json!({
+            "token": token,
+            "expires_at": chrono::Utc::now().timestamp() + expires_in,
+            "refresh_in": 1500
+        }))
+    }
+
+    fn chat_reply() -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(json!({
+            "model": "gpt-4o",
+            "choices": [{ "message": { "role": "assistant", "content": "Hi" } }]
+        }))
+    }
+
+    fn provider(server: &MockServer) -> CopilotProvider {
+        CopilotProvider::new("gho_oauth".to_string(), server.uri(), "gpt-4o".to_string())
+            .with_token_url(&format!("{}/copilot_internal/v2/token", server.uri()))
+    }
+
+    #[tokio::test]
+    async fn test_session_token_is_exchanged_once_and_reused() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/copilot_internal/v2/token"))
+            .and(header("Authorization", "token gho_oauth"))
+            .respond_with(session_token("tid=first", 1800))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(header("Authorization", "Bearer tid=first"))
+            .respond_with(chat_reply())
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let provider = provider(&mock_server);
+        provider.chat(&user_message("Hello"), ChatOptions::default()).await.unwrap();
+        provider.chat(&user_message("Hello"), ChatOptions::default()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_expiring_token_is_refreshed() {
+        let mock_server = MockServer::start().await;
+        // Expires within the refresh margin, so the next call exchanges again
+        Mock::given(method("GET"))
+            .and(path("/copilot_internal/v2/token"))
+            .respond_with(session_token("tid=expiring", 30))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/copilot_internal/v2/token"))
+            .respond_with(session_token("tid=fresh", 1800))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(chat_reply())
+            .mount(&mock_server)
+            .await;
+
+        let provider = provider(&mock_server);
+        provider.chat(&user_message("Hello"), ChatOptions::default()).await.unwrap();
+        provider.chat(&user_message("Hello"), ChatOptions::default()).await.unwrap();
+
+        let requests = mock_server.received_requests().await.unwrap();
+        let exchanges = requests.iter().filter(|r| r.url.path() == "/copilot_internal/v2/token").count();
+        assert_eq!(exchanges, 2);
+        let last = requests.last().unwrap();
+        assert_eq!(last.headers.get("Authorization").unwrap(), "Bearer tid=fresh");
+    }
+
+    #[tokio::test]
+    async fn test_unauthorized_chat_retries_with_a_new_token() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/copilot_internal/v2/token"))
+            .respond_with(session_token("tid=revoked", 1800))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/copilot_internal/v2/token"))
+            .respond_with(session_token("tid=fresh", 1800))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(header("Authorization", "Bearer tid=revoked"))
+            .respond_with(ResponseTemplate::new(401))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(header("Authorization", "Bearer tid=fresh"))
+            .respond_with(chat_reply())
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let response = provider(&mock_server).chat(&user_message("Hello"), ChatOptions::default()).await.unwrap();
+        assert_eq!(response.content, "Hi");
+
+        // Without an OAuth token there is nothing to exchange
+        let signed_out = CopilotProvider::new(String::new(), mock_server.uri(), "gpt-4o".to_string());
+        let error = signed_out.chat(&user_message("Hello"), ChatOptions::default()).await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Auth);
+        assert!(error.to_string().contains("stepbit auth copilot"));
+    }
+
+    #[tokio::test]
+    async fn test_device_flow_polls_until_approved() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/login/device/code"))
+            .and(body_partial_json(json!({ "client_id": "test-client" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "device_code": "dev-123",
+                "user_code": "ABCD-1234",
+                "verification_uri": "https://github.com/login/device",
+                "expires_in": 900,
+                "interval": 0
+            })))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/login/oauth/access_token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "error": "authorization_pending" })))
+            .up_to_n_times(2)
+            .with_priority(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/login/oauth/access_token"))
+            .and(body_partial_json(json!({
+                "device_code": "dev-123",
+                "grant_type": "urn:ietf:params:oauth:grant-type:device_code"
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "access_token": "gho_new",
+                "token_type": "bearer"
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let client = reqwest::Client::new();
+        let code = copilot::request_device_code(
+            &client,
+            &format!("{}/login/device/code", mock_server.uri()),
+            "test-client",
+        )
+        .await
+        .unwrap();
+        assert_eq!(code.user_code, "ABCD-1234");
+
+        let token = copilot::poll_access_token(
+            &client,
+            &format!("{}/login/oauth/access_token", mock_server.uri()),
+            "test-client",
+            &code,
+        )
+        .await
+        .unwrap();
+        assert_eq!(token, "gho_new");
+
+        let file = std::env::temp_dir().join(format!("stepbit-copilot-{}.json", uuid::Uuid::new_v4()));
+        copilot::save_oauth_token(&file, &token).unwrap();
+        assert_eq!(copilot::load_oauth_token(&file).as_deref(), Some("gho_new"));
+        std::fs::remove_file(&file).ok();
+    }
+}
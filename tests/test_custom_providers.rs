@@ -0,0 +1,106 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::user_message;
+    use serde_json::json;
+    use stepbit::config::AppConfig;
+    use stepbit::llm::{
+        models::ChatOptions,
+        ProviderFactory, ProviderManager,
+    };
+    use wiremock::matchers::{body_partial_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn completion(content: &str, model: &str) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(json!({
+            "model": model,
+            "choices": [{ "message": { "role": "assistant", "content": content } }]
+        }))
+    }
+
+    fn load_config(yaml: &str) -> AppConfig {
+        let path = std::env::temp_dir().join(format!("stepbit-custom-{}.yaml", uuid::Uuid::new_v4()));
+        std::fs::write(&path, yaml).unwrap();
+        let config = AppConfig::load(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).ok();
+        config
+    }
+
+    #[tokio::test]
+    async fn test_custom_entries_are_registered_and_selectable() {
+        let llama = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .and(body_partial_json(json!({ "model": "qwen2.5-7b" })))
+            .respond_with(completion("From llama.cpp", "qwen2.5-7b"))
+            .mount(&llama)
+            .await;
+        let vllm = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .and(header("authorization", "Bearer vllm-key"))
+            .and(header("x-team", "research"))
+            .respond_with(completion("From vLLM", "llama-3.1-8b"))
+            .mount(&vllm)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1/models"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "data": [{ "id": "llama-3.1-8b" }, { "id": "llama-3.1-70b" }]
+            })))
+            .mount(&vllm)
+            .await;
+
+        let config = load_config(&format!(
+            r#"
+server: {{ host: "127.0.0.1", port: 8080 }}
+database: {{ path: ":memory:" }}
+auth: {{ api_keys: [], token_expiry_hours: 1 }}
+chat: {{ max_history_messages: 10, system_prompt: "" }}
+llm:
+  provider: "llama-cpp"
+  model: "qwen2.5-7b"
+  custom:
+    - id: "llama-cpp"
+      api_base: "{}/v1"
+      default_model: "qwen2.5-7b"
+    - id: "vllm"
+      api_base: "{}/v1"
+      api_key: "vllm-key"
+      default_model: "llama-3.1-8b"
+      headers: {{ "X-Team": "research" }}
+"#,
+            llama.uri(),
+            vllm.uri()
+        ));
+
+        let llm = ProviderFactory::create_all(&config);
+        let manager = llm.as_any().downcast_ref::<ProviderManager>().unwrap();
+        let mut ids = manager.list_providers();
+        ids.sort();
+        assert_eq!(ids, vec!["llama-cpp", "vllm"]);
+
+        let messages = user_message("Hi");
+
+        let response = llm.chat(&messages, ChatOptions::default()).await.unwrap();
+        assert_eq!(response.content, "From llama.cpp");
+        assert_eq!(response.provider.as_deref(), Some("llama-cpp"));
+        // No key configured, so no Authorization header is sent
+        let requests = llama.received_requests().await.unwrap();
+        assert!(requests[0].headers.get("authorization").is_none());
+
+        manager.set_active_provider("vllm").unwrap();
+        let response = llm.chat(&messages, ChatOptions::default()).await.unwrap();
+        assert_eq!(response.content, "From vLLM");
+        assert_eq!(response.provider.as_deref(), Some("vllm"));
+
+        // Named instances list every model of their server, not only GPT ones
+        let vllm_provider = manager.get_provider("vllm").unwrap();
+        assert_eq!(vllm_provider.name(), "vllm");
+        assert_eq!(
+            vllm_provider.discover_models().await.unwrap(),
+            vec!["llama-3.1-8b", "llama-3.1-70b"]
+        );
+    }
+}
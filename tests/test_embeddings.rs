@@ -0,0 +1,136 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use serde_json::json;
+    use stepbit::llm::anthropic::AnthropicProvider;
+    use stepbit::llm::ollama::OllamaProvider;
+    use stepbit::llm::openai::OpenAiProvider;
+    use stepbit::llm::stepbit_core::StepbitCoreProvider;
+    use stepbit::llm::{ErrorKind, LlmError, LlmProvider, ProviderManager};
+    use wiremock::matchers::{body_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn inputs() -> Vec<String> {
+        vec!["first".to_string(), "second".to_string()]
+    }
+
+    #[tokio::test]
+    async fn test_openai_embeddings_follow_input_order() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/embeddings"))
+            .and(header("Authorization", "Bearer sk-test"))
+            .and(body_json(json!({ "model": "text-embedding-3-small", "input": ["first", "second"] })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "object": "list",
+                "data": [
+                    { "object": "embedding", "index": 1, "embedding": [0.5, 0.25] },
+                    { "object": "embedding", "index": 0, "embedding": [1.0, -1.0] }
+                ],
+                "usage": { "prompt_tokens": 2, "total_tokens": 2 }
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let provider = OpenAiProvider::new("sk-test".to_string(), mock_server.uri(), "gpt-4o".to_string());
+        let vectors = provider.embed(&inputs(), None).await.unwrap();
+        assert_eq!(vectors, vec![vec![1.0, -1.0], vec![0.5, 0.25]]);
+        assert!(provider.embed(&[], None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_ollama_and_stepbit_core_embeddings() {
+        let ollama = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/embed"))
+            .and(body_json(json!({ "model": "mxbai-embed-large", "input": ["first", "second"] })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "model": "mxbai-embed-large",
+                "embeddings": [[0.1, 0.2], [0.3, 0.4]]
+            })))
+            .mount(&ollama)
+            .await;
+        let provider = OllamaProvider::new(ollama.uri(), "llama3.2".to_string());
+        let vectors = provider
+            .embed(&inputs(), Some("mxbai-embed-large".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(vectors, vec![vec![0.1, 0.2], vec![0.3, 0.4]]);
+
+        let core = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/embeddings"))
+            .and(header("Authorization", "Bearer test-token"))
+            .and(body_json(json!({ "model": "phi-4", "input": ["first", "second"] })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "data": [
+                    { "index": 0, "embedding": [1.0] },
+                    { "index": 1, "embedding": [2.0] }
+                ]
+            })))
+            .mount(&core)
+            .await;
+        let provider = StepbitCoreProvider::new(core.uri(), "phi-4".to_string(), Some("test-token".to_string()));
+        let vectors = provider.embed(&inputs(), None).await.unwrap();
+        assert_eq!(vectors, vec![vec![1.0], vec![2.0]]);
+    }
+
+    #[tokio::test]
+    async fn test_manager_routes_embeddings_independently_of_chat() {
+        let openai = MockServer::start().await;
+        let ollama = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/embed"))
+            .and(body_json(json!({ "model": "nomic-embed-text", "input": ["first", "second"] })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "embeddings": [[0.1], [0.2]]
+            })))
+            .expect(1)
+            .mount(&ollama)
+            .await;
+
+        let mut providers: HashMap<String, Arc<dyn LlmProvider>> = HashMap::new();
+        providers.insert(
+            "openai".to_string(),
+            Arc::new(OpenAiProvider::new("sk-test".to_string(), openai.uri(), "gpt-4o".to_string())),
+        );
+        providers.insert(
+            "ollama".to_string(),
+            Arc::new(OllamaProvider::new(ollama.uri(), "llama3.2".to_string())),
+        );
+        let manager = ProviderManager::new(providers, "openai".to_string())
+            .with_embeddings("ollama".to_string(), Some("nomic-embed-text".to_string()));
+
+        assert_eq!(manager.get_active_provider_id(), "openai");
+        let vectors = manager.embed(&inputs(), None).await.unwrap();
+        assert_eq!(vectors, vec![vec![0.1], vec![0.2]]);
+        assert!(openai.received_requests().await.unwrap().is_empty());
+
+        // A count mismatch is an error rather than silently misaligned vectors
+        let mismatched = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/embeddings"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "data": [{ "index": 0, "embedding": [1.0] }]
+            })))
+            .mount(&mismatched)
+            .await;
+        let provider = OpenAiProvider::new("sk-test".to_string(), mismatched.uri(), "gpt-4o".to_string());
+        let error = provider.embed(&inputs(), None).await.unwrap_err();
+        assert!(error.to_string().contains("Expected 2 embeddings but received 1"));
+    }
+
+    #[tokio::test]
+    async fn test_providers_without_embeddings_report_unsupported() {
+        let provider = AnthropicProvider::new(
+            "test-key".to_string(),
+            "http://127.0.0.1:9".to_string(),
+            "claude-3-5-sonnet-20241022".to_string(),
+        );
+        let error = provider.embed(&inputs(), None).await.unwrap_err();
+        assert!(matches!(error, LlmError::Unsupported(_)));
+        assert_eq!(error.kind(), ErrorKind::InvalidRequest);
+    }
+}
@@ -0,0 +1,136 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::user_message;
+    use actix_web::{body::MessageBody, http::StatusCode, ResponseError};
+    use serde_json::json;
+    use stepbit::llm::anthropic::AnthropicProvider;
+    use stepbit::llm::ollama::OllamaProvider;
+    use stepbit::llm::openai::OpenAiProvider;
+    use stepbit::llm::{
+        models::ChatOptions,
+        ErrorKind, LlmError, LlmProvider,
+    };
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn chat_error(provider: &dyn LlmProvider) -> LlmError {
+        provider
+            .chat(&user_message("Hi"), ChatOptions::default())
+            .await
+            .unwrap_err()
+    }
+
+    async fn server_answering(response: ResponseTemplate) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("POST")).respond_with(response).mount(&server).await;
+        server
+    }
+
+    #[tokio::test]
+    async fn test_openai_context_length_error() {
+        let server = server_answering(ResponseTemplate::new(400).set_body_json(json!({
+            "error": {
+                "message": "This model's maximum context length is 128000 tokens.",
+                "type": "invalid_request_error",
+                "param": "messages",
+                "code": "context_length_exceeded"
+            }
+        })))
+        .await;
+        let provider = OpenAiProvider::new("sk-test".to_string(), server.uri(), "gpt-4o".to_string());
+
+        let err = chat_error(&provider).await;
+        assert_eq!(err.kind(), ErrorKind::ContextLengthExceeded);
+        assert_eq!(err.status(), Some(400));
+        assert_eq!(err.provider(), Some("openai"));
+        assert!(!err.is_retryable());
+        assert!(err.to_string().contains("maximum context length"));
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_openai_quota_is_not_retryable() {
+        let server = server_answering(ResponseTemplate::new(429).set_body_json(json!({
+            "error": { "message": "You exceeded your current quota.", "type": "insufficient_quota", "code": "insufficient_quota" }
+        })))
+        .await;
+        let provider = OpenAiProvider::new("sk-test".to_string(), server.uri(), "gpt-4o".to_string());
+
+        let err = chat_error(&provider).await;
+        assert_eq!(err.kind(), ErrorKind::Quota);
+        assert!(!err.is_retryable());
+        assert_eq!(err.status_code(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_auth_and_overloaded_errors() {
+        let server = server_answering(ResponseTemplate::new(401).set_body_json(json!({
+            "type": "error",
+            "error": { "type": "authentication_error", "message": "invalid x-api-key" }
+        })))
+        .await;
+        let provider = AnthropicProvider::new("bad".to_string(), server.uri(), "claude-3-5-sonnet-20241022".to_string());
+
+        let err = chat_error(&provider).await;
+        assert_eq!(err.kind(), ErrorKind::Auth);
+        assert_eq!(err.provider(), Some("anthropic"));
+
+        let response = err.error_response();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        let body: serde_json::Value =
+            serde_json::from_slice(&response.into_body().try_into_bytes().unwrap()).unwrap();
+        assert_eq!(body["error"]["type"], "authentication_error");
+        assert_eq!(body["error"]["code"], "auth");
+        assert_eq!(body["error"]["upstream_status"], 401);
+
+        let server = server_answering(ResponseTemplate::new(529).set_body_json(json!({
+            "type": "error",
+            "error": { "type": "overloaded_error", "message": "Overloaded" }
+        })))
+        .await;
+        let provider = AnthropicProvider::new("key".to_string(), server.uri(), "claude-3-5-sonnet-20241022".to_string());
+
+        let err = chat_error(&provider).await;
+        assert_eq!(err.kind(), ErrorKind::Server);
+        assert!(err.is_retryable());
+        assert_eq!(err.status_code(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_stream_error_events_are_classified() {
+        for (error_type, kind) in [("overloaded_error", ErrorKind::Server), ("rate_limit_error", ErrorKind::RateLimited)] {
+            let event = json!({ "type": "error", "error": { "type": error_type, "message": "Try again later" } });
+            let server = server_answering(
+                ResponseTemplate::new(200).set_body_raw(format!("event: error\ndata: {}\n\n", event), "text/event-stream"),
+            )
+            .await;
+            let provider = AnthropicProvider::new("key".to_string(), server.uri(), "claude-3-5-sonnet-20241022".to_string());
+
+            let (tx, _rx) = tokio::sync::mpsc::channel(10);
+            let err = provider
+                .chat_streaming(&user_message("Hi"), ChatOptions::default(), tx)
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind(), kind);
+            assert!(err.is_retryable());
+            assert_eq!(err.provider(), Some("anthropic"));
+            assert!(err.to_string().ends_with("Try again later"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ollama_string_error() {
+        let server = server_answering(
+            ResponseTemplate::new(404).set_body_json(json!({ "error": "model 'llama9' not found" })),
+        )
+        .await;
+        let provider = OllamaProvider::new(server.uri(), "llama9".to_string());
+
+        let err = chat_error(&provider).await;
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert!(err.to_string().ends_with("model 'llama9' not found"));
+        assert_eq!(err.status_code(), StatusCode::NOT_FOUND);
+    }
+}
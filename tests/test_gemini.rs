@@ -0,0 +1,296 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::message;
+    use stepbit::llm::gemini::GeminiProvider;
+    use stepbit::llm::{
+        models::{ChatOptions, FunctionCall, FunctionDefinition, Message, ToolCall, ToolDefinition},
+        ErrorKind, LlmProvider,
+    };
+    use serde_json::json;
+    use wiremock::matchers::{body_partial_json, header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn provider(server: &MockServer) -> GeminiProvider {
+        GeminiProvider::new("test-key".to_string(), server.uri(), "gemini-2.5-pro".to_string())
+    }
+
+    fn search_tool() -> ToolDefinition {
+        ToolDefinition {
+            r#type: "function".to_string(),
+            function: FunctionDefinition {
+                name: "internet_search".to_string(),
+                description: "Search the web".to_string(),
+                parameters: json!({
+                    "type": "object",
+                    "properties": { "query": { "type": "string" } },
+                    "required": ["query"]
+                }),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_gemini_chat_returns_function_call() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/models/gemini-2.5-pro:generateContent"))
+            .and(header("x-goog-api-key", "test-key"))
+            .and(body_partial_json(json!({
+                "systemInstruction": { "parts": [{ "text": "Be brief." }] },
+                "contents": [{ "role": "user", "parts": [{ "text": "Find actix docs" }] }],
+                "tools": [{
+                    "functionDeclarations": [{ "name": "internet_search", "description": "Search the web" }]
+                }],
+                "toolConfig": { "functionCallingConfig": { "mode": "ANY" } }
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "candidates": [{
+                    "content": {
+                        "role": "model",
+                        "parts": [
+                            { "text": "Let me look that up." },
+                            { "functionCall": { "name": "internet_search", "args": { "query": "rust actix" } } }
+                        ]
+                    },
+                    "finishReason": "STOP"
+                }],
+                "usageMetadata": { "promptTokenCount": 12, "candidatesTokenCount": 7, "totalTokenCount": 19 },
+                "modelVersion": "gemini-2.5-pro-preview-06-05"
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let options = ChatOptions {
+            system_prompt: Some("Be brief.".to_string()),
+            tools: Some(vec![search_tool()]),
+            tool_choice: Some(json!("required")),
+            ..Default::default()
+        };
+
+        let response = provider(&mock_server)
+            .chat(&[message("user", "Find actix docs")], options)
+            .await
+            .unwrap();
+        assert_eq!(response.content, "Let me look that up.");
+        assert_eq!(response.model, "gemini-2.5-pro");
+
+        let tool_calls = response.tool_calls.unwrap();
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].id.as_deref(), Some("call_0"));
+        assert_eq!(tool_calls[0].function.name, "internet_search");
+        assert_eq!(tool_calls[0].function.arguments, "{\"query\":\"rust actix\"}");
+
+        let usage = response.usage.unwrap();
+        assert_eq!(usage.input_tokens, 12);
+        assert_eq!(usage.output_tokens, 7);
+    }
+
+    #[tokio::test]
+    async fn test_gemini_chat_maps_tool_results_by_name() {
+        let mock_server = MockServer::start().await;
+
+        // Tool results become functionResponse parts named after the call they answer,
+        // merged into a single user turn.
+        Mock::given(method("POST"))
+            .and(path("/models/gemini-2.5-pro:generateContent"))
+            .and(body_partial_json(json!({
+                "contents": [
+                    { "role": "user", "parts": [{ "text": "Compare both" }] },
+                    {
+                        "role": "model",
+                        "parts": [
+                            { "functionCall": { "name": "internet_search", "args": { "query": "a" } } },
+                            { "functionCall": { "name": "read_url", "args": { "url": "b" } } }
+                        ]
+                    },
+                    {
+                        "role": "user",
+                        "parts": [
+                            { "functionResponse": { "name": "internet_search", "response": { "content": "result a" } } },
+                            { "functionResponse": { "name": "read_url", "response": { "title": "b" } } }
+                        ]
+                    }
+                ]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "candidates": [{ "content": { "role": "model", "parts": [{ "text": "A is better." }] } }]
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let call = |id: &str, name: &str, args: serde_json::Value| ToolCall {
+            id: Some(id.to_string()),
+            r#type: Some("function".to_string()),
+            function: FunctionCall {
+                name: name.to_string(),
+                arguments: args.to_string(),
+            },
+        };
+        let messages = vec![
+            message("user", "Compare both"),
+            Message {
+                tool_calls: Some(vec![
+                    call("call_0", "internet_search", json!({ "query": "a" })),
+                    call("call_1", "read_url", json!({ "url": "b" })),
+                ]),
+                ..message("assistant", "")
+            },
+            Message {
+                tool_call_id: Some("call_0".to_string()),
+                ..message("tool", "result a")
+            },
+            Message {
+                tool_call_id: Some("call_1".to_string()),
+                ..message("tool", "{\"title\": \"b\"}")
+            },
+        ];
+
+        let response = provider(&mock_server)
+            .chat(&messages, ChatOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(response.content, "A is better.");
+        assert!(response.tool_calls.is_none());
+        assert!(response.usage.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_gemini_streaming_text_function_call_and_usage() {
+        let mock_server = MockServer::start().await;
+
+        let chunks = [
+            json!({
+                "candidates": [{ "content": { "role": "model", "parts": [{ "text": "Searching" }] } }],
+                "usageMetadata": { "promptTokenCount": 20, "candidatesTokenCount": 1 }
+            }),
+            json!({
+                "candidates": [{ "content": { "role": "model", "parts": [{ "text": " now." }] } }],
+                "usageMetadata": { "promptTokenCount": 20, "candidatesTokenCount": 3 }
+            }),
+            json!({
+                "candidates": [{
+                    "content": {
+                        "role": "model",
+                        "parts": [{ "functionCall": { "name": "internet_search", "args": { "query": "rust" } } }]
+                    },
+                    "finishReason": "STOP"
+                }],
+                "usageMetadata": { "promptTokenCount": 20, "candidatesTokenCount": 15 }
+            }),
+        ];
+        let body: String = chunks.iter().map(|c| format!("data: {}\r\n\r\n", c)).collect();
+
+        Mock::given(method("POST"))
+            .and(path("/models/gemini-2.5-flash:streamGenerateContent"))
+            .and(query_param("alt", "sse"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&mock_server)
+            .await;
+
+        let options = ChatOptions {
+            model: Some("gemini-2.5-flash".to_string()),
+            tools: Some(vec![search_tool()]),
+            ..Default::default()
+        };
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let result = provider(&mock_server)
+            .chat_streaming(&[message("user", "Find actix docs")], options, tx)
+            .await
+            .unwrap();
+
+        let mut text = String::new();
+        while let Some(chunk) = rx.recv().await {
+            text.push_str(chunk.text().unwrap_or_default());
+        }
+        assert_eq!(text, "Searching now.");
+
+        let tool_calls = result.tool_calls.unwrap();
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].function.name, "internet_search");
+        assert_eq!(tool_calls[0].function.arguments, "{\"query\":\"rust\"}");
+
+        let usage = result.usage.unwrap();
+        assert_eq!(usage.input_tokens, 20);
+        assert_eq!(usage.output_tokens, 15);
+    }
+
+    #[tokio::test]
+    async fn test_gemini_discovers_generate_content_models() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/models"))
+            .and(header("x-goog-api-key", "test-key"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "models": [
+                    { "name": "models/gemini-2.5-pro", "supportedGenerationMethods": ["generateContent", "countTokens"] },
+                    { "name": "models/text-embedding-004", "supportedGenerationMethods": ["embedContent"] },
+                    { "name": "models/gemini-2.5-flash", "supportedGenerationMethods": ["generateContent"] }
+                ]
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let provider = provider(&mock_server);
+        let models = provider.discover_models().await.unwrap();
+        assert_eq!(models, vec!["gemini-2.5-pro", "gemini-2.5-flash"]);
+        provider.verify_connection().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_gemini_errors_are_classified() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/models/gemini-2.5-pro:generateContent"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "promptFeedback": { "blockReason": "SAFETY" }
+            })))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/models/gemini-2.5-flash:generateContent"))
+            .respond_with(ResponseTemplate::new(429).set_body_json(json!({
+                "error": { "code": 429, "message": "Quota exceeded for metric", "status": "RESOURCE_EXHAUSTED" }
+            })))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/models/gemini-2.0-flash:generateContent"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "usageMetadata": { "promptTokenCount": 3 }
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let provider = provider(&mock_server);
+        let messages = [message("user", "Hi")];
+
+        let blocked = provider.chat(&messages, ChatOptions::default()).await.unwrap_err();
+        assert_eq!(blocked.kind(), ErrorKind::ContentFilter);
+        assert_eq!(blocked.provider(), Some("gemini"));
+
+        let options = ChatOptions {
+            model: Some("gemini-2.5-flash".to_string()),
+            ..Default::default()
+        };
+        let limited = provider.chat(&messages, options).await.unwrap_err();
+        assert_eq!(limited.kind(), ErrorKind::RateLimited);
+        assert_eq!(limited.status(), Some(429));
+        assert!(limited.to_string().contains("Quota exceeded for metric"));
+
+        // An empty reply that was not blocked is the upstream's fault
+        let options = ChatOptions {
+            model: Some("gemini-2.0-flash".to_string()),
+            ..Default::default()
+        };
+        let empty = provider.chat(&messages, options).await.unwrap_err();
+        assert_eq!(empty.kind(), ErrorKind::Server);
+        assert!(empty.is_retryable());
+        assert!(empty.to_string().contains("no candidates"));
+    }
+}
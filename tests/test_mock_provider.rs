@@ -0,0 +1,124 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::message;
+    use serde_json::json;
+    use stepbit::llm::mock::MockProvider;
+    use stepbit::llm::{
+        models::{ChatOptions, Message},
+        LlmProvider,
+    };
+
+    fn script_file(extension: &str, contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("stepbit-mock-{}.{}", uuid::Uuid::new_v4(), extension));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn bundled_script() -> MockProvider {
+        MockProvider::from_file("mock_script.yaml", "mock".to_string()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_mock_drives_a_tool_loop() {
+        let provider = bundled_script();
+        let mut messages = vec![message("user", "Please search for stepbit")];
+
+        let first = provider.chat(&messages, ChatOptions::default()).await.unwrap();
+        let tool_calls = first.tool_calls.unwrap();
+        assert_eq!(tool_calls[0].function.name, "internet_search");
+        assert_eq!(tool_calls[0].function.arguments, "{\"query\":\"stepbit\"}");
+
+        messages.push(Message {
+            tool_calls: Some(tool_calls.clone()),
+            ..message("assistant", &first.content)
+        });
+        messages.push(Message {
+            tool_call_id: tool_calls[0].id.clone(),
+            ..message("tool", "Stepbit is a chat server.")
+        });
+
+        let second = provider.chat(&messages, ChatOptions::default()).await.unwrap();
+        assert_eq!(second.content, "Here is what I found in the search results.");
+        assert!(second.tool_calls.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_mock_streams_scripted_chunks_and_usage() {
+        let provider = bundled_script();
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let result = provider
+            .chat_streaming(&[message("user", "hello there")], ChatOptions::default(), tx)
+            .await
+            .unwrap();
+
+        let mut chunks = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            chunks.extend(chunk.text().map(str::to_string));
+        }
+        assert_eq!(chunks, vec!["Hello! ", "This is the ", "Stepbit mock provider."]);
+        let usage = result.usage.unwrap();
+        assert_eq!((usage.input_tokens, usage.output_tokens), (5, 8));
+
+        // Unscripted messages get the fallback, streamed word by word
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        provider
+            .chat_streaming(&[message("user", "what now?")], ChatOptions::default(), tx)
+            .await
+            .unwrap();
+        let mut chunks = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            chunks.extend(chunk.text().map(str::to_string));
+        }
+        assert_eq!(chunks.len(), 10);
+        assert_eq!(chunks.concat(), "The mock provider has no scripted reply for this message.");
+    }
+
+    #[tokio::test]
+    async fn test_mock_json_script_and_latency() {
+        let path = script_file(
+            "json",
+            &json!({
+                "rules": [{
+                    "match": "(?i)ping",
+                    "content": "pong",
+                    "latency_ms": 150,
+                    "tool_calls": [{ "id": "call_1", "name": "lookup", "arguments": { "userId": 7 } }]
+                }]
+            })
+            .to_string(),
+        );
+        let provider = MockProvider::from_file(path.to_str().unwrap(), "mock-large".to_string()).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        let started = std::time::Instant::now();
+        let response = provider
+            .chat(&[message("user", "PING")], ChatOptions::default())
+            .await
+            .unwrap();
+        assert!(started.elapsed() >= std::time::Duration::from_millis(150));
+        assert_eq!(response.content, "pong");
+        assert_eq!(response.model, "mock-large");
+        let tool_calls = response.tool_calls.unwrap();
+        assert_eq!(tool_calls[0].id.as_deref(), Some("call_1"));
+        assert_eq!(tool_calls[0].function.arguments, "{\"userId\":7}");
+
+        // Without a fallback the provider echoes the message
+        let response = provider
+            .chat(&[message("user", "anything else")], ChatOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(response.content, "Mock reply to: anything else");
+        assert_eq!(response.usage.unwrap().input_tokens, 2);
+    }
+
+    #[test]
+    fn test_mock_rejects_invalid_pattern() {
+        let path = script_file("yaml", "rules:\n  - match: \"(unclosed\"\n    content: x\n");
+        let result = MockProvider::from_file(path.to_str().unwrap(), "mock".to_string());
+        std::fs::remove_file(&path).ok();
+        assert!(result.err().unwrap().to_string().contains("Invalid mock rule pattern"));
+    }
+}
@@ -0,0 +1,154 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use serde_json::json;
+    use stepbit::db::models::MessageUsage;
+    use stepbit::llm::anthropic::AnthropicProvider;
+    use stepbit::llm::models::Usage;
+    use stepbit::llm::ollama::OllamaProvider;
+    use stepbit::llm::registry::ModelInfo;
+    use stepbit::llm::{LlmProvider, ProviderManager};
+    use wiremock::matchers::{body_partial_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn manager(ollama: &MockServer) -> ProviderManager {
+        let mut providers: HashMap<String, Arc<dyn LlmProvider>> = HashMap::new();
+        providers.insert(
+            "ollama".to_string(),
+            Arc::new(OllamaProvider::new(ollama.uri(), "llama3.2".to_string())),
+        );
+        providers.insert(
+            "anthropic".to_string(),
+            Arc::new(AnthropicProvider::new(
+                "test-key".to_string(),
+                "http://127.0.0.1:9".to_string(),
+                "claude-3-5-sonnet-20241022".to_string(),
+            )),
+        );
+        ProviderManager::new(providers, "ollama".to_string())
+    }
+
+    #[tokio::test]
+    async fn test_ollama_show_enriches_the_registry_once() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/show"))
+            .and(body_partial_json(json!({ "model": "llava" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "capabilities": ["completion", "vision"],
+                "model_info": { "general.architecture": "llama", "llama.context_length": 4096 }
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let manager = manager(&mock_server);
+        let models = vec!["llava".to_string()];
+        let infos = manager.describe_models("ollama", &models).await;
+        assert_eq!(
+            infos,
+            vec![ModelInfo {
+                context_length: Some(4096),
+                supports_tools: Some(false),
+                supports_vision: Some(true),
+                supports_thinking: Some(false),
+                supports_streaming: Some(true),
+                input_price_per_million: Some(0.0),
+                output_price_per_million: Some(0.0),
+                ..ModelInfo::new("ollama", "llava")
+            }]
+        );
+
+        // Described models come from the registry afterwards
+        let again = manager.describe_models("ollama", &models).await;
+        assert_eq!(again, infos);
+    }
+
+    #[tokio::test]
+    async fn test_config_overrides_the_bundled_table() {
+        let mock_server = MockServer::start().await;
+        let manager = manager(&mock_server).with_model_overrides(vec![ModelInfo {
+            input_price_per_million: Some(2.0),
+            ..ModelInfo::new("anthropic", "claude-3-5-sonnet-20241022")
+        }]);
+
+        let info = manager
+            .model_registry()
+            .get("anthropic", "claude-3-5-sonnet-20241022");
+        assert_eq!(info.input_price_per_million, Some(2.0));
+        assert_eq!(info.output_price_per_million, Some(15.0));
+        assert_eq!(info.context_length, Some(200000));
+        assert_eq!(info.supports_vision, Some(true));
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_discovers_models_and_falls_back_to_the_table() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1/models"))
+            .and(header("x-api-key", "test-key"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "data": [
+                    { "type": "model", "id": "claude-sonnet-4-20250514" },
+                    { "type": "model", "id": "claude-3-5-haiku-20241022" }
+                ],
+                "has_more": false
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let provider = AnthropicProvider::new(
+            "test-key".to_string(),
+            mock_server.uri(),
+            "claude-3-5-sonnet-20241022".to_string(),
+        );
+        assert_eq!(
+            provider.discover_models().await.unwrap(),
+            vec!["claude-sonnet-4-20250514", "claude-3-5-haiku-20241022"]
+        );
+
+        let unauthorized = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1/models"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&unauthorized)
+            .await;
+        let provider = AnthropicProvider::new(
+            "bad-key".to_string(),
+            unauthorized.uri(),
+            "claude-3-5-sonnet-20241022".to_string(),
+        );
+        let models = provider.discover_models().await.unwrap();
+        assert!(models.contains(&"claude-3-5-sonnet-20241022".to_string()));
+        assert_eq!(models, provider.supported_models());
+    }
+
+    #[tokio::test]
+    async fn test_replies_are_priced_with_the_registry() {
+        let mock_server = MockServer::start().await;
+        let manager = manager(&mock_server).with_model_overrides(vec![ModelInfo {
+            input_price_per_million: Some(1.0),
+            output_price_per_million: Some(2.0),
+            ..ModelInfo::new("ollama", "llama3.2")
+        }]);
+        let usage = Usage {
+            input_tokens: 500_000,
+            output_tokens: 250_000,
+            ..Default::default()
+        };
+
+        let priced = MessageUsage::of_reply(&manager, Some("ollama"), "llama3.2", Some(&usage));
+        assert_eq!(priced.provider.as_deref(), Some("ollama"));
+        assert_eq!(priced.input_tokens, Some(500_000));
+        assert_eq!(priced.cost, Some(1.0));
+        assert_eq!(priced.token_count(), Some(750_000));
+
+        // Without a price the tokens are still recorded
+        let unpriced = MessageUsage::of_reply(&manager, Some("ollama"), "mistral", Some(&usage));
+        assert_eq!(unpriced.cost, None);
+        assert_eq!(unpriced.output_tokens, Some(250_000));
+    }
+}
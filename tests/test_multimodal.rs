@@ -0,0 +1,181 @@
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use stepbit::db::models::{Message as DbMessage, CONTENT_PARTS_KEY};
+    use stepbit::llm::anthropic::AnthropicProvider;
+    use stepbit::llm::copilot::CopilotProvider;
+    use stepbit::llm::ollama::OllamaProvider;
+    use stepbit::llm::openai::OpenAiProvider;
+    use stepbit::llm::{
+        models::{ChatOptions, ContentPart, Message, MessageContent},
+        LlmProvider,
+    };
+    use wiremock::matchers::{body_partial_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn image_message() -> Vec<Message> {
+        vec![Message {
+            role: "user".to_string(),
+            content: MessageContent::Parts(vec![
+                ContentPart::text("What is in these pictures?"),
+                ContentPart::image_base64("image/png", "iVBORw0KGgo="),
+                ContentPart::image_url("https://example.com/cat.jpg"),
+            ]),
+            tool_calls: None,
+            tool_call_id: None,
+            thinking: None,
+        }]
+    }
+
+    fn openai_reply() -> serde_json::Value {
+        json!({
+            "model": "gpt-4o",
+            "choices": [{ "message": { "role": "assistant", "content": "A cat." } }]
+        })
+    }
+
+    #[tokio::test]
+    async fn test_openai_and_copilot_send_content_parts() {
+        let expected_content = json!([
+            { "type": "text", "text": "What is in these pictures?" },
+            { "type": "image_url", "image_url": { "url": "data:image/png;base64,iVBORw0KGgo=" } },
+            { "type": "image_url", "image_url": { "url": "https://example.com/cat.jpg" } }
+        ]);
+
+        let openai = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(json!({ "messages": [{ "role": "user", "content": expected_content }] })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(openai_reply()))
+            .expect(1)
+            .mount(&openai)
+            .await;
+        let provider = OpenAiProvider::new("sk-test".to_string(), openai.uri(), "gpt-4o".to_string());
+        let response = provider.chat(&image_message(), ChatOptions::default()).await.unwrap();
+        assert_eq!(response.content, "A cat.");
+
+        let copilot = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(header("Copilot-Vision-Request", "true"))
+            .and(body_partial_json(json!({ "messages": [{ "role": "user", "content": expected_content }] })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(openai_reply()))
+            .expect(1)
+            .mount(&copilot)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/copilot_internal/v2/token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "token": "tid=test",
+                "expires_at": chrono::Utc::now().timestamp() + 1800
+            })))
+            .mount(&copilot)
+            .await;
+        let provider = CopilotProvider::new("gh-test".to_string(), copilot.uri(), "gpt-4o".to_string())
+            .with_token_url(&format!("{}/copilot_internal/v2/token", copilot.uri()));
+        provider.chat(&image_message(), ChatOptions::default()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_sends_image_source_blocks() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .and(body_partial_json(json!({
+                "messages": [{
+                    "role": "user",
+                    "content": [
+                        { "type": "text", "text": "What is in these pictures?" },
+                        {
+                            "type": "image",
+                            "source": { "type": "base64", "media_type": "image/png", "data": "iVBORw0KGgo=" }
+                        },
+                        {
+                            "type": "image",
+                            "source": { "type": "url", "url": "https://example.com/cat.jpg" }
+                        }
+                    ]
+                }]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "model": "claude-3-5-sonnet-20241022",
+                "content": [{ "type": "text", "text": "A cat." }],
+                "usage": { "input_tokens": 10, "output_tokens": 2 }
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let provider = AnthropicProvider::new(
+            "test-key".to_string(),
+            mock_server.uri(),
+            "claude-3-5-sonnet-20241022".to_string(),
+        );
+        let response = provider.chat(&image_message(), ChatOptions::default()).await.unwrap();
+        assert_eq!(response.content, "A cat.");
+    }
+
+    #[tokio::test]
+    async fn test_ollama_sends_images_array() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .and(body_partial_json(json!({
+                "messages": [{
+                    "role": "user",
+                    "content": "What is in these pictures?",
+                    // Ollama cannot fetch the remote image, only the inline one is sent
+                    "images": ["iVBORw0KGgo="]
+                }]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "model": "llava",
+                "message": { "role": "assistant", "content": "A cat." },
+                "done": true
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let provider = OllamaProvider::new(mock_server.uri(), "llava".to_string());
+        let response = provider.chat(&image_message(), ChatOptions::default()).await.unwrap();
+        assert_eq!(response.content, "A cat.");
+    }
+
+    #[test]
+    fn test_stored_message_restores_content_parts() {
+        let parts = match &image_message()[0].content {
+            MessageContent::Parts(parts) => parts.clone(),
+            MessageContent::Text(_) => unreachable!(),
+        };
+        let stored = DbMessage {
+            id: 1,
+            session_id: uuid::Uuid::new_v4(),
+            role: "user".to_string(),
+            content: "What is in these pictures?".to_string(),
+            model: None,
+            token_count: None,
+            created_at: chrono::Utc::now(),
+            metadata: json!({ CONTENT_PARTS_KEY: parts }),
+            usage: Default::default(),
+        };
+
+        let restored = stored.to_llm_message();
+        assert_eq!(restored.content, MessageContent::Parts(parts));
+        assert_eq!(restored.content.text(), "What is in these pictures?");
+        assert_eq!(restored.content.images().len(), 2);
+        assert_eq!(
+            restored.content.images()[0].as_base64(),
+            Some(("image/png", "iVBORw0KGgo="))
+        );
+
+        // Plain messages are unaffected
+        let plain = DbMessage {
+            metadata: json!({}),
+            ..stored
+        };
+        assert_eq!(plain.to_llm_message().content, MessageContent::Text("What is in these pictures?".to_string()));
+        let text: MessageContent = serde_json::from_value(json!("hi")).unwrap();
+        assert_eq!(text, "hi".into());
+    }
+}
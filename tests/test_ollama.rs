@@ -0,0 +1,176 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::user_message;
+    use stepbit::llm::ollama::{OllamaProvider, PullProgress, RunningModel};
+    use stepbit::llm::{
+        models::ChatOptions,
+        ErrorKind, LlmError, LlmProvider,
+    };
+    use serde_json::json;
+    use wiremock::matchers::{body_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_ollama_streaming_reports_usage() {
+        let mock_server = MockServer::start().await;
+        let provider = OllamaProvider::new(mock_server.uri(), "llama3.2".to_string());
+
+        let body = [
+            json!({ "model": "llama3.2", "message": { "role": "assistant", "content": "Hel" }, "done": false }),
+            json!({ "model": "llama3.2", "message": { "role": "assistant", "content": "lo" }, "done": false }),
+            json!({
+                "model": "llama3.2",
+                "message": { "role": "assistant", "content": "" },
+                "done": true,
+                "prompt_eval_count": 26,
+                "eval_count": 3
+            }),
+        ]
+        .iter()
+        .map(|l| format!("{}\n", l))
+        .collect::<String>();
+
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "application/x-ndjson"))
+            .mount(&mock_server)
+            .await;
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let result = provider
+            .chat_streaming(&user_message("Hi"), ChatOptions::default(), tx)
+            .await
+            .unwrap();
+
+        let mut text = String::new();
+        while let Some(chunk) = rx.recv().await {
+            text.push_str(chunk.text().unwrap_or_default());
+        }
+        assert_eq!(text, "Hello");
+
+        let usage = result.usage.unwrap();
+        assert_eq!(usage.input_tokens, 26);
+        assert_eq!(usage.output_tokens, 3);
+    }
+
+    fn ndjson(lines: &[serde_json::Value]) -> String {
+        lines.iter().map(|l| format!("{}\n", l)).collect()
+    }
+
+    #[tokio::test]
+    async fn test_pull_streams_progress_until_an_error_line() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/pull"))
+            .and(body_json(json!({ "model": "llama3.2:1b", "stream": true })))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(
+                ndjson(&[
+                    json!({ "status": "pulling manifest" }),
+                    json!({ "status": "pulling 74701a8c35f6", "digest": "sha256:74701a8c35f6", "total": 1321082688, "completed": 1048576 }),
+                    json!({ "error": "max retries exceeded: unexpected EOF" }),
+                ]),
+                "application/x-ndjson",
+            ))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let provider = OllamaProvider::new(mock_server.uri(), "llama3.2".to_string());
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let err = provider.pull_model("llama3.2:1b", tx).await.unwrap_err();
+        assert!(matches!(err, LlmError::Api(ref message) if message.contains("unexpected EOF")));
+
+        let mut progress = Vec::new();
+        while let Some(p) = rx.recv().await {
+            progress.push(p);
+        }
+        assert_eq!(
+            progress,
+            vec![
+                PullProgress { status: "pulling manifest".to_string(), digest: None, total: None, completed: None },
+                PullProgress {
+                    status: "pulling 74701a8c35f6".to_string(),
+                    digest: Some("sha256:74701a8c35f6".to_string()),
+                    total: Some(1321082688),
+                    completed: Some(1048576),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_show_and_running_models() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/show"))
+            .and(body_json(json!({ "model": "llama3.2" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "parameters": "stop \"<|eot_id|>\"",
+                "template": "{{ .Prompt }}",
+                "details": { "family": "llama", "parameter_size": "3.2B", "quantization_level": "Q4_K_M" },
+                "model_info": { "general.architecture": "llama", "llama.context_length": 131072 },
+                "capabilities": ["completion", "tools"]
+            })))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/ps"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "models": [{
+                    "name": "llama3.2:latest",
+                    "model": "llama3.2:latest",
+                    "size": 3357174784u64,
+                    "size_vram": 3357174784u64,
+                    "expires_at": "2026-10-17T12:05:00Z",
+                    "context_length": 4096
+                }]
+            })))
+            .mount(&mock_server)
+            .await;
+        let provider = OllamaProvider::new(mock_server.uri(), "llama3.2".to_string());
+
+        let details = provider.show_model("llama3.2").await.unwrap();
+        assert_eq!(details.parameters.as_deref(), Some("stop \"<|eot_id|>\""));
+        assert_eq!(details.template.as_deref(), Some("{{ .Prompt }}"));
+        assert_eq!(details.context_length, Some(131072));
+        assert_eq!(details.capabilities, vec!["completion", "tools"]);
+        assert_eq!(details.quantization_level.as_deref(), Some("Q4_K_M"));
+
+        assert_eq!(
+            provider.running_models().await.unwrap(),
+            vec![RunningModel {
+                name: "llama3.2:latest".to_string(),
+                size: 3357174784,
+                size_vram: 3357174784,
+                expires_at: Some("2026-10-17T12:05:00Z".to_string()),
+                context_length: Some(4096),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_reports_unknown_models() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("DELETE"))
+            .and(path("/api/delete"))
+            .and(body_json(json!({ "model": "llama3.2" })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path("/api/delete"))
+            .and(body_json(json!({ "model": "missing" })))
+            .respond_with(ResponseTemplate::new(404).set_body_json(json!({ "error": "model 'missing' not found" })))
+            .mount(&mock_server)
+            .await;
+        let provider = OllamaProvider::new(mock_server.uri(), "llama3.2".to_string());
+
+        provider.delete_model("llama3.2").await.unwrap();
+        let err = provider.delete_model("missing").await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert!(err.to_string().contains("model 'missing' not found"));
+    }
+}
@@ -0,0 +1,131 @@
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test, web, App};
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use stepbit::api::ollama_routes;
+    use stepbit::llm::ollama::OllamaProvider;
+    use stepbit::llm::openai::OpenAiProvider;
+    use stepbit::llm::{LlmProvider, ProviderManager};
+    use wiremock::matchers::{body_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const HF_MODEL: &str = "hf.co/bartowski/Llama-3.2-1B-Instruct-GGUF";
+
+    /// An Ollama host registered as `gpu-box` next to an OpenAI provider.
+    fn manager(ollama: &MockServer) -> Arc<dyn LlmProvider> {
+        let mut providers: HashMap<String, Arc<dyn LlmProvider>> = HashMap::new();
+        providers.insert(
+            "gpu-box".to_string(),
+            Arc::new(OllamaProvider::new(ollama.uri(), "llama3.2".to_string())),
+        );
+        providers.insert(
+            "openai".to_string(),
+            Arc::new(OpenAiProvider::new("sk-test".to_string(), ollama.uri(), "gpt-4o".to_string())),
+        );
+        Arc::new(ProviderManager::new(providers, "openai".to_string()))
+    }
+
+    macro_rules! app {
+        ($llm:expr) => {
+            test::init_service(
+                App::new()
+                    .app_data(web::Data::new($llm))
+                    .service(web::scope("/api").configure(ollama_routes::configure)),
+            )
+            .await
+        };
+    }
+
+    #[actix_web::test]
+    async fn test_models_of_an_ollama_provider_under_any_id() {
+        let ollama = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/tags"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "models": [{ "name": "llama3.2:latest", "size": 2019393189u64 }]
+            })))
+            .expect(1)
+            .mount(&ollama)
+            .await;
+        let app = app!(manager(&ollama));
+
+        let req = test::TestRequest::get().uri("/api/providers/gpu-box/models").to_request();
+        let models: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(models[0]["name"], "llama3.2:latest");
+    }
+
+    #[actix_web::test]
+    async fn test_show_and_delete_model_names_with_slashes() {
+        let ollama = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/show"))
+            .and(body_json(json!({ "model": HF_MODEL })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "details": { "family": "llama" },
+                "model_info": { "general.architecture": "llama", "llama.context_length": 131072 }
+            })))
+            .expect(1)
+            .mount(&ollama)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path("/api/delete"))
+            .and(body_json(json!({ "model": HF_MODEL })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&ollama)
+            .await;
+        let app = app!(manager(&ollama));
+        let uri = format!("/api/providers/gpu-box/models/{}", HF_MODEL);
+
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        let details: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(details["name"], HF_MODEL);
+        assert_eq!(details["context_length"], 131072);
+
+        let req = test::TestRequest::delete().uri(&uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[actix_web::test]
+    async fn test_running_is_not_taken_for_a_model_name() {
+        let ollama = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/ps"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "models": [{ "name": "llama3.2:latest", "size": 3357174784u64, "size_vram": 3357174784u64 }]
+            })))
+            .expect(1)
+            .mount(&ollama)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/show"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .expect(0)
+            .mount(&ollama)
+            .await;
+        let app = app!(manager(&ollama));
+
+        let req = test::TestRequest::get().uri("/api/providers/gpu-box/models/running").to_request();
+        let running: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(running[0]["name"], "llama3.2:latest");
+        assert_eq!(running[0]["size_vram"], 3357174784u64);
+    }
+
+    #[actix_web::test]
+    async fn test_ids_that_are_not_ollama_providers_are_not_found() {
+        let ollama = MockServer::start().await;
+        let app = app!(manager(&ollama));
+
+        for id in ["openai", "ollama", "missing"] {
+            let req = test::TestRequest::get()
+                .uri(&format!("/api/providers/{}/models", id))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND, "provider '{}'", id);
+        }
+        assert!(ollama.received_requests().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,154 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::user_message;
+    use stepbit::llm::copilot::CopilotProvider;
+    use stepbit::llm::openai::OpenAiProvider;
+    use stepbit::llm::{
+        models::{ChatOptions, FunctionDefinition, ToolDefinition},
+        LlmProvider,
+    };
+    use serde_json::json;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn search_tool() -> ToolDefinition {
+        ToolDefinition {
+            r#type: "function".to_string(),
+            function: FunctionDefinition {
+                name: "internet_search".to_string(),
+                description: "Search the web".to_string(),
+                parameters: json!({
+                    "type": "object",
+                    "properties": { "query": { "type": "string" } }
+                }),
+            },
+        }
+    }
+
+    /// Two parallel tool calls whose ids, names and argument fragments are interleaved by index.
+    fn tool_call_stream() -> String {
+        let deltas = [
+            json!({ "role": "assistant", "content": "Checking" }),
+            json!({ "tool_calls": [{ "index": 0, "id": "call_a", "type": "function", "function": { "name": "internet_search", "arguments": "" } }] }),
+            json!({ "tool_calls": [{ "index": 0, "function": { "arguments": "{\"query\":" } }] }),
+            json!({ "tool_calls": [{ "index": 1, "id": "call_b", "type": "function", "function": { "name": "read_url", "arguments": "{\"url\":" } }] }),
+            json!({ "tool_calls": [{ "index": 0, "function": { "arguments": " \"rust\"}" } }] }),
+            json!({ "tool_calls": [{ "index": 1, "function": { "arguments": " \"https://a.b\"}" } }] }),
+        ];
+        let mut body: String = deltas
+            .iter()
+            .map(|d| format!("data: {}\n\n", json!({ "choices": [{ "index": 0, "delta": d }] })))
+            .collect();
+        body.push_str(&format!(
+            "data: {}\n\n",
+            json!({ "choices": [{ "index": 0, "delta": {}, "finish_reason": "tool_calls" }] })
+        ));
+        body.push_str("data: [DONE]\n\n");
+        body
+    }
+
+    async fn collect_streamed_tool_calls(provider: &dyn LlmProvider) {
+        let options = ChatOptions {
+            tools: Some(vec![search_tool()]),
+            ..Default::default()
+        };
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let tool_calls = provider
+            .chat_streaming(&user_message("Look it up"), options, tx)
+            .await
+            .unwrap()
+            .tool_calls
+            .unwrap();
+
+        let mut text = String::new();
+        while let Some(chunk) = rx.recv().await {
+            text.push_str(chunk.text().unwrap_or_default());
+        }
+        assert_eq!(text, "Checking");
+
+        assert_eq!(tool_calls.len(), 2);
+        assert_eq!(tool_calls[0].id.as_deref(), Some("call_a"));
+        assert_eq!(tool_calls[0].function.name, "internet_search");
+        assert_eq!(tool_calls[0].function.arguments, "{\"query\": \"rust\"}");
+        assert_eq!(tool_calls[1].id.as_deref(), Some("call_b"));
+        assert_eq!(tool_calls[1].function.name, "read_url");
+        assert_eq!(tool_calls[1].function.arguments, "{\"url\": \"https://a.b\"}");
+    }
+
+    #[tokio::test]
+    async fn test_openai_streaming_assembles_tool_call_deltas() {
+        let mock_server = MockServer::start().await;
+        let provider = OpenAiProvider::new("sk-test".to_string(), mock_server.uri(), "gpt-4o".to_string());
+
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(json!({ "stream": true, "tools": [{ "type": "function" }] })))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(tool_call_stream(), "text/event-stream"))
+            .mount(&mock_server)
+            .await;
+
+        collect_streamed_tool_calls(&provider).await;
+    }
+
+    #[tokio::test]
+    async fn test_copilot_streaming_assembles_tool_call_deltas() {
+        let mock_server = MockServer::start().await;
+        let provider = CopilotProvider::new("gh-test".to_string(), mock_server.uri(), "gpt-4o".to_string())
+            .with_token_url(&format!("{}/copilot_internal/v2/token", mock_server.uri()));
+        Mock::given(method("GET"))
+            .and(path("/copilot_internal/v2/token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "token": "tid=test",
+                "expires_at": chrono::Utc::now().timestamp() + 1800
+            })))
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(json!({ "stream": true, "tools": [{ "type": "function" }] })))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(tool_call_stream(), "text/event-stream"))
+            .mount(&mock_server)
+            .await;
+
+        collect_streamed_tool_calls(&provider).await;
+    }
+
+    #[tokio::test]
+    async fn test_openai_streaming_reports_usage() {
+        let mock_server = MockServer::start().await;
+        let provider = OpenAiProvider::new("sk-test".to_string(), mock_server.uri(), "gpt-4o".to_string());
+
+        let body = format!(
+            "data: {}\n\ndata: {}\n\ndata: {}\n\ndata: [DONE]\n\n",
+            json!({ "choices": [{ "delta": { "content": "Hello" } }] }),
+            json!({ "choices": [{ "delta": { "content": " there" }, "finish_reason": "stop" }] }),
+            json!({ "choices": [], "usage": { "prompt_tokens": 9, "completion_tokens": 2, "total_tokens": 11 } })
+        );
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(json!({ "stream_options": { "include_usage": true } })))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&mock_server)
+            .await;
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let result = provider
+            .chat_streaming(&user_message("Hi"), ChatOptions::default(), tx)
+            .await
+            .unwrap();
+        assert!(result.tool_calls.is_none());
+
+        let usage = result.usage.unwrap();
+        assert_eq!(usage.input_tokens, 9);
+        assert_eq!(usage.output_tokens, 2);
+
+        let mut text = String::new();
+        while let Some(chunk) = rx.recv().await {
+            text.push_str(chunk.text().unwrap_or_default());
+        }
+        assert_eq!(text, "Hello there");
+    }
+}
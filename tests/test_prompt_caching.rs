@@ -0,0 +1,146 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::message;
+    use serde_json::json;
+    use stepbit::config::PromptCachingConfig;
+    use stepbit::llm::anthropic::AnthropicProvider;
+    use stepbit::llm::registry::ModelRegistry;
+    use stepbit::llm::{
+        models::{ChatOptions, FunctionDefinition, ToolDefinition, Usage},
+        LlmProvider,
+    };
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn tool(name: &str) -> ToolDefinition {
+        ToolDefinition {
+            r#type: "function".to_string(),
+            function: FunctionDefinition {
+                name: name.to_string(),
+                description: format!("The {} tool", name),
+                parameters: json!({ "type": "object", "properties": {} }),
+            },
+        }
+    }
+
+    fn provider(server: &MockServer) -> AnthropicProvider {
+        AnthropicProvider::new("test-key".to_string(), server.uri(), "claude-3-5-sonnet-20241022".to_string())
+            .with_prompt_caching(PromptCachingConfig::default())
+    }
+
+    #[tokio::test]
+    async fn test_breakpoints_on_system_tools_and_newest_turn() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .and(body_partial_json(json!({
+                "system": [{ "type": "text", "text": "You are helpful.", "cache_control": { "type": "ephemeral" } }]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "content": [{ "type": "text", "text": "Sure." }],
+                "usage": {
+                    "input_tokens": 12,
+                    "output_tokens": 3,
+                    "cache_read_input_tokens": 2048,
+                    "cache_creation_input_tokens": 40
+                }
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let messages = vec![
+            message("user", "Hello"),
+            message("assistant", "Hi, how can I help?"),
+            message("user", "Search for actix"),
+        ];
+        let options = ChatOptions {
+            system_prompt: Some("You are helpful.".to_string()),
+            tools: Some(vec![tool("internet_search"), tool("read_url")]),
+            ..Default::default()
+        };
+        let response = provider(&mock_server).chat(&messages, options).await.unwrap();
+
+        let usage = response.usage.unwrap();
+        assert_eq!(usage.input_tokens, 12);
+        assert_eq!(usage.cache_read_tokens, Some(2048));
+        assert_eq!(usage.cache_write_tokens, Some(40));
+
+        let requests = mock_server.received_requests().await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        let breakpoint = json!({ "type": "ephemeral" });
+
+        let tools = body["tools"].as_array().unwrap();
+        assert!(tools[0].get("cache_control").is_none());
+        assert_eq!(tools[1]["cache_control"], breakpoint);
+
+        let turns = body["messages"].as_array().unwrap();
+        assert_eq!(turns.len(), 3);
+        assert!(turns[..2].iter().all(|turn| turn["content"][0].get("cache_control").is_none()));
+        assert_eq!(turns[2]["content"][0]["cache_control"], breakpoint);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_cache_usage_and_disabled_breakpoints() {
+        let mock_server = MockServer::start().await;
+        let events = [
+            json!({ "type": "message_start", "message": { "usage": {
+                "input_tokens": 8, "output_tokens": 1, "cache_creation_input_tokens": 1500, "cache_read_input_tokens": 0
+            } } }),
+            json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "text_delta", "text": "Hi" } }),
+            json!({ "type": "message_delta", "delta": { "stop_reason": "end_turn" }, "usage": { "output_tokens": 4 } }),
+            json!({ "type": "message_stop" }),
+        ];
+        let body: String = events
+            .iter()
+            .map(|e| format!("event: {}\ndata: {}\n\n", e["type"].as_str().unwrap(), e))
+            .collect();
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&mock_server)
+            .await;
+
+        let options = ChatOptions {
+            system_prompt: Some("You are helpful.".to_string()),
+            ..Default::default()
+        };
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let result = provider(&mock_server)
+            .chat_streaming(&[message("user", "Hello")], options.clone(), tx)
+            .await
+            .unwrap();
+        let usage = result.usage.unwrap();
+        assert_eq!((usage.input_tokens, usage.output_tokens), (8, 4));
+        assert_eq!(usage.cache_write_tokens, Some(1500));
+        assert_eq!(usage.cache_read_tokens, Some(0));
+
+        // Without caching the request keeps its plain form
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        provider(&mock_server)
+            .with_prompt_caching(PromptCachingConfig::disabled())
+            .chat_streaming(&[message("user", "Hello")], options, tx)
+            .await
+            .unwrap();
+        let requests = mock_server.received_requests().await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests[1].body).unwrap();
+        assert_eq!(body["system"], "You are helpful.");
+        assert!(body["messages"][0]["content"][0].get("cache_control").is_none());
+    }
+
+    #[test]
+    fn test_cached_input_is_priced_separately() {
+        let info = ModelRegistry::new().get("anthropic", "claude-3-5-sonnet-20241022");
+        let usage = Usage {
+            input_tokens: 100_000,
+            output_tokens: 10_000,
+            cache_read_tokens: Some(1_000_000),
+            cache_write_tokens: Some(100_000),
+        };
+        // 0.3 input + 0.15 output + 0.3 cache reads + 0.375 cache writes
+        let cost = info.cost(&usage).unwrap();
+        assert!((cost - 1.125).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,281 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::user_message;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use serde_json::json;
+    use stepbit::llm::ollama::OllamaProvider;
+    use stepbit::llm::openai::OpenAiProvider;
+    use stepbit::llm::{
+        models::ChatOptions,
+        bind_session_defaults, ErrorKind, LlmProvider, ProviderFactory, ProviderManager,
+    };
+    use stepbit::config::ProviderSpec;
+    use wiremock::matchers::{body_partial_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// An OpenAI provider answering every completion with `status`, and an Ollama fallback.
+    async fn manager_with_failing_primary(
+        status: u16,
+    ) -> (ProviderManager, MockServer, MockServer) {
+        let primary = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(status).set_body_string("upstream unavailable"))
+            .mount(&primary)
+            .await;
+        let backup = MockServer::start().await;
+
+        let mut providers: HashMap<String, Arc<dyn LlmProvider>> = HashMap::new();
+        providers.insert(
+            "openai".to_string(),
+            Arc::new(OpenAiProvider::new("sk-test".to_string(), primary.uri(), "gpt-4o".to_string())),
+        );
+        providers.insert(
+            "ollama".to_string(),
+            Arc::new(OllamaProvider::new(backup.uri(), "llama3.2".to_string())),
+        );
+
+        let manager = ProviderManager::new(providers, "openai".to_string())
+            .with_fallback(vec!["ollama".to_string()]);
+        (manager, primary, backup)
+    }
+
+    #[tokio::test]
+    async fn test_chat_fails_over_on_server_error() {
+        let (manager, _primary, backup) = manager_with_failing_primary(503).await;
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "model": "llama3.2",
+                "message": { "role": "assistant", "content": "From the backup" },
+                "done": true
+            })))
+            .mount(&backup)
+            .await;
+
+        let options = ChatOptions {
+            model: Some("gpt-4o".to_string()),
+            ..Default::default()
+        };
+        let response = manager.chat(&user_message("Hi"), options).await.unwrap();
+        assert_eq!(response.content, "From the backup");
+        assert_eq!(response.provider.as_deref(), Some("ollama"));
+        // The fallback answers with its own default model, not the one requested from OpenAI
+        assert_eq!(response.model, "llama3.2");
+    }
+
+    #[tokio::test]
+    async fn test_chat_does_not_fail_over_on_client_error() {
+        let (manager, _primary, backup) = manager_with_failing_primary(400).await;
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&backup)
+            .await;
+
+        let err = manager
+            .chat(&user_message("Hi"), ChatOptions::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidRequest);
+        assert_eq!(err.provider(), Some("openai"));
+    }
+
+    #[tokio::test]
+    async fn test_streaming_fails_over_before_first_chunk() {
+        let (manager, _primary, backup) = manager_with_failing_primary(429).await;
+        let body = [
+            json!({ "message": { "role": "assistant", "content": "Back" }, "done": false }),
+            json!({ "message": { "role": "assistant", "content": "up" }, "done": true }),
+        ]
+        .iter()
+        .map(|l| format!("{}\n", l))
+        .collect::<String>();
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "application/x-ndjson"))
+            .mount(&backup)
+            .await;
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let result = manager
+            .chat_streaming(&user_message("Hi"), ChatOptions::default(), tx)
+            .await
+            .unwrap();
+
+        let mut text = String::new();
+        while let Some(chunk) = rx.recv().await {
+            text.push_str(chunk.text().unwrap_or_default());
+        }
+        assert_eq!(text, "Backup");
+        assert_eq!(result.provider.as_deref(), Some("ollama"));
+        assert_eq!(result.model.as_deref(), Some("llama3.2"));
+    }
+
+    #[tokio::test]
+    async fn test_sessions_stay_on_their_own_provider_concurrently() {
+        let openai = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(json!({ "model": "gpt-4o-mini" })))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({
+                        "model": "gpt-4o-mini",
+                        "choices": [{ "message": { "role": "assistant", "content": "From OpenAI" } }]
+                    }))
+                    // Keeps the first session in flight while the second one runs
+                    .set_delay(std::time::Duration::from_millis(200)),
+            )
+            .expect(1)
+            .mount(&openai)
+            .await;
+        let ollama = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .and(body_partial_json(json!({ "model": "llama3.2" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "model": "llama3.2",
+                "message": { "role": "assistant", "content": "From Ollama" },
+                "done": true
+            })))
+            .expect(1)
+            .mount(&ollama)
+            .await;
+
+        let mut providers: HashMap<String, Arc<dyn LlmProvider>> = HashMap::new();
+        providers.insert(
+            "openai".to_string(),
+            Arc::new(OpenAiProvider::new("sk-test".to_string(), openai.uri(), "gpt-4o".to_string())),
+        );
+        providers.insert(
+            "ollama".to_string(),
+            Arc::new(OllamaProvider::new(ollama.uri(), "llama3.2".to_string())),
+        );
+        let manager = ProviderManager::new(providers, "openai".to_string());
+
+        // Each session is bound to whatever was active when it was created
+        manager.set_active_model(Some("gpt-4o-mini".to_string()));
+        let mut first = json!({});
+        bind_session_defaults(&manager, &mut first);
+        manager.set_active_provider("ollama").unwrap();
+        manager.set_active_model(None);
+        let mut second = json!({ "system_prompt": "Be brief." });
+        bind_session_defaults(&manager, &mut second);
+
+        assert_eq!(first, json!({ "provider": "openai", "model": "gpt-4o-mini" }));
+        assert_eq!(
+            second,
+            json!({ "system_prompt": "Be brief.", "provider": "ollama", "model": "llama3.2" })
+        );
+
+        // Switching the global default again must not move either session
+        manager.set_active_provider("openai").unwrap();
+        manager.set_active_model(Some("gpt-4o".to_string()));
+
+        let messages = user_message("Hi");
+        let (a, b) = tokio::join!(
+            manager.chat(&messages, ChatOptions::default().for_session(&first)),
+            manager.chat(&messages, ChatOptions::default().for_session(&second)),
+        );
+        let (a, b) = (a.unwrap(), b.unwrap());
+        assert_eq!(a.content, "From OpenAI");
+        assert_eq!(a.provider.as_deref(), Some("openai"));
+        assert_eq!(b.content, "From Ollama");
+        assert_eq!(b.provider.as_deref(), Some("ollama"));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_session_provider_uses_active_provider() {
+        let (manager, primary, _backup) = manager_with_failing_primary(503).await;
+        primary.reset().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "model": "gpt-4o",
+                "choices": [{ "message": { "role": "assistant", "content": "Hello" } }]
+            })))
+            .mount(&primary)
+            .await;
+
+        let options = ChatOptions::default().for_session(&json!({ "provider": "removed" }));
+        let response = manager.chat(&user_message("Hi"), options).await.unwrap();
+        assert_eq!(response.provider.as_deref(), Some("openai"));
+    }
+
+    #[tokio::test]
+    async fn test_providers_are_added_rotated_and_removed_at_runtime() {
+        let (manager, primary, _backup) = manager_with_failing_primary(503).await;
+        let vllm = MockServer::start().await;
+        for (key, reply) in [("sk-old", "Old key"), ("sk-new", "New key")] {
+            Mock::given(method("POST"))
+                .and(path("/v1/chat/completions"))
+                .and(header("authorization", format!("Bearer {}", key).as_str()))
+                .and(header("x-tenant", "acme"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "model": "qwen2.5",
+                    "choices": [{ "message": { "role": "assistant", "content": reply } }]
+                })))
+                .expect(1)
+                .mount(&vllm)
+                .await;
+        }
+        let spec = |key: &str| -> ProviderSpec {
+            serde_json::from_value(json!({
+                "kind": "openai",
+                "api_base": format!("{}/v1", vllm.uri()),
+                "default_model": "qwen2.5",
+                "api_key": key,
+                "headers": { "X-Tenant": "acme" },
+                "retry": { "max_attempts": 1 }
+            }))
+            .unwrap()
+        };
+        let on_vllm = ChatOptions {
+            provider: Some("vllm".to_string()),
+            ..Default::default()
+        };
+
+        manager.register_provider("vllm", ProviderFactory::create("vllm", &spec("sk-old")).unwrap());
+        let response = manager.chat(&user_message("Hi"), on_vllm.clone()).await.unwrap();
+        assert_eq!(response.content, "Old key");
+        assert_eq!(response.provider.as_deref(), Some("vllm"));
+
+        manager.register_provider("vllm", ProviderFactory::create("vllm", &spec("sk-new")).unwrap());
+        let response = manager.chat(&user_message("Hi"), on_vllm.clone()).await.unwrap();
+        assert_eq!(response.content, "New key");
+
+        assert!(manager.remove_provider("openai").is_err());
+        manager.remove_provider("vllm").unwrap();
+        assert!(manager.get_provider("vllm").is_none());
+
+        // Sessions bound to the removed provider move to the active one
+        primary.reset().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "model": "gpt-4o",
+                "choices": [{ "message": { "role": "assistant", "content": "Hello" } }]
+            })))
+            .mount(&primary)
+            .await;
+        let response = manager.chat(&user_message("Hi"), on_vllm).await.unwrap();
+        assert_eq!(response.provider.as_deref(), Some("openai"));
+    }
+
+    #[test]
+    fn test_headers_are_only_accepted_for_openai_compatible_providers() {
+        let spec: ProviderSpec = serde_json::from_value(json!({
+            "kind": "anthropic",
+            "default_model": "claude-sonnet-4-20250514",
+            "headers": { "X-Tenant": "acme" }
+        }))
+        .unwrap();
+        assert!(ProviderFactory::create("anthropic", &spec).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::user_message;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use serde_json::json;
+    use stepbit::llm::anthropic::AnthropicProvider;
+    use stepbit::llm::ollama::OllamaProvider;
+    use stepbit::llm::openai::OpenAiProvider;
+    use stepbit::llm::{
+        models::{ChatOptions, ResponseFormat},
+        LlmProvider, ProviderManager,
+    };
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn city_schema() -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": { "city": { "type": "string" }, "population": { "type": "integer" } },
+            "required": ["city", "population"]
+        })
+    }
+
+    fn options() -> ChatOptions {
+        ChatOptions {
+            response_format: Some(ResponseFormat::json_schema("city", city_schema())),
+            ..Default::default()
+        }
+    }
+
+    fn openai_reply(content: &str) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(json!({
+            "model": "gpt-4o",
+            "choices": [{ "message": { "role": "assistant", "content": content } }]
+        }))
+    }
+
+    fn manager(server: &MockServer) -> ProviderManager {
+        let mut providers: HashMap<String, Arc<dyn LlmProvider>> = HashMap::new();
+        providers.insert(
+            "openai".to_string(),
+            Arc::new(OpenAiProvider::new("sk-test".to_string(), server.uri(), "gpt-4o".to_string())),
+        );
+        ProviderManager::new(providers, "openai".to_string())
+    }
+
+    #[tokio::test]
+    async fn test_openai_and_ollama_request_the_format() {
+        let openai = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(json!({
+                "response_format": {
+                    "type": "json_schema",
+                    "json_schema": { "name": "city", "schema": city_schema() }
+                }
+            })))
+            .respond_with(openai_reply("{\"city\":\"Paris\",\"population\":2100000}"))
+            .expect(1)
+            .mount(&openai)
+            .await;
+        let provider = OpenAiProvider::new("sk-test".to_string(), openai.uri(), "gpt-4o".to_string());
+        provider.chat(&user_message("Largest city in France?"), options()).await.unwrap();
+
+        let ollama = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .and(body_partial_json(json!({ "format": city_schema() })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "message": { "role": "assistant", "content": "{}" },
+                "done": true
+            })))
+            .expect(1)
+            .mount(&ollama)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .and(body_partial_json(json!({ "format": "json" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "message": { "role": "assistant", "content": "{}" },
+                "done": true
+            })))
+            .expect(1)
+            .mount(&ollama)
+            .await;
+        let provider = OllamaProvider::new(ollama.uri(), "llama3.2".to_string());
+        provider.chat(&user_message("Largest city in France?"), options()).await.unwrap();
+        let json_mode = ChatOptions {
+            response_format: Some(ResponseFormat::JsonObject),
+            ..Default::default()
+        };
+        provider.chat(&user_message("Largest city in France?"), json_mode).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_answers_through_a_forced_tool() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .and(body_partial_json(json!({
+                "tools": [{ "name": "structured_response", "input_schema": city_schema() }],
+                "tool_choice": { "type": "tool", "name": "structured_response" }
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "content": [{
+                    "type": "tool_use",
+                    "id": "toolu_1",
+                    "name": "structured_response",
+                    "input": { "city": "Paris", "population": 2100000 }
+                }],
+                "usage": { "input_tokens": 20, "output_tokens": 10 }
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let provider = AnthropicProvider::new(
+            "test-key".to_string(),
+            mock_server.uri(),
+            "claude-3-5-sonnet-20241022".to_string(),
+        );
+        let response = provider.chat(&user_message("Largest city in France?"), options()).await.unwrap();
+        assert!(response.tool_calls.is_none());
+        let value: serde_json::Value = serde_json::from_str(&response.content).unwrap();
+        assert_eq!(value, json!({ "city": "Paris", "population": 2100000 }));
+    }
+
+    #[tokio::test]
+    async fn test_manager_asks_again_until_the_reply_matches() {
+        let mock_server = MockServer::start().await;
+        // The first reply misses a required field
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(openai_reply("{\"city\":\"Paris\"}"))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(openai_reply("{\"city\":\"Paris\",\"population\":2100000}"))
+            .mount(&mock_server)
+            .await;
+
+        let response = manager(&mock_server).chat(&user_message("Largest city in France?"), options()).await.unwrap();
+        assert_eq!(response.content, "{\"city\":\"Paris\",\"population\":2100000}");
+
+        let requests = mock_server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 2);
+        let retry: serde_json::Value = serde_json::from_slice(&requests[1].body).unwrap();
+        let messages = retry["messages"].as_array().unwrap();
+        assert_eq!(messages[1]["content"], "{\"city\":\"Paris\"}");
+        assert!(messages[2]["content"]
+            .as_str()
+            .unwrap()
+            .contains("$ is missing required property 'population'"));
+    }
+
+    #[tokio::test]
+    async fn test_manager_gives_up_after_bounded_retries() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(openai_reply("Paris, about two million people."))
+            .expect(1 + stepbit::llm::MAX_FORMAT_RETRIES as u64)
+            .mount(&mock_server)
+            .await;
+
+        let error = manager(&mock_server).chat(&user_message("Largest city in France?"), options()).await.unwrap_err();
+        assert!(error.to_string().contains("the reply is not valid JSON"));
+    }
+}
@@ -0,0 +1,147 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::user_message;
+    use std::sync::Arc;
+
+    use serde_json::json;
+    use stepbit::config::RetryConfig;
+    use stepbit::llm::openai::OpenAiProvider;
+    use stepbit::llm::retry::RetryingProvider;
+    use stepbit::llm::{
+        models::ChatOptions,
+        ErrorKind, LlmProvider,
+    };
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn retrying_openai(server: &MockServer, max_attempts: u32) -> RetryingProvider {
+        RetryingProvider::new(
+            Arc::new(OpenAiProvider::new("sk-test".to_string(), server.uri(), "gpt-4o".to_string())),
+            RetryConfig {
+                max_attempts,
+                base_delay_ms: 1,
+                max_delay_ms: 1_000,
+                jitter: 0.0,
+            },
+        )
+    }
+
+    fn completion(content: &str) -> serde_json::Value {
+        json!({
+            "model": "gpt-4o",
+            "choices": [{ "message": { "role": "assistant", "content": content } }],
+            "usage": { "prompt_tokens": 5, "completion_tokens": 2 }
+        })
+    }
+
+    /// Answers the first `failures` requests with `failure`, and `success` afterwards.
+    async fn mount_flaky(server: &MockServer, failures: u64, failure: ResponseTemplate, success: ResponseTemplate) {
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(failure)
+            .up_to_n_times(failures)
+            .with_priority(1)
+            .mount(server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(success)
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_chat_retries_transient_server_errors() {
+        let server = MockServer::start().await;
+        mount_flaky(
+            &server,
+            2,
+            ResponseTemplate::new(503),
+            ResponseTemplate::new(200).set_body_json(completion("Recovered")),
+        )
+        .await;
+
+        let provider = retrying_openai(&server, 3);
+        let response = provider.chat(&user_message("Hi"), ChatOptions::default()).await.unwrap();
+        assert_eq!(response.content, "Recovered");
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_chat_honors_retry_after() {
+        let server = MockServer::start().await;
+        mount_flaky(
+            &server,
+            1,
+            ResponseTemplate::new(429).insert_header("retry-after", "0"),
+            ResponseTemplate::new(200).set_body_json(completion("After the wait")),
+        )
+        .await;
+
+        let provider = retrying_openai(&server, 2);
+        let response = provider.chat(&user_message("Hi"), ChatOptions::default()).await.unwrap();
+        assert_eq!(response.content, "After the wait");
+    }
+
+    #[tokio::test]
+    async fn test_chat_gives_up_after_max_attempts() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(500).set_body_string("boom"))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let provider = retrying_openai(&server, 2);
+        let err = provider.chat(&user_message("Hi"), ChatOptions::default()).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Server);
+        assert_eq!(err.status(), Some(500));
+    }
+
+    #[tokio::test]
+    async fn test_chat_does_not_retry_client_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(400).set_body_string("bad request"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let provider = retrying_openai(&server, 3);
+        let err = provider.chat(&user_message("Hi"), ChatOptions::default()).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn test_streaming_retries_before_first_chunk() {
+        let server = MockServer::start().await;
+        let body = format!(
+            "data: {}\n\ndata: [DONE]\n\n",
+            json!({ "choices": [{ "delta": { "content": "Streamed" } }] })
+        );
+        mount_flaky(
+            &server,
+            1,
+            ResponseTemplate::new(502),
+            ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"),
+        )
+        .await;
+
+        let provider = retrying_openai(&server, 2);
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        provider
+            .chat_streaming(&user_message("Hi"), ChatOptions::default(), tx)
+            .await
+            .unwrap();
+
+        let mut text = String::new();
+        while let Some(chunk) = rx.recv().await {
+            text.push_str(chunk.text().unwrap_or_default());
+        }
+        assert_eq!(text, "Streamed");
+    }
+}
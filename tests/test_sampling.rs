@@ -0,0 +1,201 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::user_message;
+    use serde_json::json;
+    use stepbit::api::models_openai::OpenAIChatRequest;
+    use stepbit::llm::anthropic::AnthropicProvider;
+    use stepbit::llm::ollama::OllamaProvider;
+    use stepbit::llm::openai::OpenAiProvider;
+    use stepbit::llm::{
+        models::{ChatOptions, SamplingParams},
+        ErrorKind, LlmProvider,
+    };
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn options(sampling: SamplingParams) -> ChatOptions {
+        ChatOptions {
+            sampling,
+            ..Default::default()
+        }
+    }
+
+    fn openai_reply() -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(json!({
+            "model": "gpt-4o",
+            "choices": [{ "message": { "role": "assistant", "content": "Hi" } }]
+        }))
+    }
+
+    #[test]
+    fn test_adapter_request_carries_sampling_parameters() {
+        let request: OpenAIChatRequest = serde_json::from_value(json!({
+            "model": "gpt-4o",
+            "messages": [{ "role": "user", "content": "Hello" }],
+            "top_p": 0.9,
+            "stop": "END",
+            "seed": 42,
+            "frequency_penalty": 0.5
+        }))
+        .unwrap();
+
+        assert_eq!(
+            request.sampling,
+            SamplingParams {
+                top_p: Some(0.9),
+                stop: Some(vec!["END".to_string()]),
+                seed: Some(42),
+                frequency_penalty: Some(0.5),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_openai_passes_supported_and_rejects_the_rest() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(json!({
+                "top_p": 0.5,
+                "stop": ["END", "STOP"],
+                "seed": 42,
+                "presence_penalty": 0.25
+            })))
+            .respond_with(openai_reply())
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let provider = OpenAiProvider::new("sk-test".to_string(), mock_server.uri(), "gpt-4o".to_string());
+        let sampling = SamplingParams {
+            top_p: Some(0.5),
+            stop: Some(vec!["END".to_string(), "STOP".to_string()]),
+            seed: Some(42),
+            presence_penalty: Some(0.25),
+            ..Default::default()
+        };
+        provider.chat(&user_message("Hello"), options(sampling)).await.unwrap();
+
+        let sampling = SamplingParams {
+            top_k: Some(40),
+            repeat_penalty: Some(1.5),
+            ..Default::default()
+        };
+        let error = provider.chat(&user_message("Hello"), options(sampling.clone())).await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidRequest);
+        assert_eq!(error.to_string(), "Unsupported: openai does not support top_k, repeat_penalty");
+
+        // OpenAI-compatible servers get everything
+        let local = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(json!({ "top_k": 40, "repeat_penalty": 1.5 })))
+            .respond_with(openai_reply())
+            .expect(1)
+            .mount(&local)
+            .await;
+        let provider = OpenAiProvider::new(String::new(), local.uri(), "qwen2.5".to_string()).named("vllm");
+        provider.chat(&user_message("Hello"), options(sampling)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ollama_and_anthropic_mapping() {
+        let ollama = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .and(body_partial_json(json!({
+                "options": { "top_k": 40, "repeat_penalty": 1.5, "seed": 7, "stop": ["END"] }
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "message": { "role": "assistant", "content": "Hi" },
+                "done": true
+            })))
+            .expect(1)
+            .mount(&ollama)
+            .await;
+        let provider = OllamaProvider::new(ollama.uri(), "llama3.2".to_string());
+        let sampling = SamplingParams {
+            top_k: Some(40),
+            repeat_penalty: Some(1.5),
+            seed: Some(7),
+            stop: Some(vec!["END".to_string()]),
+            ..Default::default()
+        };
+        provider.chat(&user_message("Hello"), options(sampling)).await.unwrap();
+
+        let anthropic = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .and(body_partial_json(json!({ "top_k": 40, "stop_sequences": ["END"] })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "content": [{ "type": "text", "text": "Hi" }]
+            })))
+            .expect(1)
+            .mount(&anthropic)
+            .await;
+        let provider = AnthropicProvider::new(
+            "test-key".to_string(),
+            anthropic.uri(),
+            "claude-3-5-sonnet-20241022".to_string(),
+        );
+        let sampling = SamplingParams {
+            top_k: Some(40),
+            stop: Some(vec!["END".to_string()]),
+            ..Default::default()
+        };
+        provider.chat(&user_message("Hello"), options(sampling.clone())).await.unwrap();
+
+        let seeded = SamplingParams {
+            seed: Some(7),
+            ..sampling
+        };
+        let error = provider.chat(&user_message("Hello"), options(seeded)).await.unwrap_err();
+        assert_eq!(error.to_string(), "Unsupported: anthropic does not support seed");
+    }
+
+    #[test]
+    fn test_session_settings_fill_unset_parameters() {
+        let metadata = json!({
+            "provider": "ollama",
+            "temperature": 0.2,
+            "top_k": 20,
+            "seed": 1,
+            "system_prompt": "Be brief."
+        });
+        let options = ChatOptions {
+            sampling: SamplingParams {
+                seed: Some(99),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+        .for_session(&metadata);
+
+        assert_eq!(options.temperature, Some(0.2));
+        assert_eq!(options.sampling.top_k, Some(20));
+        // The request's own value wins over the session's
+        assert_eq!(options.sampling.seed, Some(99));
+        assert_eq!(options.max_tokens, None);
+    }
+
+    #[test]
+    fn test_malformed_session_setting_keeps_the_valid_ones() {
+        let metadata = json!({
+            "top_k": 40.5,
+            "seed": -1,
+            "top_p": 0.9,
+            "stop": "END",
+            "frequency_penalty": "high"
+        });
+        let options = ChatOptions::default().for_session(&metadata);
+
+        assert_eq!(options.sampling.top_p, Some(0.9));
+        assert_eq!(options.sampling.stop, Some(vec!["END".to_string()]));
+        assert_eq!(options.sampling.top_k, None);
+        assert_eq!(options.sampling.seed, None);
+        assert_eq!(options.sampling.frequency_penalty, None);
+    }
+}
@@ -2,7 +2,7 @@
 mod tests {
     use stepbit::llm::stepbit_core::StepbitCoreProvider;
     use stepbit::llm::{
-        models::{ChatOptions, Message},
+        models::{ChatOptions, Message, StreamEvent},
         LlmProvider,
     };
     use serde_json::json;
@@ -17,9 +17,10 @@ mod tests {
 
         let messages = vec![Message {
             role: "user".to_string(),
-            content: "Hello".to_string(),
+            content: "Hello".into(),
             tool_calls: None,
             tool_call_id: None,
+            thinking: None,
         }];
 
         // Verify request body if needed
@@ -85,9 +86,10 @@ mod tests {
 
         let messages = vec![Message {
             role: "user".to_string(),
-            content: "Hello".to_string(),
+            content: "Hello".into(),
             tool_calls: None,
             tool_call_id: None,
+            thinking: None,
         }];
         
         provider.chat(&messages, ChatOptions::default()).await.unwrap();
@@ -152,4 +154,44 @@ mod tests {
         
         assert_eq!(results["node1"]["value"], 42);
     }
+
+    #[tokio::test]
+    async fn test_llmos_streaming_reports_usage() {
+        let mock_server = MockServer::start().await;
+        let provider = StepbitCoreProvider::new(mock_server.uri(), "phi-4".to_string(), None);
+
+        let body = format!(
+            "data: {}\n\ndata: {}\n\ndata: [DONE]\n\n",
+            json!({ "choices": [{ "delta": { "content": "Hi!" } }] }),
+            json!({
+                "choices": [{ "delta": {}, "finish_reason": "stop" }],
+                "usage": { "prompt_tokens": 8, "completion_tokens": 2, "total_tokens": 10 }
+            })
+        );
+
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&mock_server)
+            .await;
+
+        let messages = vec![Message {
+            role: "user".to_string(),
+            content: "Hello".into(),
+            tool_calls: None,
+            tool_call_id: None,
+            thinking: None,
+        }];
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let result = provider
+            .chat_streaming(&messages, ChatOptions::default(), tx)
+            .await
+            .unwrap();
+
+        assert_eq!(rx.recv().await, Some(StreamEvent::Text("Hi!".to_string())));
+        let usage = result.usage.unwrap();
+        assert_eq!(usage.input_tokens, 8);
+        assert_eq!(usage.output_tokens, 2);
+    }
 }
@@ -0,0 +1,357 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::message;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use stepbit::llm::anthropic::AnthropicProvider;
+    use stepbit::llm::gemini::GeminiProvider;
+    use stepbit::llm::ollama::OllamaProvider;
+    use stepbit::llm::openai::OpenAiProvider;
+    use stepbit::llm::{
+        models::{ChatOptions, FunctionCall, Message, SamplingParams, StreamEvent, ToolCall},
+        registry, LlmError, LlmProvider, ProviderManager,
+    };
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn thinking(budget: u32) -> ChatOptions {
+        ChatOptions {
+            thinking_budget: Some(budget),
+            ..Default::default()
+        }
+    }
+
+    fn sse(events: &[serde_json::Value]) -> String {
+        events.iter().map(|e| format!("data: {}\n\n", e)).collect()
+    }
+
+    async fn stream(provider: &dyn LlmProvider, messages: &[Message], options: ChatOptions) -> Vec<StreamEvent> {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        provider.chat_streaming(messages, options, tx).await.unwrap();
+        let mut events = Vec::new();
+        while let Some(event) = rx.recv().await {
+            events.push(event);
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_streams_thinking_apart_from_the_answer() {
+        let mock_server = MockServer::start().await;
+        let events = [
+            json!({ "type": "message_start", "message": { "usage": { "input_tokens": 10, "output_tokens": 1 } } }),
+            json!({ "type": "content_block_start", "index": 0, "content_block": { "type": "thinking", "thinking": "" } }),
+            json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "thinking_delta", "thinking": "Two plus two" } }),
+            json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "thinking_delta", "thinking": " is four." } }),
+            json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "signature_delta", "signature": "EqQB" } }),
+            json!({ "type": "content_block_start", "index": 1, "content_block": { "type": "text", "text": "" } }),
+            json!({ "type": "content_block_delta", "index": 1, "delta": { "type": "text_delta", "text": "4" } }),
+            json!({ "type": "message_delta", "usage": { "output_tokens": 30 } }),
+        ];
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .and(body_partial_json(json!({
+                "thinking": { "type": "enabled", "budget_tokens": 2048 },
+                "max_tokens": 4096 + 2048
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(sse(&events), "text/event-stream"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let provider = AnthropicProvider::new(
+            "test-key".to_string(),
+            mock_server.uri(),
+            "claude-sonnet-4-20250514".to_string(),
+        );
+        let events = stream(&provider, &[message("user", "What is 2 + 2?")], thinking(2048)).await;
+        assert_eq!(
+            events,
+            vec![
+                StreamEvent::Thinking("Two plus two".to_string()),
+                StreamEvent::Thinking(" is four.".to_string()),
+                StreamEvent::Text("4".to_string()),
+            ]
+        );
+
+        let requests = mock_server.received_requests().await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert!(body.get("temperature").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_tool_follow_up_goes_without_thinking() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "content": [{ "type": "text", "text": "It is sunny." }],
+                "usage": { "input_tokens": 30, "output_tokens": 5 }
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let messages = vec![
+            message("user", "Weather in Lyon?"),
+            Message {
+                tool_calls: Some(vec![ToolCall {
+                    id: Some("toolu_1".to_string()),
+                    r#type: Some("function".to_string()),
+                    function: FunctionCall {
+                        name: "internet_search".to_string(),
+                        arguments: "{\"query\":\"weather lyon\"}".to_string(),
+                    },
+                }]),
+                ..message("assistant", "")
+            },
+            Message {
+                tool_call_id: Some("toolu_1".to_string()),
+                ..message("tool", "Sunny, 24C")
+            },
+        ];
+        let provider = AnthropicProvider::new(
+            "test-key".to_string(),
+            mock_server.uri(),
+            "claude-sonnet-4-20250514".to_string(),
+        );
+        let response = provider.chat(&messages, thinking(2048)).await.unwrap();
+        assert_eq!(response.content, "It is sunny.");
+        assert_eq!(response.thinking, None);
+
+        let requests = mock_server.received_requests().await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert!(body.get("thinking").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_keeps_thinking_through_a_tool_loop() {
+        let mock_server = MockServer::start().await;
+        let tool_turn = [
+            json!({ "type": "content_block_start", "index": 0, "content_block": { "type": "thinking", "thinking": "" } }),
+            json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "thinking_delta", "thinking": "I should look it up." } }),
+            json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "signature_delta", "signature": "EqQB" } }),
+            json!({ "type": "content_block_start", "index": 1, "content_block": { "type": "tool_use", "id": "toolu_1", "name": "internet_search" } }),
+            json!({ "type": "content_block_delta", "index": 1, "delta": { "type": "input_json_delta", "partial_json": "{\"query\":\"weather lyon\"}" } }),
+        ];
+        let answer_turn = [
+            json!({ "type": "content_block_start", "index": 0, "content_block": { "type": "thinking", "thinking": "" } }),
+            json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "thinking_delta", "thinking": "It says sunny." } }),
+            json!({ "type": "content_block_start", "index": 1, "content_block": { "type": "text", "text": "" } }),
+            json!({ "type": "content_block_delta", "index": 1, "delta": { "type": "text_delta", "text": "It is sunny." } }),
+        ];
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(sse(&tool_turn), "text/event-stream"))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(sse(&answer_turn), "text/event-stream"))
+            .mount(&mock_server)
+            .await;
+
+        let provider = AnthropicProvider::new(
+            "test-key".to_string(),
+            mock_server.uri(),
+            "claude-sonnet-4-20250514".to_string(),
+        );
+        let mut messages = vec![message("user", "Weather in Lyon?")];
+        let (tx, _rx) = tokio::sync::mpsc::channel(100);
+        let first = provider.chat_streaming(&messages, thinking(2048), tx).await.unwrap();
+        let signed = first.thinking.clone().unwrap();
+        assert_eq!((signed.thinking.as_str(), signed.signature.as_str()), ("I should look it up.", "EqQB"));
+
+        messages.push(Message {
+            tool_calls: first.tool_calls,
+            thinking: first.thinking,
+            ..message("assistant", "")
+        });
+        messages.push(Message {
+            tool_call_id: Some("toolu_1".to_string()),
+            ..message("tool", "Sunny, 24C")
+        });
+        let events = stream(&provider, &messages, thinking(2048)).await;
+        assert_eq!(events.last(), Some(&StreamEvent::Text("It is sunny.".to_string())));
+
+        // The follow-up still thinks, and hands the signed block back ahead of the tool call
+        let requests = mock_server.received_requests().await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests[1].body).unwrap();
+        assert_eq!(body["thinking"]["type"], "enabled");
+        assert_eq!(
+            body["messages"][1]["content"][0],
+            json!({ "type": "thinking", "thinking": "I should look it up.", "signature": "EqQB" })
+        );
+        assert_eq!(body["messages"][1]["content"][1]["type"], "tool_use");
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_thinking_drops_top_k_and_rejects_low_top_p() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .and(body_partial_json(json!({ "top_p": 1.0 })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "content": [{ "type": "text", "text": "4" }],
+                "usage": { "input_tokens": 10, "output_tokens": 1 }
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let provider = AnthropicProvider::new(
+            "test-key".to_string(),
+            mock_server.uri(),
+            "claude-sonnet-4-20250514".to_string(),
+        );
+        let sampled = |top_p| ChatOptions {
+            sampling: SamplingParams {
+                top_p: Some(top_p),
+                top_k: Some(40),
+                ..Default::default()
+            },
+            ..thinking(2048)
+        };
+        provider.chat(&[message("user", "2 + 2?")], sampled(1.0)).await.unwrap();
+
+        let requests = mock_server.received_requests().await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert!(body.get("top_k").is_none());
+
+        let err = provider.chat(&[message("user", "2 + 2?")], sampled(0.5)).await.unwrap_err();
+        assert!(matches!(err, LlmError::Unsupported(_)));
+    }
+
+    #[tokio::test]
+    async fn test_openai_compatible_reasoning_content() {
+        let mock_server = MockServer::start().await;
+        let chunks = [
+            json!({ "choices": [{ "delta": { "role": "assistant", "reasoning_content": "Let me think." } }] }),
+            json!({ "choices": [{ "delta": { "content": "Done." } }] }),
+        ];
+        let body = format!("{}data: [DONE]\n\n", sse(&chunks));
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&mock_server)
+            .await;
+
+        let provider = OpenAiProvider::new("sk-test".to_string(), mock_server.uri(), "deepseek-reasoner".to_string());
+        let events = stream(&provider, &[message("user", "Hi")], ChatOptions::default()).await;
+        assert_eq!(
+            events,
+            vec![
+                StreamEvent::Thinking("Let me think.".to_string()),
+                StreamEvent::Text("Done.".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_openai_reasoning_models_get_completion_tokens_and_no_temperature() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(json!({
+                "reasoning_effort": "medium",
+                "max_completion_tokens": 4096 + 2048
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "choices": [{ "message": { "role": "assistant", "content": "4" } }]
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let provider = OpenAiProvider::new("sk-test".to_string(), mock_server.uri(), "o3-mini".to_string());
+        let response = provider.chat(&[message("user", "2 + 2?")], thinking(2048)).await.unwrap();
+        assert_eq!(response.content, "4");
+
+        let requests = mock_server.received_requests().await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert!(body.get("max_tokens").is_none());
+        assert!(body.get("temperature").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ollama_and_gemini_native_thinking() {
+        let ollama = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .and(body_partial_json(json!({ "think": true })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "message": { "role": "assistant", "content": "4", "thinking": "Simple sum." },
+                "done": true
+            })))
+            .expect(1)
+            .mount(&ollama)
+            .await;
+        let provider = OllamaProvider::new(ollama.uri(), "qwen3".to_string());
+        let response = provider.chat(&[message("user", "2 + 2?")], thinking(1024)).await.unwrap();
+        assert_eq!(response.content, "4");
+        assert_eq!(response.thinking.as_deref(), Some("Simple sum."));
+
+        let gemini = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/models/gemini-2.5-flash:generateContent"))
+            .and(body_partial_json(json!({
+                "generationConfig": { "thinkingConfig": { "thinkingBudget": 1024, "includeThoughts": true } }
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "candidates": [{ "content": { "role": "model", "parts": [
+                    { "text": "Adding the numbers.", "thought": true },
+                    { "text": "4" }
+                ] } }]
+            })))
+            .expect(1)
+            .mount(&gemini)
+            .await;
+        let provider = GeminiProvider::new("test-key".to_string(), gemini.uri(), "gemini-2.5-flash".to_string());
+        let response = provider.chat(&[message("user", "2 + 2?")], thinking(1024)).await.unwrap();
+        assert_eq!(response.content, "4");
+        assert_eq!(response.thinking.as_deref(), Some("Adding the numbers."));
+    }
+
+    #[tokio::test]
+    async fn test_thinking_support_is_looked_up_for_the_target_model() {
+        let ollama = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/show"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "capabilities": ["completion", "tools", "thinking"]
+            })))
+            .expect(1)
+            .mount(&ollama)
+            .await;
+
+        let mut providers: HashMap<String, Arc<dyn LlmProvider>> = HashMap::new();
+        providers.insert(
+            "anthropic".to_string(),
+            Arc::new(AnthropicProvider::new(
+                "test-key".to_string(),
+                ollama.uri(),
+                "claude-3-5-sonnet-20241022".to_string(),
+            )),
+        );
+        providers.insert(
+            "ollama".to_string(),
+            Arc::new(OllamaProvider::new(ollama.uri(), "qwen3".to_string())),
+        );
+        let manager = ProviderManager::new(providers, "anthropic".to_string());
+
+        let info = registry::describe_target(&manager, &ChatOptions::default()).await;
+        assert_eq!(info.id, "claude-3-5-sonnet-20241022");
+        assert_eq!(info.supports_thinking, Some(false));
+
+        // Asked once, then served from the registry
+        let on_ollama = ChatOptions {
+            provider: Some("ollama".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(registry::describe_target(&manager, &on_ollama).await.supports_thinking, Some(true));
+        assert_eq!(registry::describe_target(&manager, &on_ollama).await.supports_thinking, Some(true));
+    }
+}